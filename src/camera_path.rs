@@ -0,0 +1,311 @@
+use crate::prelude::*;
+use crate::gfx::camera::Camera;
+
+use std::collections::HashMap;
+use std::ops::{Add, Sub, Mul};
+
+/// Speed in units per second that cutscenes move along their rails
+pub const RAIL_SPEED: f32 = 4.0;
+
+/// Seconds taken to return the camera to the player after a cutscene
+pub const CUTSCENE_BLEND_OUT: f32 = 1.0;
+
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Easing {
+	Linear,
+	QuadIn,
+	QuadOut,
+	QuadInOut,
+}
+
+impl Easing {
+	/// Maps progress `t` in [0, 1] to eased progress in [0, 1]
+	pub fn apply(self, t: f32) -> f32 {
+		let t = t.clamp(0.0, 1.0);
+
+		match self {
+			Easing::Linear => t,
+			Easing::QuadIn => t * t,
+			Easing::QuadOut => t * (2.0 - t),
+			Easing::QuadInOut => if t < 0.5 {
+				2.0 * t * t
+			} else {
+				1.0 - (2.0 - 2.0 * t).powi(2) / 2.0
+			}
+		}
+	}
+}
+
+
+/// The parts of a `Camera` that move - everything else is left as it is when applied
+#[derive(Copy, Clone, Debug)]
+pub struct CameraPose {
+	pub position: Vec3,
+	pub yaw: f32,
+	pub pitch: f32,
+}
+
+impl CameraPose {
+	pub fn from_camera(camera: &Camera) -> CameraPose {
+		CameraPose {
+			position: camera.position(),
+			yaw: camera.yaw(),
+			pitch: camera.pitch(),
+		}
+	}
+
+	pub fn apply(&self, camera: &mut Camera) {
+		camera.set_position(self.position);
+		camera.set_yaw(self.yaw);
+		camera.set_pitch(self.pitch);
+	}
+
+	/// Interpolates towards `target`, turning the shortest way around
+	pub fn lerp(&self, target: &CameraPose, t: f32) -> CameraPose {
+		CameraPose {
+			position: lerp(self.position, target.position, t),
+			yaw: self.yaw + shortest_angle_delta(self.yaw, target.yaw) * t,
+			pitch: lerp(self.pitch, target.pitch, t),
+		}
+	}
+}
+
+
+/// Moves a camera from one pose to another over a fixed time
+#[derive(Clone, Debug)]
+pub struct CameraBlend {
+	from: CameraPose,
+	to: CameraPose,
+	duration: f32,
+	elapsed: f32,
+	easing: Easing,
+}
+
+impl CameraBlend {
+	pub fn new(from: &Camera, to: &Camera, duration: f32, easing: Easing) -> CameraBlend {
+		CameraBlend::between(CameraPose::from_camera(from), CameraPose::from_camera(to), duration, easing)
+	}
+
+	pub fn between(from: CameraPose, to: CameraPose, duration: f32, easing: Easing) -> CameraBlend {
+		CameraBlend {
+			from,
+			to,
+			duration,
+			elapsed: 0.0,
+			easing,
+		}
+	}
+
+	pub fn is_finished(&self) -> bool { self.elapsed >= self.duration }
+
+	/// Advances the blend by `dt` seconds and returns the new pose
+	pub fn update(&mut self, dt: f32) -> CameraPose {
+		self.elapsed = (self.elapsed + dt).min(self.duration);
+		self.pose()
+	}
+
+	pub fn pose(&self) -> CameraPose {
+		if self.duration <= 0.0 {
+			return self.to
+		}
+
+		self.from.lerp(&self.to, self.easing.apply(self.elapsed / self.duration))
+	}
+}
+
+
+/// A Catmull-Rom spline through camera poses, built from `CAM_<rail>_<index>` entities.
+/// Keys are visited in index order, at roughly constant speed
+#[derive(Clone, Debug)]
+pub struct CameraRail {
+	keys: Vec<CameraPose>,
+
+	/// Distance along the rail to each key, measured along straight lines between keys
+	key_distances: Vec<f32>,
+}
+
+impl CameraRail {
+	/// Builds a rail from each group of `CAM_` entities in `scene`, keyed by rail name
+	pub fn load_all(scene: toy::SceneRef<'_>) -> HashMap<String, CameraRail> {
+		let mut keys_by_rail: HashMap<String, Vec<(u32, CameraPose)>> = HashMap::new();
+
+		for entity in scene.entities() {
+			if !entity.name.starts_with("CAM_") { continue }
+
+			let (rail, index) = match entity.name[4..].rsplit_once('_')
+				.and_then(|(rail, index)| index.parse::<u32>().ok().map(|index| (rail, index)))
+			{
+				Some(parsed) => parsed,
+				None => {
+					eprintln!("Camera rail key '{}' should be named CAM_<rail>_<index>", entity.name);
+					continue
+				}
+			};
+
+			let forward = entity.rotation.forward();
+
+			let pose = CameraPose {
+				position: entity.position,
+				yaw: entity.rotation.yaw(),
+				pitch: forward.y.clamp(-1.0, 1.0).asin(),
+			};
+
+			keys_by_rail.entry(rail.to_owned()).or_default().push((index, pose));
+		}
+
+		keys_by_rail.into_iter()
+			.filter_map(|(name, mut keys)| {
+				keys.sort_by_key(|&(index, _)| index);
+
+				let keys = keys.into_iter().map(|(_, pose)| pose).collect(): Vec<_>;
+				match CameraRail::new(keys) {
+					Some(rail) => Some((name, rail)),
+					None => {
+						eprintln!("Camera rail '{}' needs at least two keys", name);
+						None
+					}
+				}
+			})
+			.collect()
+	}
+
+	/// Returns None if there are fewer than two keys
+	pub fn new(mut keys: Vec<CameraPose>) -> Option<CameraRail> {
+		if keys.len() < 2 {
+			return None
+		}
+
+		// Make yaws continuous so that interpolation turns the shortest way between keys
+		for idx in 1..keys.len() {
+			let prev_yaw = keys[idx-1].yaw;
+			keys[idx].yaw = prev_yaw + shortest_angle_delta(prev_yaw, keys[idx].yaw);
+		}
+
+		let mut key_distances = vec![0.0];
+		for pair in keys.windows(2) {
+			let distance = key_distances.last().unwrap() + (pair[1].position - pair[0].position).length();
+			key_distances.push(distance);
+		}
+
+		Some(CameraRail { keys, key_distances })
+	}
+
+	pub fn length(&self) -> f32 {
+		*self.key_distances.last().unwrap()
+	}
+
+	/// The pose at `progress` in [0, 1] along the rail
+	pub fn sample(&self, progress: f32) -> CameraPose {
+		let distance = progress.clamp(0.0, 1.0) * self.length();
+
+		let segment = self.key_distances.iter()
+			.skip(1)
+			.position(|&key_distance| key_distance >= distance)
+			.unwrap_or(self.keys.len() - 2);
+
+		let segment_length = self.key_distances[segment+1] - self.key_distances[segment];
+		let t = if segment_length > 0.0 {
+			(distance - self.key_distances[segment]) / segment_length
+		} else {
+			0.0
+		};
+
+		// Repeat the end keys so that the spline reaches them
+		let key = |idx: isize| self.keys[idx.clamp(0, self.keys.len() as isize - 1) as usize];
+		let segment = segment as isize;
+		let (p0, p1, p2, p3) = (key(segment-1), key(segment), key(segment+1), key(segment+2));
+
+		CameraPose {
+			position: catmull_rom(p0.position, p1.position, p2.position, p3.position, t),
+			yaw: catmull_rom(p0.yaw, p1.yaw, p2.yaw, p3.yaw, t),
+			pitch: catmull_rom(p0.pitch, p1.pitch, p2.pitch, p3.pitch, t),
+		}
+	}
+}
+
+
+/// Takes control of a camera to fly it along a rail, then blends it back to where it started
+pub struct Cutscene {
+	rail: CameraRail,
+	duration: f32,
+	elapsed: f32,
+
+	return_pose: CameraPose,
+	blend_out: Option<CameraBlend>,
+}
+
+impl Cutscene {
+	/// Starts from wherever `camera` is, so that it can be returned there afterwards
+	pub fn new(rail: CameraRail, camera: &Camera) -> Cutscene {
+		Cutscene {
+			duration: rail.length() / RAIL_SPEED,
+			rail,
+			elapsed: 0.0,
+
+			return_pose: CameraPose::from_camera(camera),
+			blend_out: None,
+		}
+	}
+
+	pub fn is_finished(&self) -> bool {
+		self.blend_out.as_ref().map_or(false, CameraBlend::is_finished)
+	}
+
+	/// Jumps to blending back to the starting pose
+	pub fn skip(&mut self, camera: &Camera) {
+		if self.blend_out.is_none() {
+			self.elapsed = self.duration;
+			self.start_blend_out(CameraPose::from_camera(camera));
+		}
+	}
+
+	pub fn update(&mut self, camera: &mut Camera, dt: f32) {
+		if let Some(blend) = &mut self.blend_out {
+			blend.update(dt).apply(camera);
+			return
+		}
+
+		self.elapsed = (self.elapsed + dt).min(self.duration);
+
+		let progress = if self.duration > 0.0 { self.elapsed / self.duration } else { 1.0 };
+		let pose = self.rail.sample(Easing::QuadInOut.apply(progress));
+		pose.apply(camera);
+
+		if self.elapsed >= self.duration {
+			self.start_blend_out(pose);
+		}
+	}
+
+	fn start_blend_out(&mut self, from: CameraPose) {
+		self.blend_out = Some(CameraBlend::between(from, self.return_pose, CUTSCENE_BLEND_OUT, Easing::QuadInOut));
+	}
+}
+
+
+/// The signed angle to turn from `from` to reach `to` the short way around, in [-PI, PI]
+pub fn shortest_angle_delta(from: f32, to: f32) -> f32 {
+	let delta = (to - from).rem_euclid(2.0 * PI);
+	if delta > PI { delta - 2.0 * PI } else { delta }
+}
+
+
+fn lerp<T>(a: T, b: T, t: f32) -> T
+	where T: Copy + Add<Output=T> + Sub<Output=T> + Mul<f32, Output=T>
+{
+	a + (b - a) * t
+}
+
+
+/// Uniform Catmull-Rom between `p1` and `p2`
+fn catmull_rom<T>(p0: T, p1: T, p2: T, p3: T, t: f32) -> T
+	where T: Copy + Add<Output=T> + Sub<Output=T> + Mul<f32, Output=T>
+{
+	let t2 = t * t;
+	let t3 = t2 * t;
+
+	(p1 * 2.0
+		+ (p2 - p0) * t
+		+ (p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * t2
+		+ (p1 * 3.0 - p0 - p2 * 3.0 + p3) * t3) * 0.5
+}
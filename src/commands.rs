@@ -0,0 +1,145 @@
+use crate::prelude::*;
+use crate::nav;
+
+/// Offline tools, run as `skelet <command> [args...]` instead of starting the game
+pub fn run(command: &str, args: &[String]) -> Result<(), Box<dyn Error>> {
+	match command {
+		"bake-nav" => bake_nav(args),
+		"export-nav" => export_nav(args),
+		_ => Err(format!("Unknown command '{}'", command).into()),
+	}
+}
+
+
+/// bake-nav [project.toy] [output.nav] [--cell-size <size>] [--agent-height <height>]
+/// [--agent-radius <radius>] [--agent-step <step>] [--max-slope <degrees>]
+fn bake_nav(args: &[String]) -> Result<(), Box<dyn Error>> {
+	let (paths, params) = parse_bake_args(args)?;
+
+	let project_path = paths.first().cloned().unwrap_or(crate::PROJECT_PATH);
+	let output_path = paths.get(1).cloned().unwrap_or(crate::BAKED_NAV_PATH);
+
+	let project_data = std::fs::read(project_path)?;
+	let project = toy::load(&project_data)?;
+	let scene = project.find_scene("main")
+		.ok_or("Couldn't find scene 'main'")?;
+
+	let (nav_mesh, source_hash) = build_nav_mesh(scene, &params);
+	nav_mesh.save(output_path, source_hash, &params)?;
+
+	println!("Baked nav mesh ({} faces, {} edges) to {}",
+		nav_mesh.faces.len(), nav_mesh.edges.len(), output_path);
+
+	Ok(())
+}
+
+
+/// Splits positional args from bake param overrides
+fn parse_bake_args(args: &[String]) -> Result<(Vec<&str>, nav::bake::BakeParams), Box<dyn Error>> {
+	let mut params = nav::bake::BakeParams::default();
+	let mut paths = Vec::new();
+
+	let mut args = args.iter().map(String::as_str);
+
+	while let Some(arg) = args.next() {
+		if !arg.starts_with("--") {
+			paths.push(arg);
+			continue
+		}
+
+		let value: f32 = args.next()
+			.ok_or_else(|| format!("{} requires a value", arg))?
+			.parse()?;
+
+		if value < 0.0 {
+			return Err(format!("{} can't be negative", arg).into())
+		}
+
+		match arg {
+			"--cell-size" => params.cell_size = value,
+			"--agent-height" => params.agent_height = value,
+			"--agent-radius" => params.agent_radius = value,
+			"--agent-step" => params.agent_step = value,
+			"--max-slope" => params.max_slope = value.to_radians(),
+			_ => return Err(format!("Unknown option '{}'", arg).into()),
+		}
+	}
+
+	if params.cell_size <= 0.0 {
+		return Err("--cell-size must be greater than zero".into())
+	}
+
+	Ok((paths, params))
+}
+
+
+/// export-nav <output.obj|output.svg> [project.toy]
+fn export_nav(args: &[String]) -> Result<(), Box<dyn Error>> {
+	let output_path = args.get(0).ok_or("export-nav requires an output path")?;
+	let project_path = args.get(1).map_or(crate::PROJECT_PATH, String::as_str);
+
+	let project_data = std::fs::read(project_path)?;
+	let project = toy::load(&project_data)?;
+	let scene = project.find_scene("main")
+		.ok_or("Couldn't find scene 'main'")?;
+
+	let (nav_mesh, _) = build_nav_mesh(scene, &nav::bake::BakeParams::default());
+
+	let mut file = std::io::BufWriter::new(std::fs::File::create(output_path)?);
+
+	if output_path.ends_with(".obj") {
+		nav_mesh.write_obj(&mut file)?;
+	} else if output_path.ends_with(".svg") {
+		nav_mesh.write_svg(&mut file)?;
+	} else {
+		return Err("export-nav output must be either .obj or .svg".into())
+	}
+
+	println!("Exported nav mesh to {}", output_path);
+
+	Ok(())
+}
+
+
+/// Builds the nav mesh for `scene` from whichever `nav_source` it has, baking with `params` if
+/// it has no NAV_ entity. Also returns the source hash to save alongside it
+pub fn build_nav_mesh(scene: toy::SceneRef<'_>, params: &nav::bake::BakeParams) -> (nav::NavMesh, u64) {
+	let source = nav_source(scene);
+	(source.build(params), source.hash(params))
+}
+
+
+/// Where the nav mesh for a scene comes from
+#[derive(Copy, Clone)]
+pub enum NavSource<'t> {
+	/// A hand authored NAV_ entity
+	Authored(toy::EntityRef<'t>),
+
+	/// Baked from the scene's static geometry
+	Baked(toy::SceneRef<'t>),
+}
+
+/// Prefers the scene's NAV_ entity, falling back to baking when there isn't one
+pub fn nav_source(scene: toy::SceneRef<'_>) -> NavSource<'_> {
+	match scene.entities().find(|e| e.name.starts_with("NAV_")) {
+		Some(nav_ent) => NavSource::Authored(nav_ent),
+		None => NavSource::Baked(scene),
+	}
+}
+
+impl NavSource<'_> {
+	/// Identifies the nav mesh that `build` would produce. `params` only affect baked sources
+	pub fn hash(&self, params: &nav::bake::BakeParams) -> u64 {
+		match *self {
+			NavSource::Authored(nav_ent) => nav::serialize::source_hash(std::iter::once(nav_ent), None),
+			NavSource::Baked(scene) => nav::serialize::source_hash(crate::static_scene_entities(scene), Some(params)),
+		}
+	}
+
+	pub fn build(&self, params: &nav::bake::BakeParams) -> nav::NavMesh {
+		match *self {
+			NavSource::Authored(nav_ent) => nav::NavMesh::from_entity(nav_ent),
+			NavSource::Baked(scene) => nav::bake::bake_scene(scene, params),
+		}
+	}
+}
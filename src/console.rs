@@ -0,0 +1,41 @@
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+
+/// Reads commands typed into the terminal the game was launched from, without blocking the main loop
+pub struct Console {
+	lines: Receiver<String>,
+}
+
+impl Console {
+	pub fn new() -> Console {
+		let (sender, lines) = mpsc::channel();
+
+		std::thread::spawn(move || {
+			use std::io::BufRead;
+
+			let stdin = std::io::stdin();
+			for line in stdin.lock().lines() {
+				let line = match line {
+					Ok(line) => line,
+					Err(_) => break,
+				};
+
+				if sender.send(line).is_err() {
+					break
+				}
+			}
+		});
+
+		Console { lines }
+	}
+
+	/// Returns the next command entered since the last call, if any
+	pub fn poll(&mut self) -> Option<String> {
+		loop {
+			match self.lines.try_recv() {
+				Ok(line) if line.trim().is_empty() => continue,
+				Ok(line) => return Some(line.trim().to_owned()),
+				Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => return None,
+			}
+		}
+	}
+}
@@ -0,0 +1,316 @@
+use crate::prelude::*;
+use crate::gfx::debug::Debug;
+use crate::gfx::camera::Camera;
+use crate::nav::{self, NavMesh, NavFaceID};
+use crate::player_controller::{PlayerController, PLAYER_HEIGHT_VEC};
+use crate::util::{self, Aabb, Ray};
+use crate::scene_mesh::SceneMesh;
+
+use glutin::event::VirtualKeyCode;
+use std::collections::HashSet;
+
+/// Where the set of enabled overlays is remembered between runs
+const SETTINGS_PATH: &str = "debug_overlay.cfg";
+
+
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub enum OverlayItem {
+	NavMesh,
+	NavIntersect,
+	Teleporters,
+	CurrentFace,
+	SlideVectors,
+	Picking,
+}
+
+impl OverlayItem {
+	pub const ALL: [OverlayItem; 6] = [
+		OverlayItem::NavMesh,
+		OverlayItem::NavIntersect,
+		OverlayItem::Teleporters,
+		OverlayItem::CurrentFace,
+		OverlayItem::SlideVectors,
+		OverlayItem::Picking,
+	];
+
+	pub fn name(self) -> &'static str {
+		match self {
+			OverlayItem::NavMesh => "nav_mesh",
+			OverlayItem::NavIntersect => "nav_intersect",
+			OverlayItem::Teleporters => "teleporters",
+			OverlayItem::CurrentFace => "current_face",
+			OverlayItem::SlideVectors => "slide_vectors",
+			OverlayItem::Picking => "picking",
+		}
+	}
+
+	pub fn from_name(name: &str) -> Option<OverlayItem> {
+		OverlayItem::ALL.iter().cloned().find(|item| item.name() == name)
+	}
+
+	pub fn toggle_key(self) -> VirtualKeyCode {
+		match self {
+			OverlayItem::NavMesh => VirtualKeyCode::F3,
+			OverlayItem::NavIntersect => VirtualKeyCode::F4,
+			OverlayItem::Teleporters => VirtualKeyCode::F5,
+			OverlayItem::CurrentFace => VirtualKeyCode::F6,
+			OverlayItem::SlideVectors => VirtualKeyCode::F7,
+			OverlayItem::Picking => VirtualKeyCode::F10,
+		}
+	}
+}
+
+
+pub struct DebugOverlay {
+	enabled: HashSet<OverlayItem>,
+}
+
+impl DebugOverlay {
+	/// Restores whichever overlays were enabled last run
+	pub fn load() -> DebugOverlay {
+		let enabled = std::fs::read_to_string(SETTINGS_PATH)
+			.map(|settings| {
+				settings.lines()
+					.map(str::trim)
+					.filter_map(OverlayItem::from_name)
+					.collect()
+			})
+			.unwrap_or_default();
+
+		DebugOverlay { enabled }
+	}
+
+	fn save(&self) {
+		let settings = OverlayItem::ALL.iter()
+			.filter(|&&item| self.is_enabled(item))
+			.map(|item| format!("{}\n", item.name()))
+			.collect(): String;
+
+		if let Err(err) = std::fs::write(SETTINGS_PATH, settings) {
+			eprintln!("Failed to save debug overlay settings to '{}': {}", SETTINGS_PATH, err);
+		}
+	}
+
+	pub fn is_enabled(&self, item: OverlayItem) -> bool {
+		self.enabled.contains(&item)
+	}
+
+	pub fn set_enabled(&mut self, item: OverlayItem, enabled: bool) {
+		let changed = if enabled {
+			self.enabled.insert(item)
+		} else {
+			self.enabled.remove(&item)
+		};
+
+		if changed {
+			println!("debug overlay '{}' {}", item.name(), if enabled { "on" } else { "off" });
+			self.save();
+		}
+	}
+
+	pub fn toggle(&mut self, item: OverlayItem) {
+		self.set_enabled(item, !self.is_enabled(item));
+	}
+
+	/// Toggles the overlay bound to `key`, returning whether `key` was bound to anything
+	pub fn handle_key(&mut self, key: VirtualKeyCode) -> bool {
+		match OverlayItem::ALL.iter().find(|item| item.toggle_key() == key) {
+			Some(&item) => {
+				self.toggle(item);
+				true
+			}
+
+			None => false,
+		}
+	}
+
+	/// Handles console commands of the form `overlay <name> [on|off]` or `overlay list`
+	pub fn run_command(&mut self, command: &str) -> Result<(), String> {
+		let mut args = command.split_whitespace();
+
+		if args.next() != Some("overlay") {
+			return Err(format!("Unknown command '{}'", command))
+		}
+
+		match (args.next(), args.next()) {
+			(Some("list"), None) | (None, None) => {
+				for &item in OverlayItem::ALL.iter() {
+					let state = if self.is_enabled(item) { "on" } else { "off" };
+					println!("{:<16} {:<4} [{:?}]", item.name(), state, item.toggle_key());
+				}
+			}
+
+			(Some(name), state) => {
+				let item = OverlayItem::from_name(name)
+					.ok_or_else(|| format!("Unknown overlay '{}'", name))?;
+
+				match state {
+					None => self.toggle(item),
+					Some("on") => self.set_enabled(item, true),
+					Some("off") => self.set_enabled(item, false),
+					Some(state) => return Err(format!("Expected 'on' or 'off', got '{}'", state)),
+				}
+			}
+
+			_ => return Err("Usage: overlay [list | <name> [on|off]]".into()),
+		}
+
+		Ok(())
+	}
+
+
+	/// `pick_ray` is whatever the player is pointing at - through the cursor, or the middle of the screen
+	pub fn draw(&self, debug: &mut Debug, nav: &NavMesh, scene_mesh: &SceneMesh, camera: &Camera, player: &PlayerController,
+		pick_ray: &Ray, teleporter_links: impl Iterator<Item=(Vec3, Vec3)>)
+	{
+		if self.is_enabled(OverlayItem::NavMesh) {
+			draw_nav_mesh(debug, nav);
+		}
+
+		if self.is_enabled(OverlayItem::NavIntersect) {
+			draw_nav_intersect(debug, nav, camera, player.nav_face());
+		}
+
+		if self.is_enabled(OverlayItem::Teleporters) {
+			for (pos, target_pos) in teleporter_links {
+				debug.point(pos, Color::rgb(1.0, 0.0, 1.0));
+				debug.line(pos, target_pos, Color::rgb(0.7, 0.0, 0.7));
+			}
+		}
+
+		if self.is_enabled(OverlayItem::CurrentFace) {
+			if let Some(face_idx) = player.nav_face() {
+				draw_nav_face(debug, nav, face_idx);
+			}
+		}
+
+		if self.is_enabled(OverlayItem::SlideVectors) {
+			if let Some(slide) = player.last_slide() {
+				// Scale up so per-frame movement is actually visible
+				let scale = 20.0;
+				let feet_pos = camera.position() - PLAYER_HEIGHT_VEC + Vec3::from_y(0.05);
+
+				debug.line(feet_pos, feet_pos + slide.desired_delta.to_x0z() * scale, Color::rgb(1.0, 0.6, 0.2));
+				debug.line(feet_pos, feet_pos + slide.resolved_delta.to_x0z() * scale, Color::rgb(0.2, 0.8, 1.0));
+			}
+		}
+
+		if self.is_enabled(OverlayItem::Picking) {
+			draw_picking(debug, nav, scene_mesh, pick_ray);
+		}
+	}
+}
+
+
+
+fn draw_nav_mesh(debug: &mut Debug, nav: &NavMesh) {
+	for v in nav.vertices.iter() {
+		debug.point(v.position, Color::rgb(1.0, 0.0, 1.0));
+	}
+
+	for &nav::NavFace{start_edge, center, ..} in nav.faces.iter() {
+		debug.point(center, Color::rgb(1.0, 1.0, 0.5));
+
+		for (edge_idx, edge) in nav.iter_edge_loop(start_edge) {
+			let (pos_a, pos_b) = nav.edge_vertex_positions(edge_idx);
+
+			let pos_a = (0.1).ease_linear(pos_a, center);
+			let pos_b = (0.1).ease_linear(pos_b, center);
+
+			let edge_dir = (pos_b - pos_a).normalize();
+
+			let edge_col = if edge.twin.is_some() {
+				Color::rgba(0.2, 0.6, 0.3, 0.5)
+			} else {
+				Color::rgba(0.5, 0.2, 0.2, 0.5)
+			};
+
+			debug.line(pos_a, pos_b, edge_col);
+			debug.line(pos_b, pos_b - edge_dir * 0.1 + Vec3::from_y(0.1), edge_col);
+			debug.line(pos_b, pos_b - edge_dir * 0.1 - Vec3::from_y(0.1), edge_col);
+		}
+	}
+}
+
+
+fn draw_nav_intersect(debug: &mut Debug, nav: &NavMesh, camera: &Camera, player_nav_face: Option<NavFaceID>) {
+	let cam_pos = camera.position();
+	let cam_down = Vec3::from_y(-1.0);
+
+	if let Some(face_idx) = player_nav_face {
+		let face = &nav.faces[face_idx];
+
+		let intersect = match util::intersect_plane(face.plane, cam_pos, cam_down) {
+			Some(intersect) => intersect,
+			None => return
+		};
+
+		debug.line(intersect, face.center, Color::rgb(0.6, 1.0, 0.4));
+
+		for (edge_idx, edge) in nav.iter_edge_loop(face.start_edge) {
+			if edge.twin.is_none() {
+				let (va, vb) = nav.edge_vertex_positions(edge_idx);
+				debug.line(va + Vec3::from_y(0.1), vb + Vec3::from_y(0.1), Color::rgb(0.0, 1.0, 0.7));
+			}
+
+			let (incoming_barrier_idx, outgoing_barrier_idx) = match nav.vertex_barriers(edge.vertex) {
+				Some(barriers) => barriers,
+				None => continue,
+			};
+
+			if nav.is_concave_barrier_vertex(edge.vertex) {
+				let (va, vb) = nav.edge_vertex_positions(incoming_barrier_idx);
+				debug.line(va, vb, Color::rgb(1.0, 0.3, 0.5));
+
+				let (va, vb) = nav.edge_vertex_positions(outgoing_barrier_idx);
+				debug.line(va, vb, Color::rgb(1.0, 0.3, 0.5));
+			}
+		}
+	}
+}
+
+
+fn draw_nav_face(debug: &mut Debug, nav: &NavMesh, face_idx: NavFaceID) {
+	let face = &nav.faces[face_idx];
+	let raise = Vec3::from_y(0.05);
+	let color = Color::rgb(1.0, 0.9, 0.2);
+
+	debug.point(face.center + raise, color);
+
+	for (edge_idx, _) in nav.iter_edge_loop(face.start_edge) {
+		let (va, vb) = nav.edge_vertex_positions(edge_idx);
+		debug.line(va + raise, vb + raise, color);
+		debug.line(vb + raise, face.center + raise, Color::rgba(1.0, 0.9, 0.2, 0.3));
+	}
+}
+
+
+fn draw_picking(debug: &mut Debug, nav: &NavMesh, scene_mesh: &SceneMesh, ray: &Ray) {
+	if let Some((face_idx, distance)) = nav.raycast(ray) {
+		draw_nav_face(debug, nav, face_idx);
+		debug.point(ray.at(distance), Color::rgb(1.0, 0.9, 0.2));
+	}
+
+	if let Some((entity, distance)) = scene_mesh.raycast(ray) {
+		draw_aabb(debug, &entity.bounds, Color::rgb(0.3, 0.9, 1.0));
+		debug.point(ray.at(distance), Color::rgb(0.3, 0.9, 1.0));
+	}
+}
+
+
+fn draw_aabb(debug: &mut Debug, aabb: &Aabb, color: Color) {
+	let corner = |i: usize| Vec3::new(
+		if i & 1 == 0 { aabb.min.x } else { aabb.max.x },
+		if i & 2 == 0 { aabb.min.y } else { aabb.max.y },
+		if i & 4 == 0 { aabb.min.z } else { aabb.max.z },
+	);
+
+	// Connect each corner to the corners that differ from it along one axis
+	for i in 0..8 {
+		for &axis in [1, 2, 4].iter() {
+			if i & axis == 0 {
+				debug.line(corner(i), corner(i | axis), color);
+			}
+		}
+	}
+}
@@ -1,17 +1,31 @@
 pub mod core;
+pub mod resource;
+pub mod state;
 pub mod mesh;
 pub mod debug;
 pub mod shader;
 pub mod vertex;
 pub mod texture_buffer;
+pub mod instance_buffer;
+pub mod uniform_buffer;
+pub mod framebuffer;
+pub mod texture;
+pub mod image;
+pub mod screenshot;
+pub mod profiler;
 pub mod mesh_builder;
+pub mod render_queue;
 pub mod camera;
 pub mod animation;
 
+#[cfg(test)]
+pub(crate) mod test_context;
+
 pub struct Gfx {
 	pub core: core::Core,
 	pub debug: debug::Debug,
 	pub anim: animation::AnimationManager,
+	pub queue: render_queue::RenderQueue,
 }
 
 
@@ -22,10 +36,6 @@ impl Gfx {
 		core.set_blend_mode(core::BlendMode::Alpha);
 
 		unsafe {
-			let mut vao = 0;
-			gl::GenVertexArrays(1, &mut vao);
-			gl::BindVertexArray(vao);
-			
 			gl::PointSize(3.0);
 			gl::LineWidth(3.0);
 
@@ -58,6 +68,7 @@ impl Gfx {
 			core,
 			debug,
 			anim,
+			queue: render_queue::RenderQueue::new(),
 		}
 	}
 }
@@ -105,4 +116,45 @@ extern "system" fn gl_message_callback(source: u32, ty: u32, _id: u32, severity:
 	}
 
 	panic!("GL ERROR!");
-}
\ No newline at end of file
+}
+
+
+#[cfg(test)]
+mod tests {
+	use crate::prelude::*;
+	use super::test_context::HeadlessGfx;
+	use super::framebuffer::FramebufferSettings;
+	use super::image::Image;
+
+	const GOLDEN_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/golden");
+
+	#[test]
+	fn headless_render_matches_golden() {
+		let mut headless = match HeadlessGfx::new("headless_render_matches_golden") {
+			Some(headless) => headless,
+			None => return,
+		};
+
+		let gfx = &mut headless.gfx;
+
+		let framebuffer = gfx.core.new_framebuffer(FramebufferSettings::new(Vec2i::splat(64)).add_color());
+		gfx.core.bind_framebuffer(framebuffer);
+
+		gfx.core.set_bg_color(Color::rgb(1.0, 0.0, 0.0));
+		gfx.core.clear();
+
+		gfx.debug.screen_rect(Vec2::new(-1.0, -1.0), Vec2::new(0.0, 1.0), Color::rgb(0.0, 1.0, 0.0));
+		gfx.debug.submit(&mut gfx.core, &mut gfx.queue);
+		gfx.queue.execute(&mut gfx.core);
+
+		let rendered = gfx.core.read_pixels();
+		let golden = Image::load_png(format!("{}/split_screen.png", GOLDEN_DIR)).unwrap();
+		let diff = rendered.diff(&golden, 2).unwrap();
+
+		if !diff.is_match() {
+			let _ = rendered.save_png(std::env::temp_dir().join("split_screen.actual.png"));
+		}
+
+		assert!(diff.is_match(), "Rendered image differs from golden: {:?}", diff);
+	}
+}
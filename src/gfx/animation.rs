@@ -2,11 +2,11 @@ use crate::prelude::*;
 use crate::gfx;
 
 use super::core::{self, Core};
-use super::camera::Camera;
 use super::shader::ShaderID;
 use super::mesh::{MeshID, BasicMeshID};
 use super::texture_buffer::TextureBufferID;
-use super::vertex;
+use super::instance_buffer::{InstanceBufferID, TransformInstance, TRANSFORM_INSTANCE_ATTRIBUTES};
+use super::vertex::{self, Vertex};
 
 use std::collections::HashMap;
 
@@ -30,6 +30,9 @@ struct AnimatedMeshData {
 	bones: Vec<(Vec3, Vec3)>,
 
 	bone_mesh: BasicMeshID<WeightedVertex>,
+
+	/// Shared by the mesh and bone mesh, refilled every frame
+	instance_buffer: InstanceBufferID<AnimatedInstance>,
 }
 
 pub struct Animation {
@@ -58,11 +61,17 @@ impl AnimationManager {
 	pub fn new(core: &mut Core) -> AnimationManager {
 		let bone_buffer = core.new_texture_buffer();
 
-		let shader = core.new_shader(
-			include_str!("../shaders/weighted_vert.glsl"),
-			include_str!("../shaders/color_frag.glsl"),
-			&["a_vertex", "a_color", "a_bone_indices", "a_bone_weights"]
-		);
+		let attribs = ["a_vertex", "a_color", "a_bone_indices", "a_bone_weights"].iter()
+			.chain(&TRANSFORM_INSTANCE_ATTRIBUTES)
+			.chain(&["a_bone_offset"])
+			.cloned()
+			.collect(): Vec<_>;
+
+		let shader = core.new_instanced_shader::<WeightedVertex, AnimatedInstance>(
+			crate::shader_source!("shaders/weighted_vert.glsl"),
+			crate::shader_source!("shaders/color_frag.glsl"),
+			&attribs
+		).expect("Failed to create animation shader");
 
 		AnimationManager {
 			bone_buffer,
@@ -87,9 +96,9 @@ impl AnimationManager {
 				let toy::MeshWeightVertex{indices, weights} = *vertex_weight;
 
 				let indices = [
-					indices[0] as f32,
-					indices[1] as f32,
-					indices[2] as f32,
+					indices[0] as u16,
+					indices[1] as u16,
+					indices[2] as u16,
 				];
 
 				WeightedVertex::new(pos, color.into(), indices, weights)
@@ -141,11 +150,11 @@ impl AnimationManager {
 		{
 			let bone_color = Color::rgb(0.5, 0.1, 0.5);
 
-			let to_vert = |b, idx| WeightedVertex::new(b, bone_color, [0.0, idx, 0.0], [0.0, 1.0, 0.0]);
+			let to_vert = |b, idx| WeightedVertex::new(b, bone_color, [0, idx, 0], [0.0, 1.0, 0.0]);
 			let verts = animation_data.bones.iter().enumerate()
 				.flat_map(move |(idx, b)| {
-					let v0 = std::iter::once(to_vert(b.head, idx as f32));
-					let v1 = std::iter::once(to_vert(b.tail, idx as f32));
+					let v0 = std::iter::once(to_vert(b.head, idx as u16));
+					let v1 = std::iter::once(to_vert(b.tail, idx as u16));
 					v0.chain(v1)
 				})
 				.collect(): Vec<_>;
@@ -157,7 +166,9 @@ impl AnimationManager {
 			.map(|bone| (bone.head, bone.tail))
 			.collect();
 
-		self.mesh_animations.insert(mesh, AnimatedMeshData{animations, bones, bone_mesh});
+		let instance_buffer = core.new_instance_buffer();
+
+		self.mesh_animations.insert(mesh, AnimatedMeshData{animations, bones, bone_mesh, instance_buffer});
 
 		mesh
 	}
@@ -191,13 +202,12 @@ impl AnimationManager {
 		self.instances.clear();
 	}
 
-	pub fn draw(&self, core: &mut Core, camera: &Camera) {
+	/// Draws all instances, with one draw call per animated mesh
+	pub fn draw(&self, core: &mut Core) {
 		let mut bone_frames = Vec::new();
-		let mut bone_offsets = Vec::new();
-
-		for AnimatedMeshInstance{animation, animation_time, ..} in self.instances.iter() {
-			bone_offsets.push(bone_frames.len());
+		let mut mesh_instances: HashMap<AnimatedMeshID, Vec<AnimatedInstance>> = HashMap::new();
 
+		for AnimatedMeshInstance{transform, animation, animation_time} in self.instances.iter() {
 			let AnimationID(mesh, idx) = animation;
 			let animated_mesh_data = self.mesh_animations.get(mesh)
 				.expect("trying to get animation data for unregistered mesh");
@@ -209,35 +219,37 @@ impl AnimationManager {
 			let frame_data_start = animation.bone_count * frame_number;
 			let frame_data_end = frame_data_start + animation.bone_count;
 
+			mesh_instances.entry(*mesh).or_default().push(AnimatedInstance {
+				transform: TransformInstance::new(transform, Color::rgb(1.0, 1.0, 1.0)),
+				bone_offset: bone_frames.len() as _,
+			});
+
 			let frame_data = &animation.frame_data[frame_data_start..frame_data_end];
 			bone_frames.extend_from_slice(frame_data);
 		}
 
+		if mesh_instances.is_empty() {
+			return
+		}
+
 		core.update_texture_buffer(self.bone_buffer, &bone_frames);
 
 		core.use_shader(self.shader);
-		core.set_uniform_mat4("u_proj_view", &camera.projection_view());
 		core.set_uniform_texture_buffer("u_bone_tex", self.bone_buffer, 0);
 
-		for (inst, bone_offset) in self.instances.iter().zip(&bone_offsets) {
-			let AnimationID(mesh, _) = inst.animation;
+		for (&mesh, instances) in mesh_instances.iter() {
+			let instance_buffer = self.mesh_animations[&mesh].instance_buffer;
 
-			core.set_uniform_i32("u_bone_offset", *bone_offset as _);
-			core.set_uniform_mat4("u_object", &inst.transform);
-			core.draw_mesh(mesh);
+			core.update_instance_buffer(instance_buffer, instances);
+			core.draw_mesh_instanced(mesh, instance_buffer);
 		}
 
 		// debug bone viz
 		if self.draw_bone_debug {
 			core.set_depth(None);
-			for (AnimatedMeshInstance{transform, animation, ..}, bone_offset) in self.instances.iter().zip(&bone_offsets) {
-				let AnimationID(mesh, _) = animation;
-				let animated_mesh_data = self.mesh_animations.get(mesh)
-					.expect("trying to get animation data for unregistered mesh");
-
-				core.set_uniform_i32("u_bone_offset", *bone_offset as _);
-				core.set_uniform_mat4("u_object", &transform);
-				core.draw_mesh_lines(animated_mesh_data.bone_mesh);
+			for mesh in mesh_instances.keys() {
+				let animated_mesh_data = &self.mesh_animations[mesh];
+				core.draw_mesh_lines_instanced(animated_mesh_data.bone_mesh, animated_mesh_data.instance_buffer);
 			}
 			core.set_depth(core::DepthFunc::default());
 		}
@@ -264,6 +276,23 @@ impl BoneFrame {
 
 
 
+/// A `TransformInstance` plus where the instance's bone frames start in the bone buffer
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct AnimatedInstance {
+	transform: TransformInstance,
+	bone_offset: i32,
+}
+
+impl vertex::Vertex for AnimatedInstance {
+	fn descriptor() -> vertex::Descriptor {
+		TransformInstance::descriptor()
+			.add_typed(4, 1, vertex::AttributeType::I32)
+	}
+}
+
+
+
 const WEIGHTS_PER_VERTEX: usize = 3;
 
 #[repr(C)]
@@ -271,12 +300,13 @@ const WEIGHTS_PER_VERTEX: usize = 3;
 pub struct WeightedVertex {
 	pub pos: Vec3,
 	pub color: Vec4,
-	pub bone_indices: [f32; WEIGHTS_PER_VERTEX],
 	pub bone_weights: [f32; WEIGHTS_PER_VERTEX],
+	// Last so that the struct padding falls after it rather than between attributes
+	pub bone_indices: [u16; WEIGHTS_PER_VERTEX],
 }
 
 impl WeightedVertex {
-	pub fn new(pos: Vec3, color: Color, bone_indices: [f32; WEIGHTS_PER_VERTEX],
+	pub fn new(pos: Vec3, color: Color, bone_indices: [u16; WEIGHTS_PER_VERTEX],
 		bone_weights: [f32; WEIGHTS_PER_VERTEX]) -> Self
 	{
 		WeightedVertex{pos, color: color.into(), bone_indices, bone_weights}
@@ -285,7 +315,12 @@ impl WeightedVertex {
 
 impl vertex::Vertex for WeightedVertex {
 	fn descriptor() -> vertex::Descriptor {
-		vertex::Descriptor::from(&[3, 4, WEIGHTS_PER_VERTEX as _, WEIGHTS_PER_VERTEX as _])
+		// Attribute positions match the attribute order passed to new_shader
+		vertex::Descriptor::new()
+			.add(0, 3)
+			.add(1, 4)
+			.add(3, WEIGHTS_PER_VERTEX as _)
+			.add_typed(2, WEIGHTS_PER_VERTEX as _, vertex::AttributeType::U16)
 	}
 }
 
@@ -1,4 +1,5 @@
 use crate::prelude::*;
+use crate::util::{Aabb, Ray};
 
 use std::cell::Cell;
 use std::fmt::Debug;
@@ -32,6 +33,37 @@ pub struct Camera {
 }
 
 
+/// Matches the std140 layout of `CameraBlock`:
+/// ```glsl
+/// layout(std140) uniform CameraBlock {
+/// 	mat4 u_proj_view;
+/// 	mat4 u_view;
+/// 	mat4 u_projection;
+/// 	vec4 u_camera_position;
+/// };
+/// ```
+/// Matrices are column major
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct CameraUniforms {
+	pub proj_view: Mat4,
+	pub view: Mat4,
+	pub projection: Mat4,
+	pub position: Vec4,
+}
+
+impl Default for CameraUniforms {
+	fn default() -> Self {
+		CameraUniforms {
+			proj_view: Mat4::ident(),
+			view: Mat4::ident(),
+			projection: Mat4::ident(),
+			position: Vec4::new(0.0, 0.0, 0.0, 1.0),
+		}
+	}
+}
+
+
 #[derive(Copy, Clone, Debug)]
 pub enum ProjectionMode {
 	Orthographic { view_size: f32 },
@@ -165,6 +197,89 @@ impl Camera {
 	}
 
 
+	pub fn uniforms(&self) -> CameraUniforms {
+		CameraUniforms {
+			proj_view: self.projection_view().transpose(),
+			view: self.view_matrix().transpose(),
+			projection: self.projection_matrix().transpose(),
+			position: Vec4::new(self.position.x, self.position.y, self.position.z, 1.0),
+		}
+	}
+
+
+	/// The planes bounding everything the camera can see
+	pub fn frustum(&self) -> Frustum {
+		let [r0, r1, r2, r3] = self.projection_view().rows;
+
+		// Each plane is a sum or difference of rows of the projection view matrix, e.g., a point
+		// is inside the left plane when clip.x >= -clip.w, i.e., (r3 + r0) . (p, 1) >= 0
+		let plane = |v: Vec4| {
+			let normal = Vec3::new(v.x, v.y, v.z);
+			let scale = normal.length();
+			Plane { normal: normal / scale, length: -v.w / scale }
+		};
+
+		Frustum {
+			planes: [
+				plane(r3 + r0),
+				plane(r3 - r0),
+				plane(r3 + r1),
+				plane(r3 - r1),
+				plane(r3 + r2),
+				plane(r3 - r2),
+			]
+		}
+	}
+
+
+	/// Projects a world position into normalised device coordinates - x and y in [-1, 1] with y up,
+	/// and z in [-1, 1] from the near plane to the far plane. None if the position is behind the camera
+	pub fn world_to_ndc(&self, world: Vec3) -> Option<Vec3> {
+		let clip = self.projection_view() * Vec4::new(world.x, world.y, world.z, 1.0);
+		if clip.w <= 0.0 {
+			return None
+		}
+
+		Some(clip.to_vec3() / clip.w)
+	}
+
+	pub fn ndc_to_world(&self, ndc: Vec3) -> Vec3 {
+		let world = self.inverse_projection_view() * Vec4::new(ndc.x, ndc.y, ndc.z, 1.0);
+		world.to_vec3() / world.w
+	}
+
+	/// Converts from window pixels, with the origin in the top left as given by cursor events
+	pub fn window_to_ndc(&self, window_pos: Vec2) -> Vec2 {
+		let window_half = self.viewport.to_vec2() / 2.0;
+		(window_pos - window_half) / window_half * Vec2::new(1.0, -1.0)
+	}
+
+	pub fn ndc_to_window(&self, ndc: Vec2) -> Vec2 {
+		let window_half = self.viewport.to_vec2() / 2.0;
+		ndc * Vec2::new(1.0, -1.0) * window_half + window_half
+	}
+
+	pub fn world_to_window(&self, world: Vec3) -> Option<Vec2> {
+		self.world_to_ndc(world)
+			.map(|ndc| self.ndc_to_window(Vec2::new(ndc.x, ndc.y)))
+	}
+
+	/// A ray from the near plane through `ndc`. Rays from orthographic cameras are all parallel
+	pub fn ray_from_ndc(&self, ndc: Vec2) -> Ray {
+		let near = self.ndc_to_world(Vec3::new(ndc.x, ndc.y, -1.0));
+		let far = self.ndc_to_world(Vec3::new(ndc.x, ndc.y, 1.0));
+		Ray::new(near, far - near)
+	}
+
+	pub fn ray_from_window(&self, window_pos: Vec2) -> Ray {
+		self.ray_from_ndc(self.window_to_ndc(window_pos))
+	}
+
+	/// The ray through the middle of the screen, e.g., for picking with a crosshair
+	pub fn center_ray(&self) -> Ray {
+		self.ray_from_ndc(Vec2::zero())
+	}
+
 
 	pub fn inverse_projection_matrix(&self) -> Mat4 {
 		self.inv_projection_matrix.get_or_update(|| {
@@ -210,6 +325,31 @@ impl Camera {
 
 
 
+/// Left, right, bottom, top, near and far planes, with normals pointing inwards
+#[derive(Copy, Clone, Debug)]
+pub struct Frustum {
+	pub planes: [Plane; 6],
+}
+
+impl Frustum {
+	/// Conservative - boxes near the corners of the frustum may be reported visible when they aren't
+	pub fn contains_aabb(&self, aabb: &Aabb) -> bool {
+		self.planes.iter().all(|plane| {
+			// The corner furthest along the plane normal
+			let corner = Vec3::new(
+				if plane.normal.x >= 0.0 { aabb.max.x } else { aabb.min.x },
+				if plane.normal.y >= 0.0 { aabb.max.y } else { aabb.min.y },
+				if plane.normal.z >= 0.0 { aabb.max.z } else { aabb.min.z },
+			);
+
+			plane.normal.dot(corner) >= plane.length
+		})
+	}
+}
+
+
+
+
 #[derive(Clone, Debug)]
 struct Memoised<T: Copy + Clone + Debug> {
 	mat: Cell<T>,
@@ -1,5 +1,6 @@
 
 use std::marker::PhantomData;
+use std::ops::Range;
 
 use crate::prelude::*;
 
@@ -7,8 +8,18 @@ use super::shader::*;
 use super::vertex::*;
 use super::mesh::*;
 use super::texture_buffer::*;
-
-
+use super::instance_buffer::*;
+use super::uniform_buffer::*;
+use super::framebuffer::*;
+use super::texture::*;
+use super::image::Image;
+use super::camera::{Camera, CameraUniforms};
+use super::resource::ResourceStore;
+use super::profiler::Profiler;
+use super::state::{StateCache, FrameStats};
+
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum BlendMode {
 	None,
 	Alpha,
@@ -22,6 +33,7 @@ pub enum BlendMode {
 }
 
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum DepthFunc {
 	LessEqual,
 	Equal,
@@ -36,13 +48,32 @@ impl Default for DepthFunc {
 pub struct Core {
 	capabilities: Capabilities,
 
-	shaders: Vec<Shader>,
-	meshes: Vec<Mesh>,
-	basic_meshes: Vec<BasicMesh>,
-	texture_buffers: Vec<TextureBuffer>,
+	shaders: ResourceStore<Shader>,
+	meshes: ResourceStore<Mesh>,
+	basic_meshes: ResourceStore<BasicMesh>,
+	texture_buffers: ResourceStore<TextureBuffer>,
+	instance_buffers: ResourceStore<InstanceBuffer>,
+	uniform_buffers: ResourceStore<UniformBuffer>,
+	textures: ResourceStore<Texture>,
+	framebuffers: ResourceStore<Framebuffer>,
+
+	/// Whether `poll_shader_changes` should reload shaders from disk. On by default in debug builds
+	shader_hot_reload: bool,
 
 	bound_shader: Option<ShaderID>,
 	bound_mesh: Option<UntypedMeshID>,
+	bound_framebuffer: Option<FramebufferID>,
+
+	/// Permanently bound to CAMERA_BLOCK_BINDING, and updated by `set_camera`
+	camera_buffer: UniformBufferID<CameraUniforms>,
+
+	/// The viewport last requested for the default framebuffer, restored when unbinding framebuffers
+	backbuffer_viewport: Vec2i,
+
+	state: StateCache,
+	stats: FrameStats,
+
+	profiler: Profiler,
 }
 
 
@@ -52,21 +83,61 @@ impl Core {
 
 		println!("capabilities: {:#?}", capabilities);
 
-		Core {
+		let profiler = Profiler::new(capabilities.timer_queries);
+
+		let mut uniform_buffers = ResourceStore::new();
+		let camera_buffer = UniformBufferID(uniform_buffers.insert(UniformBuffer::new()), PhantomData);
+
+		let mut core = Core {
 			capabilities,
 
-			shaders: Vec::new(),
-			meshes: Vec::new(),
-			basic_meshes: Vec::new(),
-			texture_buffers: Vec::new(),
+			shaders: ResourceStore::new(),
+			meshes: ResourceStore::new(),
+			basic_meshes: ResourceStore::new(),
+			texture_buffers: ResourceStore::new(),
+			instance_buffers: ResourceStore::new(),
+			uniform_buffers,
+			textures: ResourceStore::new(),
+			framebuffers: ResourceStore::new(),
+
+			shader_hot_reload: cfg!(debug_assertions),
 
 			bound_shader: None,
 			bound_mesh: None,
-		}
+			bound_framebuffer: None,
+
+			camera_buffer,
+
+			backbuffer_viewport: Vec2i::splat(1),
+
+			state: StateCache::default(),
+			stats: FrameStats::default(),
+
+			profiler,
+		};
+
+		core.update_uniform_buffer(camera_buffer, &CameraUniforms::default());
+		core.bind_uniform_buffer(camera_buffer, CAMERA_BLOCK_BINDING);
+		core
 	}
 
 	pub fn capabilities(&self) -> &Capabilities { &self.capabilities }
 
+	pub fn profiler(&mut self) -> &mut Profiler { &mut self.profiler }
+
+	/// Counts of draw calls, state changes and uploads since the last call
+	pub fn take_frame_stats(&mut self) -> FrameStats {
+		std::mem::take(&mut self.stats)
+	}
+
+	pub fn frame_stats(&self) -> FrameStats { self.stats }
+
+	/// Forces all state to be reapplied, for when something has changed GL state outside of Core
+	pub fn invalidate_state_cache(&mut self) {
+		self.state = StateCache::default();
+		self.bound_mesh = None;
+	}
+
 	pub fn set_bg_color(&mut self, c: Color) {
 		unsafe {
 			let (r,g,b,a) = c.to_tuple();
@@ -75,6 +146,17 @@ impl Core {
 	}
 
 	pub fn set_viewport(&mut self, size: Vec2i) {
+		if self.bound_framebuffer.is_none() {
+			self.backbuffer_viewport = size;
+		}
+
+		self.apply_viewport(size);
+	}
+
+	fn apply_viewport(&mut self, size: Vec2i) {
+		if !self.stats.record_state_change(self.state.viewport != Some(size)) { return }
+		self.state.viewport = Some(size);
+
 		unsafe {
 			let Vec2i{x, y} = size;
 			gl::Viewport(0, 0, x, y);
@@ -82,6 +164,9 @@ impl Core {
 	}
 
 	pub fn set_color_write(&mut self, enable: bool) {
+		if !self.stats.record_state_change(self.state.color_write != Some(enable)) { return }
+		self.state.color_write = Some(enable);
+
 		unsafe {
 			let v = if enable { gl::TRUE } else { gl::FALSE };
 			gl::ColorMask(v, v, v, v);
@@ -89,8 +174,12 @@ impl Core {
 	}
 
 	pub fn set_stencil(&mut self, params: impl Into<Option<StencilParams>>) {
+		let params = params.into();
+		if !self.stats.record_state_change(self.state.stencil != Some(params)) { return }
+		self.state.stencil = Some(params);
+
 		unsafe {
-			if let Some(params) = params.into() {
+			if let Some(params) = params {
 				gl::Enable(gl::STENCIL_TEST);
 				gl::StencilFunc(params.write_condition, params.reference as _, 0xff);
 				gl::StencilOp(params.stencil_fail_op, params.depth_fail_op, params.pass_op);
@@ -104,8 +193,12 @@ impl Core {
 	}
 
 	pub fn set_depth(&mut self, params: impl Into<Option<DepthFunc>>) {
+		let params = params.into();
+		if !self.stats.record_state_change(self.state.depth != Some(params)) { return }
+		self.state.depth = Some(params);
+
 		unsafe {
-			if let Some(params) = params.into() {
+			if let Some(params) = params {
 				let depth_func = match params {
 					DepthFunc::LessEqual => gl::LEQUAL,
 					DepthFunc::Equal => gl::EQUAL,
@@ -125,6 +218,9 @@ impl Core {
 	pub fn set_blend_mode(&mut self, blend_mode: BlendMode) {
 		use self::BlendMode::*;
 
+		if !self.stats.record_state_change(self.state.blend_mode != Some(blend_mode)) { return }
+		self.state.blend_mode = Some(blend_mode);
+
 		let (equation, source, target) = match blend_mode {
 			None => unsafe {
 				gl::Disable(gl::BLEND);
@@ -156,47 +252,144 @@ impl Core {
 
 
 	// Shaders
-	pub fn new_shader(&mut self, vsrc: &str, fsrc: &str, attribs: &[&str]) -> ShaderID {
-		self.shaders.push(Shader::new(vsrc, fsrc, attribs));
-		ShaderID(self.shaders.len()-1)
+	/// Creates a shader for drawing meshes of vertex type `V`. `attribs` names the shader inputs
+	/// for each attribute location in `V::descriptor()`, and the shader's active attributes
+	/// are validated against it
+	pub fn new_shader<V: Vertex>(&mut self, vsrc: impl Into<ShaderSource>, fsrc: impl Into<ShaderSource>, attribs: &[&str])
+		-> Result<ShaderID, ShaderError>
+	{
+		self.new_shader_variant::<V>(vsrc, fsrc, attribs, &[])
 	}
 
-	pub fn use_shader(&mut self, id: ShaderID) {
-		unsafe {
-			let shader = self.shaders.get(id.0).expect("Tried to use invalid shader");
-			gl::UseProgram(shader.handle);
-			self.bound_shader = Some(id);
+	/// Creates a shader with extra `#define NAME VALUE`s injected into both stages
+	pub fn new_shader_variant<V: Vertex>(&mut self, vsrc: impl Into<ShaderSource>, fsrc: impl Into<ShaderSource>, attribs: &[&str],
+		defines: &[(&str, &str)]) -> Result<ShaderID, ShaderError>
+	{
+		let shader = Shader::new::<V>(vsrc.into(), fsrc.into(), attribs, defines)?;
+		Ok(ShaderID(self.shaders.insert(shader)))
+	}
 
+	/// Creates a shader for drawing meshes of vertex type `V` with per-instance attributes of type `I`.
+	/// `attribs` names `V`'s attributes followed by `I`'s, and `INSTANCED` is defined in both stages
+	pub fn new_instanced_shader<V: Vertex, I: Vertex>(&mut self, vsrc: impl Into<ShaderSource>, fsrc: impl Into<ShaderSource>,
+		attribs: &[&str]) -> Result<ShaderID, ShaderError>
+	{
+		let vertex_descriptor = V::descriptor();
+		let instance_descriptor = I::descriptor().offset_positions(vertex_descriptor.location_count());
+
+		let bindings = vertex_descriptor.bindings().iter()
+			.chain(instance_descriptor.bindings())
+			.cloned()
+			.collect();
+
+		let shader = Shader::with_bindings(vsrc.into(), fsrc.into(), attribs, &[("INSTANCED", "1")], bindings)?;
+		Ok(ShaderID(self.shaders.insert(shader)))
+	}
 
-			for i in 0..shader.attribute_count {
-				gl::EnableVertexAttribArray(i);
-			}
+	pub fn set_shader_hot_reload(&mut self, enabled: bool) {
+		self.shader_hot_reload = enabled;
+	}
+
+	/// Recompiles any shaders whose source files have changed on disk, if hot reloading is enabled.
+	/// Shaders that fail to compile keep their previous program
+	pub fn poll_shader_changes(&mut self) {
+		if !self.shader_hot_reload { return }
+
+		let mut rebind_shader = false;
 
-			for i in shader.attribute_count..8 {
-				gl::DisableVertexAttribArray(i);
+		for (key, shader) in self.shaders.iter_mut() {
+			match shader.reload_if_changed() {
+				Some(Ok(())) => {
+					println!("Reloaded shader {}", shader.describe());
+					rebind_shader |= self.bound_shader == Some(ShaderID(key));
+				}
+
+				Some(Err(err)) => eprintln!("Failed to reload shader {}: {}", shader.describe(), err),
+				None => {}
 			}
 		}
+
+		if let Some(id) = self.bound_shader.filter(|_| rebind_shader) {
+			self.use_shader(id);
+		}
 	}
 
-	fn get_uniform_location(&self, name: &str) -> i32 {
+	pub fn destroy_shader(&mut self, id: ShaderID) {
+		let shader = self.shaders.remove(id.0).expect("Tried to destroy invalid or destroyed shader");
+
+		if self.state.program == Some(shader.handle) {
+			self.state.program = None;
+		}
+
+		shader.destroy();
+
+		if self.bound_shader == Some(id) {
+			self.bound_shader = None;
+		}
+	}
+
+	pub fn use_shader(&mut self, id: ShaderID) {
+		let shader = self.shaders.get(id.0).expect("Tried to use invalid or destroyed shader");
+		self.bound_shader = Some(id);
+
+		// Reloaded shaders get a new handle, so are rebound
+		if !self.stats.record_state_change(self.state.program != Some(shader.handle)) { return }
+		self.state.program = Some(shader.handle);
+
 		unsafe {
-			let shader_id = self.bound_shader.unwrap();
-			let shader = &self.shaders[shader_id.0];
-			let name = std::ffi::CString::new(name.as_bytes()).unwrap();
-			gl::GetUniformLocation(shader.handle, name.as_ptr())
+			gl::UseProgram(shader.handle);
 		}
 	}
 
+	fn get_uniform_location(&mut self, name: &str) -> i32 {
+		let shader_id = self.bound_shader.expect("Tried to set uniform without a bound shader");
+		let shader = self.shaders.get_mut(shader_id.0).expect("Bound shader was destroyed");
+		shader.uniform_location(name)
+	}
+
 	pub fn set_uniform_i32(&mut self, name: &str, value: i32) {
 		let loc = self.get_uniform_location(name);
 		unsafe { gl::Uniform1i(loc, value) }
 	}
 
+	pub fn set_uniform_f32(&mut self, name: &str, value: f32) {
+		let loc = self.get_uniform_location(name);
+		unsafe { gl::Uniform1f(loc, value) }
+	}
+
+	pub fn set_uniform_vec2(&mut self, name: &str, Vec2{x, y}: Vec2) {
+		let loc = self.get_uniform_location(name);
+		unsafe { gl::Uniform2f(loc, x, y) }
+	}
+
+	pub fn set_uniform_vec3(&mut self, name: &str, Vec3{x, y, z}: Vec3) {
+		let loc = self.get_uniform_location(name);
+		unsafe { gl::Uniform3f(loc, x, y, z) }
+	}
+
 	pub fn set_uniform_vec4(&mut self, name: &str, Vec4{x, y, z, w}: Vec4) {
 		let loc = self.get_uniform_location(name);
 		unsafe { gl::Uniform4f(loc, x, y, z, w) }
 	}
 
+	/// Sets a mat3 uniform from the upper 3x3 of `value`, discarding translation and the last row,
+	/// e.g., for transforming normals
+	pub fn set_uniform_mat3_from_mat4(&mut self, name: &str, value: &Mat4) {
+		let loc = self.get_uniform_location(name);
+		let [r0, r1, r2, _] = value.rows;
+
+		// Column major
+		let data = [
+			r0.x, r1.x, r2.x,
+			r0.y, r1.y, r2.y,
+			r0.z, r1.z, r2.z,
+		];
+
+		unsafe {
+			gl::UniformMatrix3fv(loc, 1, 0, data.as_ptr());
+		}
+	}
+
 	pub fn set_uniform_mat4(&mut self, name: &str, value: &Mat4) {
 		let loc = self.get_uniform_location(name);
 		unsafe {
@@ -204,33 +397,110 @@ impl Core {
 		}
 	}
 
+	pub fn set_uniform_i32_array(&mut self, name: &str, values: &[i32]) {
+		let loc = self.get_uniform_location(name);
+		unsafe { gl::Uniform1iv(loc, values.len() as _, values.as_ptr()) }
+	}
+
+	pub fn set_uniform_f32_array(&mut self, name: &str, values: &[f32]) {
+		let loc = self.get_uniform_location(name);
+		unsafe { gl::Uniform1fv(loc, values.len() as _, values.as_ptr()) }
+	}
+
+	pub fn set_uniform_vec2_array(&mut self, name: &str, values: &[Vec2]) {
+		let loc = self.get_uniform_location(name);
+		unsafe { gl::Uniform2fv(loc, values.len() as _, values.as_ptr() as *const f32) }
+	}
+
+	pub fn set_uniform_vec3_array(&mut self, name: &str, values: &[Vec3]) {
+		let loc = self.get_uniform_location(name);
+		unsafe { gl::Uniform3fv(loc, values.len() as _, values.as_ptr() as *const f32) }
+	}
+
+	pub fn set_uniform_vec4_array(&mut self, name: &str, values: &[Vec4]) {
+		let loc = self.get_uniform_location(name);
+		unsafe { gl::Uniform4fv(loc, values.len() as _, values.as_ptr() as *const f32) }
+	}
+
+	pub fn set_uniform_mat4_array(&mut self, name: &str, values: &[Mat4]) {
+		let loc = self.get_uniform_location(name);
+		let values = values.iter().map(|m| m.transpose()).collect(): Vec<_>;
+		unsafe {
+			gl::UniformMatrix4fv(loc, values.len() as _, 0, values.as_ptr() as *const f32);
+		}
+	}
+
 	pub fn set_uniform_texture_buffer<V: Copy>(&mut self, name: &str, buffer: TextureBufferID<V>, slot: u32) {
 		let loc = self.get_uniform_location(name);
-		let buffer = self.texture_buffers.get(buffer.0).expect("Tried to bind invalid texture buffer");
+		let texture = self.texture_buffers.get(buffer.0).expect("Tried to bind invalid or destroyed texture buffer").texture_id;
+		self.bind_texture(slot, gl::TEXTURE_BUFFER, texture);
+
 		unsafe {
-			gl::ActiveTexture(gl::TEXTURE0 + slot);
-			gl::BindTexture(gl::TEXTURE_BUFFER, buffer.texture_id);
 			gl::Uniform1i(loc, slot as _)
 		}
 	}
 
+	fn bind_texture(&mut self, slot: u32, target: u32, handle: u32) {
+		if !self.stats.record_state_change(self.state.textures.get(&(slot, target)) != Some(&handle)) { return }
+		self.state.textures.insert((slot, target), handle);
+
+		unsafe {
+			if self.state.active_texture_slot != Some(slot) {
+				gl::ActiveTexture(gl::TEXTURE0 + slot);
+				self.state.active_texture_slot = Some(slot);
+			}
+
+			gl::BindTexture(target, handle);
+		}
+	}
+
 
 	// Meshes
 	pub fn new_mesh<V: Vertex>(&mut self) -> MeshID<V> {
-		self.meshes.push(Mesh::new(V::descriptor()));
-		MeshID(self.meshes.len()-1, PhantomData)
+		let id = MeshID(self.meshes.insert(Mesh::new::<V>()), PhantomData);
+		self.bound_mesh = Some(id.into());
+		id
 	}
 
 	pub fn new_basic_mesh<V: Vertex>(&mut self) -> BasicMeshID<V> {
-		self.basic_meshes.push(BasicMesh::new(V::descriptor()));
-		BasicMeshID(self.basic_meshes.len()-1, PhantomData)
+		let id = BasicMeshID(self.basic_meshes.insert(BasicMesh::new::<V>()), PhantomData);
+		self.bound_mesh = Some(id.into());
+		id
+	}
+
+	pub fn destroy_mesh<V: Vertex>(&mut self, id: MeshID<V>) {
+		let mesh = self.meshes.remove(id.0).expect("Tried to destroy invalid or destroyed mesh");
+		mesh.destroy();
+		self.forget_bound_mesh(id.into());
+	}
+
+	pub fn destroy_basic_mesh<V: Vertex>(&mut self, id: BasicMeshID<V>) {
+		let mesh = self.basic_meshes.remove(id.0).expect("Tried to destroy invalid or destroyed mesh");
+		mesh.destroy();
+		self.forget_bound_mesh(id.into());
+	}
+
+	fn forget_bound_mesh(&mut self, id: UntypedMeshID) {
+		if self.bound_mesh == Some(id) {
+			self.bound_mesh = None;
+		}
 	}
 
 	pub fn update_mesh<V: Vertex>(&mut self, id: MeshID<V>, vs: &[V], es: &[u16]) {
+		self.upload_mesh(id, vs, es, gl::UNSIGNED_SHORT);
+	}
+
+	/// Like `update_mesh` but for meshes with more than 65536 vertices
+	pub fn update_mesh_u32<V: Vertex>(&mut self, id: MeshID<V>, vs: &[V], es: &[u32]) {
+		self.upload_mesh(id, vs, es, gl::UNSIGNED_INT);
+	}
+
+	fn upload_mesh<V: Vertex, I: Copy>(&mut self, id: MeshID<V>, vs: &[V], es: &[I], index_type: u32) {
 		id.bind_mesh(self);
 
-		let mesh = self.meshes.get_mut(id.0).expect("Tried to bind invalid mesh");
+		let mesh = self.meshes.get_mut(id.0).expect("Tried to update invalid or destroyed mesh");
 		mesh.element_count = es.len() as _;
+		mesh.index_type = index_type;
 
 		unsafe {
 			gl::BufferData(
@@ -242,17 +512,19 @@ impl Core {
 
 			gl::BufferData(
 				gl::ELEMENT_ARRAY_BUFFER,
-				(es.len() * std::mem::size_of::<u16>()) as _,
+				(es.len() * std::mem::size_of::<I>()) as _,
 				es.as_ptr() as *const _,
 				gl::STATIC_DRAW
 			);
 		}
+
+		self.stats.bytes_uploaded += vs.len() * std::mem::size_of::<V>() + es.len() * std::mem::size_of::<I>();
 	}
 
 	pub fn update_basic_mesh<V: Vertex>(&mut self, id: BasicMeshID<V>, vs: &[V]) {
 		id.bind_mesh(self);
 
-		let mesh = self.basic_meshes.get_mut(id.0).expect("Tried to bind invalid mesh");
+		let mesh = self.basic_meshes.get_mut(id.0).expect("Tried to update invalid or destroyed mesh");
 		mesh.vertex_count = vs.len() as _;
 
 		unsafe {
@@ -263,6 +535,8 @@ impl Core {
 				gl::STATIC_DRAW
 			);
 		}
+
+		self.stats.bytes_uploaded += vs.len() * std::mem::size_of::<V>();
 	}
 
 	pub fn draw_mesh<ID: MeshIDLike>(&mut self, id: ID) {
@@ -280,11 +554,148 @@ impl Core {
 		id.draw_mesh(self, gl::POINTS);
 	}
 
+	/// Draws a contiguous range of a mesh's triangles. `range` is in elements, or in vertices for basic meshes
+	pub fn draw_mesh_range<ID: MeshIDLike>(&mut self, id: ID, range: Range<u32>) {
+		id.bind_mesh(self);
+		id.draw_mesh_range(self, gl::TRIANGLES, range);
+	}
+
+	/// Draws `id` once for every instance in `instances`, in a single draw call
+	pub fn draw_mesh_instanced<ID: Into<UntypedMeshID>, I: Vertex>(&mut self, id: ID, instances: InstanceBufferID<I>) {
+		self.draw_instanced(id.into(), instances.into(), gl::TRIANGLES);
+	}
+
+	pub fn draw_mesh_lines_instanced<ID: Into<UntypedMeshID>, I: Vertex>(&mut self, id: ID, instances: InstanceBufferID<I>) {
+		self.draw_instanced(id.into(), instances.into(), gl::LINES);
+	}
+
+	pub(super) fn draw_instanced(&mut self, mesh_id: UntypedMeshID, instances: UntypedInstanceBufferID, draw_mode: u32) {
+		let buffer = self.instance_buffers.get(instances.0).expect("Tried to draw with invalid or destroyed instance buffer");
+		let (buffer_handle, instance_count) = (buffer.handle, buffer.instance_count);
+
+		if instance_count == 0 { return }
+
+		mesh_id.bind_mesh(self);
+
+		let (attached_buffer, attribute_count) = match mesh_id {
+			UntypedMeshID::Indexed(key) => {
+				let mesh = self.meshes.get_mut(key).expect("Tried to draw invalid or destroyed mesh");
+				(&mut mesh.instance_buffer, mesh.attribute_count)
+			}
+
+			UntypedMeshID::Basic(key) => {
+				let mesh = self.basic_meshes.get_mut(key).expect("Tried to draw invalid or destroyed mesh");
+				(&mut mesh.instance_buffer, mesh.attribute_count)
+			}
+		};
+
+		// Instance attributes are vertex array state, so only need pointing at the buffer when it changes
+		if *attached_buffer != Some(instances.0) {
+			*attached_buffer = Some(instances.0);
+
+			unsafe {
+				gl::BindBuffer(gl::ARRAY_BUFFER, buffer_handle);
+			}
+
+			let buffer = self.instance_buffers.get(instances.0).unwrap();
+			buffer.descriptor.clone()
+				.offset_positions(attribute_count)
+				.configure_instanced(buffer.stride);
+
+			// Rebind to restore the mesh's own vertex buffer, which updates expect to be bound
+			self.bound_mesh = None;
+			mesh_id.bind_mesh(self);
+		}
+
+		self.submit_draw(mesh_id, draw_mode, None, Some(instance_count));
+	}
+
+	/// Draws the whole mesh, or just the elements (or vertices, for basic meshes) in `range`
+	fn submit_draw(&mut self, mesh_id: UntypedMeshID, draw_mode: u32, range: Option<Range<u32>>, instance_count: Option<u32>) {
+		let (total_count, index_type) = match mesh_id {
+			UntypedMeshID::Indexed(key) => {
+				let mesh = self.meshes.get(key).expect("Tried to draw invalid or destroyed mesh");
+				(mesh.element_count, Some(mesh.index_type))
+			}
+
+			UntypedMeshID::Basic(key) => {
+				let mesh = self.basic_meshes.get(key).expect("Tried to draw invalid or destroyed mesh");
+				(mesh.vertex_count, None)
+			}
+		};
+
+		let range = range.unwrap_or(0..total_count);
+		assert!(range.start <= range.end && range.end <= total_count,
+			"Tried to draw range {:?} of mesh with {} elements", range, total_count);
+
+		let element_count = range.end - range.start;
+		if element_count == 0 { return }
+
+		self.stats.draw_calls += 1;
+
+		unsafe {
+			match index_type {
+				Some(index_type) => {
+					let index_size = if index_type == gl::UNSIGNED_INT { 4 } else { 2 };
+					let offset = (range.start as usize * index_size) as *const _;
+
+					match instance_count {
+						None => gl::DrawElements(draw_mode, element_count as _, index_type, offset),
+						Some(instance_count) => gl::DrawElementsInstanced(draw_mode, element_count as _,
+							index_type, offset, instance_count as _),
+					}
+				}
+
+				None => match instance_count {
+					None => gl::DrawArrays(draw_mode, range.start as _, element_count as _),
+					Some(instance_count) => gl::DrawArraysInstanced(draw_mode, range.start as _,
+						element_count as _, instance_count as _),
+				}
+			}
+		}
+	}
+
+
+	// InstanceBuffers
+	pub fn new_instance_buffer<I: Vertex>(&mut self) -> InstanceBufferID<I> {
+		InstanceBufferID(self.instance_buffers.insert(InstanceBuffer::new::<I>()), PhantomData)
+	}
+
+	pub fn destroy_instance_buffer<I: Vertex>(&mut self, id: InstanceBufferID<I>) {
+		let buffer = self.instance_buffers.remove(id.0).expect("Tried to destroy invalid or destroyed instance buffer");
+		buffer.destroy();
+	}
+
+	pub fn update_instance_buffer<I: Vertex>(&mut self, id: InstanceBufferID<I>, instances: &[I]) {
+		let buffer = self.instance_buffers.get_mut(id.0).expect("Tried to update invalid or destroyed instance buffer");
+		buffer.instance_count = instances.len() as _;
+
+		unsafe {
+			gl::BindBuffer(gl::ARRAY_BUFFER, buffer.handle);
+			gl::BufferData(
+				gl::ARRAY_BUFFER,
+				(instances.len() * std::mem::size_of::<I>()) as _,
+				instances.as_ptr() as *const _,
+				gl::STREAM_DRAW
+			);
+		}
+
+		// The bound mesh's vertex buffer is no longer bound to ARRAY_BUFFER
+		self.bound_mesh = None;
+		self.stats.bytes_uploaded += instances.len() * std::mem::size_of::<I>();
+	}
+
 
 	// TextureBuffers
 	pub fn new_texture_buffer<V: Copy>(&mut self) -> TextureBufferID<V> {
-		self.texture_buffers.push(TextureBuffer::new());
-		TextureBufferID(self.texture_buffers.len()-1, PhantomData)
+		self.state.invalidate_textures();
+		TextureBufferID(self.texture_buffers.insert(TextureBuffer::new()), PhantomData)
+	}
+
+	pub fn destroy_texture_buffer<V: Copy>(&mut self, id: TextureBufferID<V>) {
+		let buffer = self.texture_buffers.remove(id.0).expect("Tried to destroy invalid or destroyed texture buffer");
+		buffer.destroy();
+		self.state.invalidate_textures();
 	}
 
 	pub fn update_texture_buffer<V: Copy>(&mut self, id: TextureBufferID<V>, data: &[V]) {
@@ -295,7 +706,7 @@ impl Core {
 		assert!(buffer_size % (std::mem::size_of::<f32>() * 4) == 0,
 			"Texture buffer data mis-sized; currently only support 4xf32 format data");
 
-		let buffer = self.texture_buffers.get(id.0).expect("Tried to update invalid texture buffer");
+		let buffer = self.texture_buffers.get(id.0).expect("Tried to update invalid or destroyed texture buffer");
 
 		unsafe {
 			gl::BindBuffer(gl::TEXTURE_BUFFER, buffer.buffer_id);
@@ -306,6 +717,264 @@ impl Core {
 				gl::STREAM_DRAW
 			);
 		}
+
+		self.stats.bytes_uploaded += buffer_size;
+	}
+
+
+	// UniformBuffers
+	pub fn new_uniform_buffer<T: Copy>(&mut self) -> UniformBufferID<T> {
+		UniformBufferID(self.uniform_buffers.insert(UniformBuffer::new()), PhantomData)
+	}
+
+	pub fn destroy_uniform_buffer<T: Copy>(&mut self, id: UniformBufferID<T>) {
+		assert!(id.0 != self.camera_buffer.0, "Tried to destroy camera uniform buffer");
+
+		let buffer = self.uniform_buffers.remove(id.0).expect("Tried to destroy invalid or destroyed uniform buffer");
+		buffer.destroy();
+	}
+
+	/// `T` must be laid out to match the std140 layout of the blocks the buffer is bound to
+	pub fn update_uniform_buffer<T: Copy>(&mut self, id: UniformBufferID<T>, data: &T) {
+		let buffer = self.uniform_buffers.get(id.0).expect("Tried to update invalid or destroyed uniform buffer");
+
+		unsafe {
+			gl::BindBuffer(gl::UNIFORM_BUFFER, buffer.handle);
+			gl::BufferData(
+				gl::UNIFORM_BUFFER,
+				std::mem::size_of::<T>() as _,
+				data as *const T as *const _,
+				gl::STREAM_DRAW
+			);
+		}
+
+		self.stats.bytes_uploaded += std::mem::size_of::<T>();
+	}
+
+	/// Binds a uniform buffer to a binding point, so that any shader with a block assigned
+	/// to that binding point reads from it
+	pub fn bind_uniform_buffer<T: Copy>(&mut self, id: UniformBufferID<T>, binding: u32) {
+		let buffer = self.uniform_buffers.get(id.0).expect("Tried to bind invalid or destroyed uniform buffer");
+
+		unsafe {
+			gl::BindBufferBase(gl::UNIFORM_BUFFER, binding, buffer.handle);
+		}
+	}
+
+	/// Assigns a uniform block in the bound shader to a binding point.
+	/// `CameraBlock` is assigned automatically for all shaders
+	pub fn set_uniform_block_binding(&mut self, name: &str, binding: u32) {
+		let shader_id = self.bound_shader.expect("Tried to set uniform block binding without a bound shader");
+		let shader = self.shaders.get(shader_id.0).expect("Bound shader was destroyed");
+
+		if !super::shader::bind_uniform_block(shader.handle, name, binding) {
+			eprintln!("Warning: shader {} has no active uniform block '{}'", shader.handle, name);
+		}
+	}
+
+	/// Updates the camera block shared by all shaders. Should be called whenever the camera
+	/// being rendered from changes, and at least once per frame
+	pub fn set_camera(&mut self, camera: &Camera) {
+		self.update_uniform_buffer(self.camera_buffer, &camera.uniforms());
+	}
+
+
+	// Textures
+	pub fn new_texture(&mut self, image: &Image, settings: TextureSettings) -> TextureID {
+		let mut texture = Texture::new(settings);
+		texture.upload(image);
+
+		self.state.invalidate_textures();
+		self.stats.bytes_uploaded += image.data.len();
+
+		TextureID(self.textures.insert(texture))
+	}
+
+	pub fn destroy_texture(&mut self, id: TextureID) {
+		let texture = self.textures.remove(id.0).expect("Tried to destroy invalid or destroyed texture");
+		texture.destroy();
+		self.state.invalidate_textures();
+	}
+
+	pub fn new_texture_from_png(&mut self, path: impl AsRef<std::path::Path>, settings: TextureSettings) -> Result<TextureID, Box<dyn Error>> {
+		let image = Image::load_png(path)?;
+		Ok(self.new_texture(&image, settings))
+	}
+
+	pub fn update_texture(&mut self, id: TextureID, image: &Image) {
+		let texture = self.textures.get_mut(id.0).expect("Tried to update invalid or destroyed texture");
+		texture.upload(image);
+
+		self.state.invalidate_textures();
+		self.stats.bytes_uploaded += image.data.len();
+	}
+
+	pub fn texture_size(&self, id: TextureID) -> Vec2i {
+		self.textures.get(id.0).expect("Tried to query invalid or destroyed texture").size
+	}
+
+	pub fn set_uniform_texture(&mut self, name: &str, id: TextureID, slot: u32) {
+		let loc = self.get_uniform_location(name);
+		let texture = self.textures.get(id.0).expect("Tried to bind invalid or destroyed texture").handle;
+		self.bind_texture(slot, gl::TEXTURE_2D, texture);
+
+		unsafe {
+			gl::Uniform1i(loc, slot as _)
+		}
+	}
+
+
+	// Framebuffers
+	pub fn new_framebuffer(&mut self, settings: FramebufferSettings) -> FramebufferID {
+		let id = FramebufferID(self.framebuffers.insert(Framebuffer::new(&settings)));
+
+		// Framebuffer::new leaves the new framebuffer bound, and binds its attachments
+		self.state.framebuffer = None;
+		self.state.invalidate_textures();
+
+		let prev_bound = self.bound_framebuffer.take();
+		self.bind_framebuffer(prev_bound);
+
+		id
+	}
+
+	/// Destroys a framebuffer and all of its attachments.
+	/// If it is currently bound, rendering returns to the default framebuffer
+	pub fn destroy_framebuffer(&mut self, id: FramebufferID) {
+		let framebuffer = self.framebuffers.remove(id.0).expect("Tried to destroy invalid or destroyed framebuffer");
+		framebuffer.destroy();
+		self.state.invalidate_textures();
+
+		if self.bound_framebuffer == Some(id) {
+			self.bind_framebuffer(None);
+		}
+	}
+
+	/// Reallocates all attachments of a framebuffer if `size` differs from its current size.
+	/// Cheap enough to call every frame with the window size. Zero sizes, e.g., from a minimised
+	/// window, are ignored and the framebuffer keeps its previous attachments
+	pub fn resize_framebuffer(&mut self, id: FramebufferID, size: Vec2i) {
+		let framebuffer = self.framebuffers.get_mut(id.0).expect("Tried to resize invalid or destroyed framebuffer");
+		if framebuffer.size == size || size.x <= 0 || size.y <= 0 { return }
+
+		unsafe {
+			gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer.handle);
+		}
+
+		framebuffer.resize(size);
+
+		self.state.framebuffer = None;
+		self.state.invalidate_textures();
+
+		let prev_bound = self.bound_framebuffer.take();
+		self.bind_framebuffer(prev_bound);
+	}
+
+	pub fn framebuffer_size(&self, id: FramebufferID) -> Vec2i {
+		self.framebuffers.get(id.0).expect("Tried to query invalid or destroyed framebuffer").size
+	}
+
+	/// Binds a framebuffer as the render target and sets the viewport to cover it.
+	/// Binding `None` returns to the default framebuffer and its viewport
+	pub fn bind_framebuffer(&mut self, id: impl Into<Option<FramebufferID>>) {
+		let id = id.into();
+
+		let (handle, viewport) = match id {
+			Some(id) => {
+				let framebuffer = self.framebuffers.get(id.0).expect("Tried to bind invalid or destroyed framebuffer");
+				(framebuffer.handle, framebuffer.size)
+			}
+
+			None => (0, self.backbuffer_viewport),
+		};
+
+		self.bound_framebuffer = id;
+		self.apply_viewport(viewport);
+
+		if !self.stats.record_state_change(self.state.framebuffer != Some(handle)) { return }
+		self.state.framebuffer = Some(handle);
+
+		unsafe {
+			gl::BindFramebuffer(gl::FRAMEBUFFER, handle);
+		}
+	}
+
+	pub fn bound_framebuffer(&self) -> Option<FramebufferID> { self.bound_framebuffer }
+
+	/// Size of whatever is currently being rendered to - the bound framebuffer or the backbuffer
+	pub fn render_target_size(&self) -> Vec2i {
+		match self.bound_framebuffer {
+			Some(id) => self.framebuffer_size(id),
+			None => self.backbuffer_viewport,
+		}
+	}
+
+	/// Reads back the colour of the bound framebuffer (its first colour attachment),
+	/// or the backbuffer if none is bound
+	pub fn read_pixels(&mut self) -> Image {
+		let size = self.render_target_size();
+		let mut data = vec![0u8; size.x as usize * size.y as usize * 4];
+
+		unsafe {
+			gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+			gl::ReadPixels(0, 0, size.x, size.y, gl::RGBA, gl::UNSIGNED_BYTE, data.as_mut_ptr() as *mut _);
+		}
+
+		// GL reads bottom row first
+		let image = Image::new(size, data);
+		Image::new(size, image.flipped_rows())
+	}
+
+	/// Reads back raw depth values of the current render target, top row first.
+	/// Returns None if the bound framebuffer has no depth attachment
+	pub fn read_depth(&mut self) -> Option<Vec<f32>> {
+		if let Some(id) = self.bound_framebuffer {
+			let framebuffer = self.framebuffers.get(id.0).expect("Bound framebuffer was destroyed");
+			if !framebuffer.has_depth() { return None }
+		}
+
+		let size = self.render_target_size();
+		let mut data = vec![0.0f32; size.x as usize * size.y as usize];
+
+		unsafe {
+			gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+			gl::ReadPixels(0, 0, size.x, size.y, gl::DEPTH_COMPONENT, gl::FLOAT, data.as_mut_ptr() as *mut _);
+		}
+
+		Some(flip_rows(&data, size.x as usize))
+	}
+
+	/// Reads back stencil values of the current render target, top row first.
+	/// Returns None if the bound framebuffer has no stencil attachment
+	pub fn read_stencil(&mut self) -> Option<Vec<u8>> {
+		if let Some(id) = self.bound_framebuffer {
+			let framebuffer = self.framebuffers.get(id.0).expect("Bound framebuffer was destroyed");
+			if !framebuffer.has_stencil() { return None }
+		}
+
+		let size = self.render_target_size();
+		let mut data = vec![0u8; size.x as usize * size.y as usize];
+
+		unsafe {
+			gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+			gl::ReadPixels(0, 0, size.x, size.y, gl::STENCIL_INDEX, gl::UNSIGNED_BYTE, data.as_mut_ptr() as *mut _);
+		}
+
+		Some(flip_rows(&data, size.x as usize))
+	}
+
+	pub fn set_uniform_framebuffer_texture(&mut self, name: &str, id: FramebufferID, attachment: FramebufferAttachment, slot: u32) {
+		let loc = self.get_uniform_location(name);
+		let texture = self.framebuffers.get(id.0)
+			.expect("Tried to bind invalid or destroyed framebuffer")
+			.attachment_texture(attachment)
+			.expect("Tried to bind missing framebuffer attachment");
+
+		self.bind_texture(slot, gl::TEXTURE_2D, texture);
+
+		unsafe {
+			gl::Uniform1i(loc, slot as _)
+		}
 	}
 }
 
@@ -314,12 +983,20 @@ impl Core {
 
 
 
+fn flip_rows<T: Copy>(data: &[T], row_size: usize) -> Vec<T> {
+	data.chunks(row_size).rev().flatten().cloned().collect()
+}
+
+
 pub trait MeshIDLike {
 	// type Vertex: Vertex;
 	// type Mesh;
 
 	fn bind_mesh(&self, core: &mut Core);
 	fn draw_mesh(&self, core: &mut Core, draw_mode: u32);
+
+	/// Draws the elements in `range`, or vertices for meshes without indices
+	fn draw_mesh_range(&self, core: &mut Core, draw_mode: u32, range: Range<u32>);
 }
 
 
@@ -327,30 +1004,15 @@ impl<V: Vertex> MeshIDLike for MeshID<V> {
 	// type Vertex = V;
 
 	fn bind_mesh(&self, core: &mut Core) {
-		let untyped_id = UntypedMeshID::from(*self);
-		if core.bound_mesh == Some(untyped_id) {
-			return;
-		}
-
-		let mesh = core.meshes.get(self.0).expect("Tried to bind invalid mesh");
-		mesh.bind();
-		core.bound_mesh = Some(untyped_id);
+		UntypedMeshID::from(*self).bind_mesh(core);
 	}
 
 	fn draw_mesh(&self, core: &mut Core, draw_mode: u32) {
-		let mesh = core.meshes.get(self.0).expect("Tried to bind invalid mesh");
-		if mesh.element_count == 0 { return }
-
-		mesh.descriptor.bind();
+		UntypedMeshID::from(*self).draw_mesh(core, draw_mode);
+	}
 
-		unsafe {
-			gl::DrawElements(
-				draw_mode,
-				mesh.element_count as _,
-				gl::UNSIGNED_SHORT,
-				std::ptr::null()
-			);
-		}
+	fn draw_mesh_range(&self, core: &mut Core, draw_mode: u32, range: Range<u32>) {
+		UntypedMeshID::from(*self).draw_mesh_range(core, draw_mode, range);
 	}
 }
 
@@ -358,25 +1020,38 @@ impl<V: Vertex> MeshIDLike for BasicMeshID<V> {
 	// type Vertex = V;
 
 	fn bind_mesh(&self, core: &mut Core) {
-		let untyped_id = UntypedMeshID::from(*self);
-		if core.bound_mesh == Some(untyped_id) {
+		UntypedMeshID::from(*self).bind_mesh(core);
+	}
+
+	fn draw_mesh(&self, core: &mut Core, draw_mode: u32) {
+		UntypedMeshID::from(*self).draw_mesh(core, draw_mode);
+	}
+
+	fn draw_mesh_range(&self, core: &mut Core, draw_mode: u32, range: Range<u32>) {
+		UntypedMeshID::from(*self).draw_mesh_range(core, draw_mode, range);
+	}
+}
+
+impl MeshIDLike for UntypedMeshID {
+	fn bind_mesh(&self, core: &mut Core) {
+		if !core.stats.record_state_change(core.bound_mesh != Some(*self)) {
 			return;
 		}
 
-		let mesh = core.basic_meshes.get(self.0).expect("Tried to bind invalid mesh");
-		mesh.bind();
-		core.bound_mesh = Some(untyped_id);
+		match *self {
+			UntypedMeshID::Indexed(key) => core.meshes.get(key).expect("Tried to bind invalid or destroyed mesh").bind(),
+			UntypedMeshID::Basic(key) => core.basic_meshes.get(key).expect("Tried to bind invalid or destroyed mesh").bind(),
+		}
+
+		core.bound_mesh = Some(*self);
 	}
 
 	fn draw_mesh(&self, core: &mut Core, draw_mode: u32) {
-		let mesh = core.basic_meshes.get(self.0).expect("Tried to bind invalid mesh");
-		if mesh.vertex_count == 0 { return }
-		
-		mesh.descriptor.bind();
+		core.submit_draw(*self, draw_mode, None, None);
+	}
 
-		unsafe {
-			gl::DrawArrays(draw_mode, 0, mesh.vertex_count as _);
-		}
+	fn draw_mesh_range(&self, core: &mut Core, draw_mode: u32, range: Range<u32>) {
+		core.submit_draw(*self, draw_mode, Some(range), None);
 	}
 }
 
@@ -387,6 +1062,12 @@ impl<V: Vertex> MeshIDLike for BasicMeshID<V> {
 #[derive(Debug)]
 pub struct Capabilities {
 	pub texture_buffer_size: usize,
+
+	/// Timestamp queries are core in GL 3.3, and otherwise need ARB_timer_query
+	pub timer_queries: bool,
+
+	/// Per-instance attributes are core in GL 3.3, and otherwise need ARB_instanced_arrays
+	pub instancing: bool,
 }
 
 impl Capabilities {
@@ -397,12 +1078,38 @@ impl Capabilities {
 				gl::GetIntegerv(gl::MAX_TEXTURE_BUFFER_SIZE, &mut v);
 				v as usize
 			},
+
+			timer_queries: gl_version() >= (3, 3) || has_extension("GL_ARB_timer_query"),
+			instancing: gl_version() >= (3, 3) || has_extension("GL_ARB_instanced_arrays"),
 		}
 	}
 }
 
 
-#[derive(Debug, Clone, Copy)]
+fn gl_version() -> (i32, i32) {
+	let (mut major, mut minor) = (0, 0);
+	unsafe {
+		gl::GetIntegerv(gl::MAJOR_VERSION, &mut major);
+		gl::GetIntegerv(gl::MINOR_VERSION, &mut minor);
+	}
+
+	(major, minor)
+}
+
+fn has_extension(name: &str) -> bool {
+	let mut count = 0;
+	unsafe {
+		gl::GetIntegerv(gl::NUM_EXTENSIONS, &mut count);
+	}
+
+	(0..count as u32).any(|idx| unsafe {
+		let ext = gl::GetStringi(gl::EXTENSIONS, idx);
+		!ext.is_null() && std::ffi::CStr::from_ptr(ext as _).to_bytes() == name.as_bytes()
+	})
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct StencilParams {
 	pub write_condition: u32,
 	pub write_mask: bool,
@@ -437,4 +1144,75 @@ impl StencilParams {
 			pass_op: gl::KEEP,
 		}
 	}
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::gfx::test_context::HeadlessGfx;
+
+	fn current_program() -> u32 {
+		let mut program = 0;
+		unsafe { gl::GetIntegerv(gl::CURRENT_PROGRAM, &mut program) }
+		program as u32
+	}
+
+	fn is_enabled(cap: u32) -> bool {
+		unsafe { gl::IsEnabled(cap) != 0 }
+	}
+
+	#[test]
+	fn redundant_state_changes_are_skipped() {
+		let mut headless = match HeadlessGfx::new("redundant_state_changes_are_skipped") {
+			Some(headless) => headless,
+			None => return,
+		};
+
+		let core = &mut headless.gfx.core;
+
+		let shader = core.new_shader::<ColorVertex>(
+			crate::shader_source!("shaders/color_vert.glsl"),
+			crate::shader_source!("shaders/color_frag.glsl"),
+			&["a_vertex", "a_color"]
+		).unwrap();
+
+		core.use_shader(shader);
+		core.set_depth(None);
+		core.set_blend_mode(BlendMode::None);
+		core.take_frame_stats();
+
+		// Change GL state behind Core's back - if the repeated calls below reached GL,
+		// they would undo these
+		unsafe {
+			gl::UseProgram(0);
+			gl::Enable(gl::DEPTH_TEST);
+			gl::Enable(gl::BLEND);
+		}
+
+		core.use_shader(shader);
+		core.set_depth(None);
+		core.set_blend_mode(BlendMode::None);
+
+		let stats = core.take_frame_stats();
+		assert_eq!(stats.state_changes, 0);
+		assert_eq!(stats.redundant_state_changes, 3);
+
+		assert_eq!(current_program(), 0);
+		assert!(is_enabled(gl::DEPTH_TEST));
+		assert!(is_enabled(gl::BLEND));
+
+		// Once the cache is invalidated, the same calls are made again
+		core.invalidate_state_cache();
+		core.use_shader(shader);
+		core.set_depth(None);
+		core.set_blend_mode(BlendMode::None);
+
+		let stats = core.take_frame_stats();
+		assert_eq!(stats.state_changes, 3);
+		assert_eq!(stats.redundant_state_changes, 0);
+
+		assert_ne!(current_program(), 0);
+		assert!(!is_enabled(gl::DEPTH_TEST));
+		assert!(!is_enabled(gl::BLEND));
+	}
+}
@@ -1,63 +1,91 @@
 use crate::prelude::*;
 
-use super::core::Core;
+use super::core::{Core, BlendMode};
 use super::shader::ShaderID;
 use super::vertex::ColorVertex;
 use super::mesh::BasicMeshID;
-use super::camera::Camera;
+use super::render_queue::{RenderQueue, DrawItem, RenderPass, Primitive};
 
 
 pub struct Debug {
 	shader: ShaderID,
+	screen_shader: ShaderID,
 
 	points: Vec<ColorVertex>,
 	lines: Vec<ColorVertex>,
 
+	screen_triangles: Vec<ColorVertex>,
+	screen_lines: Vec<ColorVertex>,
+
 	points_mesh: BasicMeshID<ColorVertex>,
 	lines_mesh: BasicMeshID<ColorVertex>,
+
+	screen_triangles_mesh: BasicMeshID<ColorVertex>,
+	screen_lines_mesh: BasicMeshID<ColorVertex>,
 }
 
 impl Debug {
 	pub fn new(core: &mut Core) -> Debug {
-		let shader = core.new_shader(
-			include_str!("../shaders/color_vert.glsl"),
-			include_str!("../shaders/color_frag.glsl"),
+		let shader = core.new_shader::<ColorVertex>(
+			crate::shader_source!("shaders/color_vert.glsl"),
+			crate::shader_source!("shaders/color_frag.glsl"),
 			&["a_vertex", "a_color"]
-		);
+		).expect("Failed to create debug shader");
+
+		// Takes positions in normalised device coordinates
+		let screen_shader = core.new_shader_variant::<ColorVertex>(
+			crate::shader_source!("shaders/color_vert.glsl"),
+			crate::shader_source!("shaders/color_frag.glsl"),
+			&["a_vertex", "a_color"],
+			&[("SCREEN_SPACE", "1")]
+		).expect("Failed to create screen space debug shader");
 
 		let points_mesh = core.new_basic_mesh();
 		let lines_mesh = core.new_basic_mesh();
 
+		let screen_triangles_mesh = core.new_basic_mesh();
+		let screen_lines_mesh = core.new_basic_mesh();
+
 		Debug {
 			shader,
+			screen_shader,
 
 			points: Vec::new(),
 			lines: Vec::new(),
 
+			screen_triangles: Vec::new(),
+			screen_lines: Vec::new(),
+
 			points_mesh,
 			lines_mesh,
-		}
-	}
-
-	pub fn draw(&mut self, core: &mut Core, camera: &Camera) {
-		core.set_depth(None);
-
-		core.use_shader(self.shader);
-		core.set_uniform_mat4("u_proj_view", &camera.projection_view());
 
-		if !self.points.is_empty() {
-			core.update_basic_mesh(self.points_mesh, &self.points);
-			core.draw_mesh_points(self.points_mesh);
-			self.points.clear();
+			screen_triangles_mesh,
+			screen_lines_mesh,
 		}
+	}
 
-		if !self.lines.is_empty() {
-			core.update_basic_mesh(self.lines_mesh, &self.lines);
-			core.draw_mesh_lines(self.lines_mesh);
-			self.lines.clear();
+	/// Uploads everything drawn this frame and submits it to `queue` as overlay items,
+	/// drawn over everything else. World space points and lines are drawn before screen space shapes
+	pub fn submit(&mut self, core: &mut Core, queue: &mut RenderQueue) {
+		let batches = [
+			(&mut self.points, self.points_mesh, self.shader, Primitive::Points),
+			(&mut self.lines, self.lines_mesh, self.shader, Primitive::Lines),
+			(&mut self.screen_triangles, self.screen_triangles_mesh, self.screen_shader, Primitive::Triangles),
+			(&mut self.screen_lines, self.screen_lines_mesh, self.screen_shader, Primitive::Lines),
+		];
+
+		for (vertices, mesh, shader, primitive) in batches.iter_mut() {
+			if vertices.is_empty() { continue }
+
+			core.update_basic_mesh(*mesh, vertices);
+			vertices.clear();
+
+			queue.submit(DrawItem::new(*mesh, *shader)
+				.with_pass(RenderPass::Overlay)
+				.with_primitive(*primitive)
+				.with_blend_mode(BlendMode::Alpha)
+				.with_depth(None));
 		}
-
-		core.set_depth(super::core::DepthFunc::default());
 	}
 
 	pub fn point(&mut self, world: Vec3, color: Color) {
@@ -69,4 +97,26 @@ impl Debug {
 		self.lines.push(ColorVertex::new(start, color));
 		self.lines.push(ColorVertex::new(end, color));
 	}
+
+	/// `min` and `max` are in normalised device coordinates, and are drawn over everything else
+	pub fn screen_rect(&mut self, min: Vec2, max: Vec2, color: Color) {
+		let color = color.into();
+		let corners = [
+			Vec3::new(min.x, min.y, 0.0),
+			Vec3::new(max.x, min.y, 0.0),
+			Vec3::new(max.x, max.y, 0.0),
+			Vec3::new(min.x, max.y, 0.0),
+		];
+
+		for &idx in &[0, 1, 2, 0, 2, 3] {
+			self.screen_triangles.push(ColorVertex::new(corners[idx], color));
+		}
+	}
+
+	/// `start` and `end` are in normalised device coordinates, and are drawn over everything else
+	pub fn screen_line(&mut self, start: Vec2, end: Vec2, color: Color) {
+		let color = color.into();
+		self.screen_lines.push(ColorVertex::new(Vec3::new(start.x, start.y, 0.0), color));
+		self.screen_lines.push(ColorVertex::new(Vec3::new(end.x, end.y, 0.0), color));
+	}
 }
\ No newline at end of file
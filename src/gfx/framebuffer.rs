@@ -0,0 +1,198 @@
+use crate::prelude::*;
+use super::resource::ResourceKey;
+
+
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub struct FramebufferID(pub(super) ResourceKey);
+
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DepthStencilAttachment {
+	None,
+	Depth,
+	DepthStencil,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FramebufferAttachment {
+	Color(u32),
+	DepthStencil,
+}
+
+
+#[derive(Clone, Debug)]
+pub struct FramebufferSettings {
+	pub size: Vec2i,
+	pub color_attachments: u32,
+	pub depth_stencil: DepthStencilAttachment,
+}
+
+impl FramebufferSettings {
+	pub fn new(size: Vec2i) -> Self {
+		FramebufferSettings {
+			size,
+			color_attachments: 0,
+			depth_stencil: DepthStencilAttachment::None,
+		}
+	}
+
+	pub fn add_color(mut self) -> Self {
+		self.color_attachments += 1;
+		self
+	}
+
+	pub fn add_depth(mut self) -> Self {
+		self.depth_stencil = DepthStencilAttachment::Depth;
+		self
+	}
+
+	pub fn add_depth_stencil(mut self) -> Self {
+		self.depth_stencil = DepthStencilAttachment::DepthStencil;
+		self
+	}
+}
+
+
+
+pub(super) struct Framebuffer {
+	pub(super) handle: u32,
+	pub(super) size: Vec2i,
+
+	// All attachments are textures rather than renderbuffers so that they can be sampled
+	pub(super) color_textures: Vec<u32>,
+	pub(super) depth_stencil_texture: Option<u32>,
+	depth_stencil: DepthStencilAttachment,
+}
+
+impl Framebuffer {
+	pub(super) fn new(settings: &FramebufferSettings) -> Framebuffer {
+		let mut handle = 0;
+		let mut color_textures = vec![0; settings.color_attachments as usize];
+		let mut depth_stencil_texture = None;
+
+		unsafe {
+			gl::GenFramebuffers(1, &mut handle);
+			gl::BindFramebuffer(gl::FRAMEBUFFER, handle);
+
+			if !color_textures.is_empty() {
+				gl::GenTextures(color_textures.len() as _, color_textures.as_mut_ptr());
+			}
+
+			for (idx, &texture) in color_textures.iter().enumerate() {
+				init_attachment_texture(texture);
+				gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0 + idx as u32,
+					gl::TEXTURE_2D, texture, 0);
+			}
+
+			let draw_buffers = (0..color_textures.len() as u32)
+				.map(|idx| gl::COLOR_ATTACHMENT0 + idx)
+				.collect(): Vec<_>;
+
+			if draw_buffers.is_empty() {
+				gl::DrawBuffer(gl::NONE);
+				gl::ReadBuffer(gl::NONE);
+			} else {
+				gl::DrawBuffers(draw_buffers.len() as _, draw_buffers.as_ptr());
+			}
+
+			let attachment_point = match settings.depth_stencil {
+				DepthStencilAttachment::None => None,
+				DepthStencilAttachment::Depth => Some(gl::DEPTH_ATTACHMENT),
+				DepthStencilAttachment::DepthStencil => Some(gl::DEPTH_STENCIL_ATTACHMENT),
+			};
+
+			if let Some(attachment_point) = attachment_point {
+				let mut texture = 0;
+				gl::GenTextures(1, &mut texture);
+				init_attachment_texture(texture);
+				gl::FramebufferTexture2D(gl::FRAMEBUFFER, attachment_point, gl::TEXTURE_2D, texture, 0);
+				depth_stencil_texture = Some(texture);
+			}
+		}
+
+		let mut framebuffer = Framebuffer {
+			handle,
+			size: Vec2i::zero(),
+
+			color_textures,
+			depth_stencil_texture,
+			depth_stencil: settings.depth_stencil,
+		};
+
+		framebuffer.resize(settings.size);
+
+		unsafe {
+			let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+			assert!(status == gl::FRAMEBUFFER_COMPLETE, "Framebuffer incomplete: 0x{:x}", status);
+		}
+
+		framebuffer
+	}
+
+	/// Reallocates storage for all attachments. Expects the framebuffer to be bound
+	pub(super) fn resize(&mut self, size: Vec2i) {
+		if size == self.size { return }
+
+		assert!(size.x > 0 && size.y > 0, "Tried to resize framebuffer to zero size");
+		self.size = size;
+
+		unsafe {
+			for &texture in self.color_textures.iter() {
+				gl::BindTexture(gl::TEXTURE_2D, texture);
+				gl::TexImage2D(gl::TEXTURE_2D, 0, gl::RGBA8 as _, size.x, size.y, 0,
+					gl::RGBA, gl::UNSIGNED_BYTE, std::ptr::null());
+			}
+
+			if let Some(texture) = self.depth_stencil_texture {
+				let (internal_format, format, ty) = match self.depth_stencil {
+					DepthStencilAttachment::Depth => (gl::DEPTH_COMPONENT24, gl::DEPTH_COMPONENT, gl::UNSIGNED_INT),
+					_ => (gl::DEPTH24_STENCIL8, gl::DEPTH_STENCIL, gl::UNSIGNED_INT_24_8),
+				};
+
+				gl::BindTexture(gl::TEXTURE_2D, texture);
+				gl::TexImage2D(gl::TEXTURE_2D, 0, internal_format as _, size.x, size.y, 0,
+					format, ty, std::ptr::null());
+			}
+
+			gl::BindTexture(gl::TEXTURE_2D, 0);
+		}
+	}
+
+	pub(super) fn destroy(self) {
+		unsafe {
+			gl::DeleteFramebuffers(1, &self.handle);
+
+			if !self.color_textures.is_empty() {
+				gl::DeleteTextures(self.color_textures.len() as _, self.color_textures.as_ptr());
+			}
+
+			if let Some(texture) = self.depth_stencil_texture {
+				gl::DeleteTextures(1, &texture);
+			}
+		}
+	}
+
+	pub(super) fn has_depth(&self) -> bool {
+		self.depth_stencil != DepthStencilAttachment::None
+	}
+
+	pub(super) fn has_stencil(&self) -> bool {
+		self.depth_stencil == DepthStencilAttachment::DepthStencil
+	}
+
+	pub(super) fn attachment_texture(&self, attachment: FramebufferAttachment) -> Option<u32> {
+		match attachment {
+			FramebufferAttachment::Color(idx) => self.color_textures.get(idx as usize).cloned(),
+			FramebufferAttachment::DepthStencil => self.depth_stencil_texture,
+		}
+	}
+}
+
+
+unsafe fn init_attachment_texture(texture: u32) {
+	gl::BindTexture(gl::TEXTURE_2D, texture);
+	gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as _);
+	gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as _);
+	gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as _);
+	gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as _);
+}
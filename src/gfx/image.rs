@@ -0,0 +1,107 @@
+use crate::prelude::*;
+use std::path::Path;
+
+
+/// 8 bit RGBA pixel data, stored top row first
+#[derive(Clone, Debug)]
+pub struct Image {
+	pub size: Vec2i,
+	pub data: Vec<u8>,
+}
+
+impl Image {
+	pub fn new(size: Vec2i, data: Vec<u8>) -> Image {
+		assert!(data.len() == size.x as usize * size.y as usize * 4, "Image data doesn't match size");
+		Image { size, data }
+	}
+
+	pub fn load_png(path: impl AsRef<Path>) -> Result<Image, Box<dyn Error>> {
+		let file = std::fs::File::open(path)?;
+		Image::from_png_reader(std::io::BufReader::new(file))
+	}
+
+	pub fn from_png_bytes(bytes: &[u8]) -> Result<Image, Box<dyn Error>> {
+		Image::from_png_reader(bytes)
+	}
+
+	fn from_png_reader(reader: impl std::io::Read) -> Result<Image, Box<dyn Error>> {
+		let mut decoder = png::Decoder::new(reader);
+
+		// Expand palettes and low bit depths to 8 bits per channel
+		decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+
+		let (info, mut reader) = decoder.read_info()?;
+		let mut buffer = vec![0; info.buffer_size()];
+		reader.next_frame(&mut buffer)?;
+
+		let data = match info.color_type {
+			png::ColorType::RGBA => buffer,
+			png::ColorType::RGB => buffer.chunks(3)
+				.flat_map(|px| vec![px[0], px[1], px[2], 255])
+				.collect(),
+			png::ColorType::GrayscaleAlpha => buffer.chunks(2)
+				.flat_map(|px| vec![px[0], px[0], px[0], px[1]])
+				.collect(),
+			png::ColorType::Grayscale => buffer.iter()
+				.flat_map(|&v| vec![v, v, v, 255])
+				.collect(),
+			png::ColorType::Indexed => return Err("Indexed png wasn't expanded".into()),
+		};
+
+		Ok(Image::new(Vec2i::new(info.width as i32, info.height as i32), data))
+	}
+
+	pub fn save_png(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
+		let file = std::fs::File::create(path)?;
+		let mut encoder = png::Encoder::new(std::io::BufWriter::new(file), self.size.x as u32, self.size.y as u32);
+		encoder.set_color(png::ColorType::RGBA);
+		encoder.set_depth(png::BitDepth::Eight);
+
+		let mut writer = encoder.write_header()?;
+		writer.write_image_data(&self.data)?;
+		Ok(())
+	}
+
+	/// Compares against another image, e.g., a golden image in a rendering test.
+	/// Channels that differ by no more than `tolerance` are considered equal, to allow for
+	/// differences in rasterisation between drivers
+	pub fn diff(&self, other: &Image, tolerance: u8) -> Result<ImageDiff, String> {
+		if self.size != other.size {
+			return Err(format!("Image sizes differ: {:?} vs {:?}", self.size, other.size))
+		}
+
+		let mut diff = ImageDiff { differing_pixels: 0, max_channel_difference: 0 };
+
+		for (a, b) in self.data.chunks(4).zip(other.data.chunks(4)) {
+			let pixel_difference = a.iter().zip(b)
+				.map(|(&a, &b)| (a as i32 - b as i32).abs() as u8)
+				.max()
+				.unwrap_or(0);
+
+			diff.max_channel_difference = diff.max_channel_difference.max(pixel_difference);
+			if pixel_difference > tolerance {
+				diff.differing_pixels += 1;
+			}
+		}
+
+		Ok(diff)
+	}
+
+	/// Rows in bottom to top order, as GL expects for uploads and produces from readback
+	pub(super) fn flipped_rows(&self) -> Vec<u8> {
+		let row_size = self.size.x as usize * 4;
+		self.data.chunks(row_size).rev().flatten().cloned().collect()
+	}
+}
+
+
+#[derive(Copy, Clone, Debug)]
+pub struct ImageDiff {
+	/// Pixels with any channel differing by more than the tolerance
+	pub differing_pixels: usize,
+	pub max_channel_difference: u8,
+}
+
+impl ImageDiff {
+	pub fn is_match(&self) -> bool { self.differing_pixels == 0 }
+}
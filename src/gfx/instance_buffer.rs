@@ -0,0 +1,105 @@
+use crate::prelude::*;
+use std::marker::PhantomData;
+use super::resource::ResourceKey;
+use super::vertex::{Vertex, Descriptor};
+
+
+/// Per-instance attributes for `Core::draw_mesh_instanced`. `I` describes its attributes like a
+/// vertex type does, with positions counted from zero - they are placed after the mesh's own attributes
+#[derive(Copy, Clone, Debug)]
+pub struct InstanceBufferID<I: Vertex>(pub(super) ResourceKey, pub(super) PhantomData<*const I>);
+
+
+// Manual implementations required because of PhantomData
+// see: https://github.com/rust-lang/rust/issues/26925
+impl<I: Vertex> std::hash::Hash for InstanceBufferID<I> {
+	#[inline]
+	fn hash<H: std::hash::Hasher>(&self, h: &mut H) { self.0.hash(h) }
+}
+
+impl<I: Vertex> std::cmp::PartialEq for InstanceBufferID<I> {
+    fn eq(&self, o: &InstanceBufferID<I>) -> bool { self.0.eq(&o.0) }
+}
+
+impl<I: Vertex> std::cmp::Eq for InstanceBufferID<I> {}
+
+
+/// An instance buffer of any instance type, e.g., for storing in a `DrawItem`
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct UntypedInstanceBufferID(pub(super) ResourceKey);
+
+impl<I: Vertex> From<InstanceBufferID<I>> for UntypedInstanceBufferID {
+	fn from(InstanceBufferID(key, _): InstanceBufferID<I>) -> UntypedInstanceBufferID {
+		UntypedInstanceBufferID(key)
+	}
+}
+
+
+
+pub(super) struct InstanceBuffer {
+	pub(super) handle: u32,
+	pub(super) instance_count: u32,
+
+	// Kept so that untyped instance buffers can still be attached to meshes
+	pub(super) descriptor: Descriptor,
+	pub(super) stride: usize,
+}
+
+impl InstanceBuffer {
+	pub(super) fn new<I: Vertex>() -> InstanceBuffer {
+		let mut handle = 0;
+
+		unsafe {
+			gl::GenBuffers(1, &mut handle);
+		}
+
+		InstanceBuffer {
+			handle,
+			instance_count: 0,
+
+			descriptor: I::descriptor(),
+			stride: std::mem::size_of::<I>(),
+		}
+	}
+
+	pub(super) fn destroy(self) {
+		unsafe {
+			gl::DeleteBuffers(1, &self.handle);
+		}
+	}
+}
+
+
+
+/// An object transform and colour per instance. Shaders declare the matching attributes
+/// with `#include "instance.glsl"`
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct TransformInstance {
+	/// The first three rows of the transform
+	pub rows: [Vec4; 3],
+	pub color: Vec4,
+}
+
+impl TransformInstance {
+	pub fn new(transform: &Mat4, color: Color) -> Self {
+		let mut rows = [Vec4::zero(); 3];
+		rows.copy_from_slice(&transform.rows[..3]);
+
+		TransformInstance { rows, color: color.into() }
+	}
+}
+
+impl Vertex for TransformInstance {
+	fn descriptor() -> Descriptor {
+		Descriptor::from(&[4, 4, 4, 4])
+	}
+}
+
+/// Attribute names for `TransformInstance`, to append to a shader's vertex attribute names
+pub const TRANSFORM_INSTANCE_ATTRIBUTES: [&str; 4] = [
+	"a_instance_row_0",
+	"a_instance_row_1",
+	"a_instance_row_2",
+	"a_instance_color",
+];
@@ -1,18 +1,20 @@
 // use crate::prelude::*;
 use super::vertex::*;
+use super::resource::ResourceKey;
 use std::marker::PhantomData;
 
 
 #[derive(Copy, Clone, Debug)]
-pub struct MeshID<V: Vertex>(pub(super) usize, pub(super) PhantomData<*const V>);
+pub struct MeshID<V: Vertex>(pub(super) ResourceKey, pub(super) PhantomData<*const V>);
 
 #[derive(Copy, Clone, Debug)]
-pub struct BasicMeshID<V: Vertex>(pub(super) usize, pub(super) PhantomData<*const V>);
+pub struct BasicMeshID<V: Vertex>(pub(super) ResourceKey, pub(super) PhantomData<*const V>);
 
-#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
-pub(super) enum UntypedMeshID {
-	Indexed(usize),
-	Basic(usize),
+/// A mesh of any vertex type, e.g., for storing meshes of different types together
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub enum UntypedMeshID {
+	Indexed(ResourceKey),
+	Basic(ResourceKey),
 }
 
 impl<V: Vertex> From<MeshID<V>> for UntypedMeshID {
@@ -57,61 +59,115 @@ impl<V: Vertex> std::cmp::Eq for BasicMeshID<V> {}
 
 
 pub(super) struct Mesh {
-	pub(super) descriptor: Descriptor,
 	pub(super) element_count: u32,
+	/// Either UNSIGNED_SHORT or UNSIGNED_INT, depending on which update_mesh variant was used last
+	pub(super) index_type: u32,
+	pub(super) vao: u32,
 	pub(super) vbo: u32,
-	pub(super) ebo: u32
+	pub(super) ebo: u32,
+
+	/// Number of attribute locations used by the vertex type. Instance attributes start here
+	pub(super) attribute_count: u32,
+
+	/// The instance buffer the vertex array's instance attributes currently point at
+	pub(super) instance_buffer: Option<ResourceKey>,
 }
 
 impl Mesh {
-	pub(super) fn new(descriptor: Descriptor) -> Mesh {
+	/// Creates a mesh with its vertex array configured for `V`. Leaves the new mesh bound
+	pub(super) fn new<V: Vertex>() -> Mesh {
 		unsafe {
+			let mut vao = 0;
+			gl::GenVertexArrays(1, &mut vao);
+
 			let mut buffers = [0; 2];
 			gl::GenBuffers(2, buffers.as_mut_ptr());
 
 			let [vbo, ebo] = buffers;
-			Mesh {
-				descriptor,
+			let descriptor = V::descriptor();
+
+			let mesh = Mesh {
 				element_count: 0,
-				vbo, ebo
-			}
+				index_type: gl::UNSIGNED_SHORT,
+				vao, vbo, ebo,
+				attribute_count: descriptor.location_count(),
+				instance_buffer: None,
+			};
+
+			// The element buffer binding is part of vertex array state, so only needs binding once
+			mesh.bind();
+			descriptor.configure(std::mem::size_of::<V>());
+			mesh
 		}
 	}
 
 	pub(super) fn bind(&self) {
 		unsafe {
+			gl::BindVertexArray(self.vao);
 			gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
 			gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, self.ebo);
 		}
 	}
+
+	pub(super) fn destroy(self) {
+		unsafe {
+			gl::DeleteVertexArrays(1, &self.vao);
+
+			let buffers = [self.vbo, self.ebo];
+			gl::DeleteBuffers(2, buffers.as_ptr());
+		}
+	}
 }
 
 
 
 
 pub(super) struct BasicMesh {
-	pub(super) descriptor: Descriptor,
 	pub(super) vertex_count: u32,
+	pub(super) vao: u32,
 	pub(super) vbo: u32,
+
+	/// See `Mesh`
+	pub(super) attribute_count: u32,
+	pub(super) instance_buffer: Option<ResourceKey>,
 }
 
 impl BasicMesh {
-	pub(super) fn new(descriptor: Descriptor) -> BasicMesh {
+	/// Creates a mesh with its vertex array configured for `V`. Leaves the new mesh bound
+	pub(super) fn new<V: Vertex>() -> BasicMesh {
 		unsafe {
+			let mut vao = 0;
+			gl::GenVertexArrays(1, &mut vao);
+
 			let mut vbo = 0;
 			gl::GenBuffers(1, &mut vbo);
 
-			BasicMesh {
-				descriptor,
+			let descriptor = V::descriptor();
+
+			let mesh = BasicMesh {
 				vertex_count: 0,
-				vbo
-			}
+				vao, vbo,
+				attribute_count: descriptor.location_count(),
+				instance_buffer: None,
+			};
+
+			mesh.bind();
+			descriptor.configure(std::mem::size_of::<V>());
+			mesh
 		}
 	}
 
 	pub(super) fn bind(&self) {
 		unsafe {
+			gl::BindVertexArray(self.vao);
 			gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
 		}
 	}
+
+	pub(super) fn destroy(self) {
+		unsafe {
+			gl::DeleteVertexArrays(1, &self.vao);
+			gl::DeleteBuffers(1, &self.vbo);
+		}
+	}
 }
@@ -4,7 +4,7 @@ use super::mesh::MeshID;
 pub struct MeshBuilder<V: Vertex> {
 	pub mesh_id: MeshID<V>,
 	pub vs: Vec<V>,
-	pub es: Vec<u16>,
+	pub es: Vec<u32>,
 }
 
 impl<V: Vertex> MeshBuilder<V> {
@@ -21,22 +21,25 @@ impl<V: Vertex> MeshBuilder<V> {
 		self.es.clear();
 	}
 
+	/// Uploads to the mesh, using 16b indices where possible and falling back to 32b indices
+	/// for meshes with too many vertices to address with 16b
 	pub fn commit(&self, core: &mut super::core::Core) {
-		core.update_mesh(self.mesh_id, &self.vs, &self.es);
+		if !needs_32b_indices(self.vs.len()) {
+			let es = self.es.iter().map(|&e| e as u16).collect(): Vec<_>;
+			core.update_mesh(self.mesh_id, &self.vs, &es);
+		} else {
+			core.update_mesh_u32(self.mesh_id, &self.vs, &self.es);
+		}
 	}
 
 	pub fn add_geometry<I, Item>(&mut self, verts: &[V], indices: I) where I: IntoIterator<Item=Item>, Item: IntoIndex {
-		let start = self.vs.len();
-		if start >= 0xffff {
-			panic!("Too many verts!");
-		}
-
+		let start = self.vs.len() as u32;
 		self.vs.extend_from_slice(verts);
-		self.es.extend(indices.into_iter().map(|i| i.into_index() + start as u16));
+		self.es.extend(indices.into_iter().map(|i| i.into_index() + start));
 	}
 
 	pub fn add_quad(&mut self, verts: &[V]) {
-		self.add_geometry(verts, &[0, 1, 2, 0, 2, 3]);
+		self.add_geometry(verts, &[0u32, 1, 2, 0, 2, 3]);
 	}
 
 	pub fn add_tri_fan(&mut self, vs: &[V]) {
@@ -44,7 +47,7 @@ impl<V: Vertex> MeshBuilder<V> {
 
 		let indices = (1..vs.len()-1)
 			.flat_map(|i| {
-				let i = i as u16;
+				let i = i as u32;
 				let is = [0, i, i+1];
 				(0..3).map(move |i| is[i])
 			});
@@ -56,22 +59,51 @@ impl<V: Vertex> MeshBuilder<V> {
 		assert!(vs.len() >= 3);
 
 		let indices = (0..vs.len()-2)
-			.flat_map(|i| (0..3).map(move |offset| i as u16 + offset));
+			.flat_map(|i| (0..3).map(move |offset| i as u32 + offset));
 
 		self.add_geometry(vs, indices);
 	}
 }
 
 
+/// Whether a mesh with this many vertices has indices that don't fit in 16b
+fn needs_32b_indices(vertex_count: usize) -> bool {
+	vertex_count > 0x10000
+}
+
+
 
 pub trait IntoIndex {
-	fn into_index(self) -> u16;
+	fn into_index(self) -> u32;
 }
 
 impl IntoIndex for u16 {
-	fn into_index(self) -> u16 { self }
+	fn into_index(self) -> u32 { self as u32 }
 }
 
 impl<'a> IntoIndex for &'a u16 {
-	fn into_index(self) -> u16 { *self }
-}
\ No newline at end of file
+	fn into_index(self) -> u32 { *self as u32 }
+}
+
+impl IntoIndex for u32 {
+	fn into_index(self) -> u32 { self }
+}
+
+impl<'a> IntoIndex for &'a u32 {
+	fn into_index(self) -> u32 { *self }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn index_width_boundary() {
+		// The last vertex of a 0x10000 vertex mesh has index 0xffff, which still fits
+		assert!(!needs_32b_indices(0xffff));
+		assert!(!needs_32b_indices(0x10000));
+		assert!(needs_32b_indices(0x10001));
+	}
+}
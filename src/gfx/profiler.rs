@@ -0,0 +1,402 @@
+use crate::prelude::*;
+use super::debug::Debug;
+
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// How many resolved frames are kept for the overlay and trace dumps
+pub const HISTORY_LENGTH: usize = 120;
+
+pub const TRACE_DIR: &str = "profiles";
+
+/// Timer query results usually arrive a couple of frames late. Past this many frames in
+/// flight, the oldest is read back even if it means stalling
+const MAX_PENDING_FRAMES: usize = 4;
+
+
+/// A span of time in milliseconds, relative to the start of the frame it was recorded in
+#[derive(Copy, Clone, Debug)]
+pub struct TimeSpan {
+	pub start: f32,
+	pub duration: f32,
+}
+
+#[derive(Clone, Debug)]
+pub struct ScopeTiming {
+	pub name: &'static str,
+
+	/// How many scopes this one is nested in
+	pub depth: u32,
+
+	pub cpu: TimeSpan,
+
+	/// Measured from the GPU timestamp taken at the start of the frame
+	pub gpu: Option<TimeSpan>,
+}
+
+#[derive(Clone, Debug)]
+pub struct FrameProfile {
+	pub index: u64,
+
+	/// When the frame began, relative to when the profiler was created
+	pub start: Duration,
+
+	/// In milliseconds
+	pub cpu_duration: f32,
+	pub gpu_duration: Option<f32>,
+
+	pub scopes: Vec<ScopeTiming>,
+}
+
+
+struct PendingFrame {
+	profile: FrameProfile,
+	start_query: u32,
+	end_query: u32,
+
+	/// Start and end timestamp queries, parallel to `profile.scopes`
+	scope_queries: Vec<(u32, u32)>,
+}
+
+
+/// Times nested scopes on both the CPU and GPU. GPU times come from timestamp queries, which
+/// are resolved a few frames later, so only frames that have made it into `history` are complete.
+/// Without timer query support, only CPU times are recorded
+pub struct Profiler {
+	epoch: Instant,
+	frame_start: Instant,
+	frame_index: u64,
+
+	current: Option<PendingFrame>,
+	scope_stack: Vec<usize>,
+
+	pending: VecDeque<PendingFrame>,
+	history: VecDeque<FrameProfile>,
+
+	free_queries: Vec<u32>,
+	timer_queries: bool,
+
+	overlay_enabled: bool,
+}
+
+impl Profiler {
+	pub(super) fn new(timer_queries: bool) -> Profiler {
+		let now = Instant::now();
+
+		Profiler {
+			epoch: now,
+			frame_start: now,
+			frame_index: 0,
+
+			current: None,
+			scope_stack: Vec::new(),
+
+			pending: VecDeque::new(),
+			history: VecDeque::with_capacity(HISTORY_LENGTH),
+
+			free_queries: Vec::new(),
+			timer_queries,
+
+			overlay_enabled: false,
+		}
+	}
+
+	pub fn begin_frame(&mut self) {
+		assert!(self.current.is_none(), "Profiler frame begun before the previous one ended");
+
+		let start_query = self.timestamp();
+		self.frame_start = Instant::now();
+
+		self.current = Some(PendingFrame {
+			profile: FrameProfile {
+				index: self.frame_index,
+				start: self.frame_start - self.epoch,
+				cpu_duration: 0.0,
+				gpu_duration: None,
+				scopes: Vec::new(),
+			},
+
+			start_query,
+			end_query: 0,
+			scope_queries: Vec::new(),
+		});
+
+		self.frame_index += 1;
+	}
+
+	pub fn end_frame(&mut self) {
+		if let Some(&open_scope) = self.scope_stack.last() {
+			let frame = self.current.as_ref().unwrap();
+			panic!("Profiler frame ended with scope '{}' still open", frame.profile.scopes[open_scope].name);
+		}
+
+		let end_query = self.timestamp();
+
+		let mut frame = self.current.take().expect("Profiler frame ended without being begun");
+		frame.end_query = end_query;
+		frame.profile.cpu_duration = ms_since(self.frame_start);
+
+		self.pending.push_back(frame);
+		self.resolve_pending_frames();
+	}
+
+	pub fn begin_scope(&mut self, name: &'static str) {
+		let start_query = self.timestamp();
+
+		let frame = self.current.as_mut().expect("Profiler scope begun outside of a frame");
+		frame.profile.scopes.push(ScopeTiming {
+			name,
+			depth: self.scope_stack.len() as u32,
+			cpu: TimeSpan { start: ms_since(self.frame_start), duration: 0.0 },
+			gpu: None,
+		});
+
+		frame.scope_queries.push((start_query, 0));
+		self.scope_stack.push(frame.profile.scopes.len() - 1);
+	}
+
+	pub fn end_scope(&mut self) {
+		let end_query = self.timestamp();
+
+		let scope_idx = self.scope_stack.pop().expect("Profiler scope ended without being begun");
+		let frame = self.current.as_mut().expect("Profiler scope ended outside of a frame");
+
+		let scope = &mut frame.profile.scopes[scope_idx];
+		scope.cpu.duration = ms_since(self.frame_start) - scope.cpu.start;
+		frame.scope_queries[scope_idx].1 = end_query;
+	}
+
+	pub fn overlay_enabled(&self) -> bool { self.overlay_enabled }
+
+	pub fn toggle_overlay(&mut self) {
+		self.overlay_enabled = !self.overlay_enabled;
+		println!("profiler overlay {}", if self.overlay_enabled { "on" } else { "off" });
+
+		if self.overlay_enabled {
+			self.print_summary();
+		}
+	}
+
+	/// Prints the average time spent in each scope over the history, along with the colour
+	/// it is drawn with in the overlay
+	pub fn print_summary(&self) {
+		let mut totals: Vec<(&'static str, u32, f32, f32)> = Vec::new();
+
+		for scope in self.history.iter().flat_map(|frame| &frame.scopes) {
+			let gpu = scope.gpu.map_or(0.0, |span| span.duration);
+
+			match totals.iter_mut().find(|(name, depth, ..)| *name == scope.name && *depth == scope.depth) {
+				Some(total) => { total.2 += scope.cpu.duration; total.3 += gpu; }
+				None => totals.push((scope.name, scope.depth, scope.cpu.duration, gpu)),
+			}
+		}
+
+		let frame_count = self.history.len().max(1) as f32;
+
+		println!("{:<24} {:>8} {:>8}  colour", "scope", "cpu ms", "gpu ms");
+		for (name, depth, cpu, gpu) in totals {
+			let indented_name = format!("{}{}", "  ".repeat(depth as usize), name);
+			let color = scope_color(name).to_tuple();
+
+			println!("{:<24} {:>8.3} {:>8.3}  ({:.1}, {:.1}, {:.1})", indented_name,
+				cpu / frame_count, gpu / frame_count, color.0, color.1, color.2);
+		}
+	}
+
+	/// Draws the GPU time of each top level scope as a stacked bar per frame, with the CPU frame
+	/// time as a line over the top. Scopes without GPU timings, e.g., without timer query support,
+	/// are drawn with their CPU time instead. The guide lines mark 60 and 30 fps
+	pub fn draw_overlay(&self, debug: &mut Debug) {
+		if !self.overlay_enabled {
+			return
+		}
+
+		let origin = Vec2::new(-0.95, -0.95);
+		let size = Vec2::new(1.0, 0.5);
+		let max_ms = 40.0;
+
+		let bar_width = size.x / HISTORY_LENGTH as f32;
+		let ms_to_height = size.y / max_ms;
+
+		debug.screen_rect(origin, origin + size, Color::rgba(0.0, 0.0, 0.0, 0.6));
+
+		for &(ms, color) in &[(1000.0 / 60.0, Color::rgb(0.3, 1.0, 0.3)), (1000.0 / 30.0, Color::rgb(1.0, 0.3, 0.3))] {
+			let y = origin.y + ms * ms_to_height;
+			debug.screen_line(Vec2::new(origin.x, y), Vec2::new(origin.x + size.x, y), color);
+		}
+
+		let mut prev_cpu_point = None;
+
+		for (frame_idx, frame) in self.history.iter().enumerate() {
+			let x = origin.x + frame_idx as f32 * bar_width;
+			let mut y = origin.y;
+
+			for scope in frame.scopes.iter().filter(|scope| scope.depth == 0) {
+				let duration = scope.gpu.map_or(scope.cpu.duration, |span| span.duration);
+				let height = (duration * ms_to_height).min(origin.y + size.y - y);
+
+				debug.screen_rect(Vec2::new(x, y), Vec2::new(x + bar_width, y + height), scope_color(scope.name));
+				y += height;
+			}
+
+			let cpu_point = Vec2::new(x + bar_width / 2.0, origin.y + (frame.cpu_duration * ms_to_height).min(size.y));
+			if let Some(prev_cpu_point) = prev_cpu_point {
+				debug.screen_line(prev_cpu_point, cpu_point, Color::rgb(1.0, 1.0, 1.0));
+			}
+
+			prev_cpu_point = Some(cpu_point);
+		}
+	}
+
+	/// Writes the history as a Chrome trace (chrome://tracing, or https://ui.perfetto.dev),
+	/// with CPU and GPU scopes on separate tracks
+	pub fn save_trace(&self) -> Result<PathBuf, Box<dyn Error>> {
+		let timestamp = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?;
+		let path = PathBuf::from(TRACE_DIR).join(format!("trace-{}.json", timestamp.as_secs()));
+
+		std::fs::create_dir_all(TRACE_DIR)?;
+		std::fs::write(&path, self.trace_json())?;
+
+		Ok(path)
+	}
+
+	fn trace_json(&self) -> String {
+		const CPU_TRACK: u32 = 1;
+		const GPU_TRACK: u32 = 2;
+
+		let mut events = vec![
+			r#"{"name":"thread_name","ph":"M","pid":1,"tid":1,"args":{"name":"CPU"}}"#.to_owned(),
+			r#"{"name":"thread_name","ph":"M","pid":1,"tid":2,"args":{"name":"GPU"}}"#.to_owned(),
+		];
+
+		let mut push_event = |name: &str, track: u32, frame_start_us: f64, start_ms: f32, duration_ms: f32| {
+			events.push(format!(r#"{{"name":"{}","ph":"X","pid":1,"tid":{},"ts":{:.3},"dur":{:.3}}}"#,
+				name.escape_default(), track, frame_start_us + start_ms as f64 * 1000.0, duration_ms as f64 * 1000.0));
+		};
+
+		for frame in self.history.iter() {
+			let frame_start_us = frame.start.as_secs_f64() * 1.0e6;
+			let frame_name = format!("frame {}", frame.index);
+
+			push_event(&frame_name, CPU_TRACK, frame_start_us, 0.0, frame.cpu_duration);
+
+			// GPU clocks aren't synchronised with the CPU, so GPU frames are aligned to the CPU frame start
+			if let Some(gpu_duration) = frame.gpu_duration {
+				push_event(&frame_name, GPU_TRACK, frame_start_us, 0.0, gpu_duration);
+			}
+
+			for scope in frame.scopes.iter() {
+				push_event(scope.name, CPU_TRACK, frame_start_us, scope.cpu.start, scope.cpu.duration);
+
+				if let Some(gpu) = scope.gpu {
+					push_event(scope.name, GPU_TRACK, frame_start_us, gpu.start, gpu.duration);
+				}
+			}
+		}
+
+		format!("{{\"traceEvents\":[\n{}\n],\"displayTimeUnit\":\"ms\"}}\n", events.join(",\n"))
+	}
+
+	/// Returns 0, which is never a valid query name, if timer queries aren't supported
+	fn timestamp(&mut self) -> u32 {
+		if !self.timer_queries {
+			return 0
+		}
+
+		let query = self.free_queries.pop()
+			.unwrap_or_else(|| unsafe {
+				let mut query = 0;
+				gl::GenQueries(1, &mut query);
+				query
+			});
+
+		unsafe {
+			gl::QueryCounter(query, gl::TIMESTAMP);
+		}
+
+		query
+	}
+
+	fn resolve_pending_frames(&mut self) {
+		while let Some(frame) = self.pending.front() {
+			// Queries complete in order, so if the last one is ready, the rest of the frame is too
+			if self.timer_queries && self.pending.len() <= MAX_PENDING_FRAMES && !query_available(frame.end_query) {
+				break
+			}
+
+			let mut frame = self.pending.pop_front().unwrap();
+
+			if self.timer_queries {
+				self.resolve_gpu_timings(&mut frame);
+			}
+
+			if self.history.len() >= HISTORY_LENGTH {
+				self.history.pop_front();
+			}
+
+			self.history.push_back(frame.profile);
+		}
+	}
+
+	fn resolve_gpu_timings(&mut self, frame: &mut PendingFrame) {
+		let gpu_start = query_result(frame.start_query);
+		let to_ms = |query| query_result(query).saturating_sub(gpu_start) as f32 / 1.0e6;
+
+		frame.profile.gpu_duration = Some(to_ms(frame.end_query));
+
+		for (scope, &(start_query, end_query)) in frame.profile.scopes.iter_mut().zip(&frame.scope_queries) {
+			let start = to_ms(start_query);
+			scope.gpu = Some(TimeSpan { start, duration: to_ms(end_query) - start });
+		}
+
+		self.free_queries.push(frame.start_query);
+		self.free_queries.push(frame.end_query);
+		for &(start_query, end_query) in frame.scope_queries.iter() {
+			self.free_queries.push(start_query);
+			self.free_queries.push(end_query);
+		}
+	}
+}
+
+
+fn ms_since(start: Instant) -> f32 {
+	start.elapsed().as_secs_f32() * 1000.0
+}
+
+fn query_available(query: u32) -> bool {
+	let mut available = 0;
+	unsafe {
+		gl::GetQueryObjectiv(query, gl::QUERY_RESULT_AVAILABLE, &mut available);
+	}
+
+	available != 0
+}
+
+/// Blocks until the query result is available. In nanoseconds for timestamps
+fn query_result(query: u32) -> u64 {
+	let mut result = 0;
+	unsafe {
+		gl::GetQueryObjectui64v(query, gl::QUERY_RESULT, &mut result);
+	}
+
+	result
+}
+
+/// A stable colour per scope name, so scopes can be told apart across frames
+fn scope_color(name: &str) -> Color {
+	let palette = [
+		Color::rgb(0.9, 0.4, 0.3),
+		Color::rgb(0.3, 0.7, 0.9),
+		Color::rgb(0.9, 0.8, 0.3),
+		Color::rgb(0.6, 0.4, 0.9),
+		Color::rgb(0.4, 0.9, 0.5),
+		Color::rgb(0.9, 0.5, 0.8),
+		Color::rgb(0.5, 0.9, 0.9),
+		Color::rgb(0.9, 0.6, 0.2),
+	];
+
+	// FNV-1a
+	let hash = name.bytes().fold(0x811c9dc5u32, |hash, byte| (hash ^ byte as u32).wrapping_mul(0x01000193));
+	palette[hash as usize % palette.len()]
+}
@@ -0,0 +1,352 @@
+use crate::prelude::*;
+use std::cmp::{Ordering, Reverse};
+use std::ops::Range;
+
+use super::core::{Core, BlendMode, DepthFunc, StencilParams, MeshIDLike};
+use super::mesh::UntypedMeshID;
+use super::instance_buffer::UntypedInstanceBufferID;
+use super::shader::ShaderID;
+use super::texture::TextureID;
+use super::camera::Camera;
+
+/// Opaque items are drawn front to back in buckets of this depth, sorted by state within each bucket,
+/// trading exact ordering for fewer state changes
+const OPAQUE_DEPTH_BUCKET_SIZE: f32 = 8.0;
+
+
+/// Passes are drawn in order. Within a pass, lower layers are drawn first
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum RenderPass {
+	/// Sorted roughly front to back, then by state
+	Opaque,
+
+	/// Sorted back to front so that blending is correct
+	Transparent,
+
+	/// Drawn last, in submission order within a layer
+	Overlay,
+}
+
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Primitive {
+	Triangles,
+	Lines,
+	Points,
+}
+
+impl Primitive {
+	fn draw_mode(self) -> u32 {
+		match self {
+			Primitive::Triangles => gl::TRIANGLES,
+			Primitive::Lines => gl::LINES,
+			Primitive::Points => gl::POINTS,
+		}
+	}
+}
+
+
+#[derive(Clone, Debug)]
+pub enum UniformValue {
+	I32(i32),
+	F32(f32),
+	Vec2(Vec2),
+	Vec3(Vec3),
+	Vec4(Vec4),
+
+	/// A mat3 uniform set from the upper 3x3 of the matrix
+	Mat3FromMat4(Mat4),
+	Mat4(Mat4),
+
+	Texture(TextureID, u32),
+}
+
+impl UniformValue {
+	fn apply(&self, core: &mut Core, name: &str) {
+		match self {
+			UniformValue::I32(v) => core.set_uniform_i32(name, *v),
+			UniformValue::F32(v) => core.set_uniform_f32(name, *v),
+			UniformValue::Vec2(v) => core.set_uniform_vec2(name, *v),
+			UniformValue::Vec3(v) => core.set_uniform_vec3(name, *v),
+			UniformValue::Vec4(v) => core.set_uniform_vec4(name, *v),
+			UniformValue::Mat3FromMat4(v) => core.set_uniform_mat3_from_mat4(name, v),
+			UniformValue::Mat4(v) => core.set_uniform_mat4(name, v),
+			UniformValue::Texture(id, slot) => core.set_uniform_texture(name, *id, *slot),
+		}
+	}
+}
+
+
+/// Everything needed to draw a mesh, independent of whatever was drawn before it
+#[derive(Clone, Debug)]
+pub struct DrawItem {
+	pub mesh: UntypedMeshID,
+	pub primitive: Primitive,
+
+	/// Elements (or vertices, for basic meshes) to draw, or None to draw the whole mesh
+	pub range: Option<Range<u32>>,
+
+	/// Draws the whole mesh once per instance in the buffer, rather than once.
+	/// Instanced items can't also have a range
+	pub instances: Option<UntypedInstanceBufferID>,
+
+	pub shader: ShaderID,
+	pub uniforms: Vec<(&'static str, UniformValue)>,
+
+	pub blend_mode: BlendMode,
+	pub depth: Option<DepthFunc>,
+	pub stencil: Option<StencilParams>,
+	pub color_write: bool,
+
+	pub pass: RenderPass,
+	pub layer: i32,
+
+	/// Distance from the camera, for ordering within a pass
+	pub sort_depth: f32,
+}
+
+impl DrawItem {
+	/// An opaque, depth tested item in layer 0
+	pub fn new(mesh: impl Into<UntypedMeshID>, shader: ShaderID) -> DrawItem {
+		DrawItem {
+			mesh: mesh.into(),
+			primitive: Primitive::Triangles,
+			range: None,
+			instances: None,
+			shader,
+			uniforms: Vec::new(),
+
+			blend_mode: BlendMode::None,
+			depth: Some(DepthFunc::default()),
+			stencil: None,
+			color_write: true,
+
+			pass: RenderPass::Opaque,
+			layer: 0,
+
+			sort_depth: 0.0,
+		}
+	}
+
+	/// Moves the item into the transparent pass with alpha blending
+	pub fn transparent(self) -> DrawItem {
+		DrawItem {
+			pass: RenderPass::Transparent,
+			blend_mode: BlendMode::Alpha,
+			..self
+		}
+	}
+
+	pub fn with_pass(self, pass: RenderPass) -> DrawItem { DrawItem { pass, ..self } }
+	pub fn with_layer(self, layer: i32) -> DrawItem { DrawItem { layer, ..self } }
+	pub fn with_primitive(self, primitive: Primitive) -> DrawItem { DrawItem { primitive, ..self } }
+	pub fn with_blend_mode(self, blend_mode: BlendMode) -> DrawItem { DrawItem { blend_mode, ..self } }
+	pub fn with_color_write(self, color_write: bool) -> DrawItem { DrawItem { color_write, ..self } }
+	pub fn with_range(self, range: Range<u32>) -> DrawItem { DrawItem { range: Some(range), ..self } }
+
+	pub fn with_instances(self, instances: impl Into<UntypedInstanceBufferID>) -> DrawItem {
+		DrawItem { instances: Some(instances.into()), ..self }
+	}
+
+	pub fn with_depth(self, depth: impl Into<Option<DepthFunc>>) -> DrawItem {
+		DrawItem { depth: depth.into(), ..self }
+	}
+
+	pub fn with_stencil(self, stencil: impl Into<Option<StencilParams>>) -> DrawItem {
+		DrawItem { stencil: stencil.into(), ..self }
+	}
+
+	pub fn with_uniform(mut self, name: &'static str, value: UniformValue) -> DrawItem {
+		self.uniforms.push((name, value));
+		self
+	}
+
+	/// Sets `sort_depth` from the distance between `position` and `camera`
+	pub fn at_position(self, position: Vec3, camera: &Camera) -> DrawItem {
+		DrawItem { sort_depth: (position - camera.position()).length(), ..self }
+	}
+
+	fn depth_bucket(&self) -> i32 {
+		(self.sort_depth / OPAQUE_DEPTH_BUCKET_SIZE).floor() as i32
+	}
+
+	fn state_key(&self) -> (ShaderID, BlendMode, Option<DepthFunc>, Option<StencilParams>, bool, UntypedMeshID) {
+		(self.shader, self.blend_mode, self.depth, self.stencil, self.color_write, self.mesh)
+	}
+}
+
+
+/// Collects draw items over a frame, then sorts and draws them all at once.
+///
+/// Not everything goes through the queue. Views that render another scene (e.g., the view screen)
+/// switch cameras and stencil state between passes, which depend on each other's results, and
+/// animated meshes bind a bone texture buffer, which `UniformValue` can't express.
+/// Those still draw through `Core` directly, between queue executions
+pub struct RenderQueue {
+	items: Vec<DrawItem>,
+}
+
+impl RenderQueue {
+	pub fn new() -> RenderQueue {
+		RenderQueue { items: Vec::new() }
+	}
+
+	pub fn submit(&mut self, item: DrawItem) {
+		self.items.push(item);
+	}
+
+	/// Draws and clears all submitted items. Leaves the stencil test disabled and depth testing
+	/// and colour writes at their defaults
+	pub fn execute(&mut self, core: &mut Core) {
+		// Stable, so that overlay items and ties keep their submission order
+		self.items.sort_by(compare_items);
+
+		for item in self.items.drain(..) {
+			core.use_shader(item.shader);
+			core.set_blend_mode(item.blend_mode);
+			core.set_depth(item.depth);
+			core.set_stencil(item.stencil);
+			core.set_color_write(item.color_write);
+
+			for (name, value) in item.uniforms.iter() {
+				value.apply(core, name);
+			}
+
+			match (item.instances, item.range) {
+				(Some(instances), None) => core.draw_instanced(item.mesh, instances, item.primitive.draw_mode()),
+				(Some(_), Some(_)) => panic!("Instanced draw items can't have a range"),
+
+				(None, range) => {
+					item.mesh.bind_mesh(core);
+					match range {
+						Some(range) => item.mesh.draw_mesh_range(core, item.primitive.draw_mode(), range),
+						None => item.mesh.draw_mesh(core, item.primitive.draw_mode()),
+					}
+				}
+			}
+		}
+
+		core.set_stencil(None);
+		core.set_depth(DepthFunc::default());
+		core.set_color_write(true);
+	}
+}
+
+
+fn compare_items(a: &DrawItem, b: &DrawItem) -> Ordering {
+	let ordering = (a.pass, a.layer).cmp(&(b.pass, b.layer));
+	if ordering != Ordering::Equal {
+		return ordering
+	}
+
+	match a.pass {
+		// Near buckets first so that depth testing can reject as much as possible
+		RenderPass::Opaque => (a.depth_bucket(), a.state_key(), a.sort_depth.ordify())
+			.cmp(&(b.depth_bucket(), b.state_key(), b.sort_depth.ordify())),
+
+		RenderPass::Transparent => (Reverse(a.sort_depth.ordify()), a.state_key())
+			.cmp(&(Reverse(b.sort_depth.ordify()), b.state_key())),
+
+		RenderPass::Overlay => Ordering::Equal,
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::gfx::resource::ResourceStore;
+
+	struct Ids {
+		mesh: UntypedMeshID,
+		shaders: Vec<ShaderID>,
+	}
+
+	// Keys from a store of nothing, since sorting never touches GL
+	fn ids() -> Ids {
+		let mut store = ResourceStore::new();
+
+		Ids {
+			mesh: UntypedMeshID::Indexed(store.insert(())),
+			shaders: (0..2).map(|_| ShaderID(store.insert(()))).collect(),
+		}
+	}
+
+	fn item(ids: &Ids, shader: usize, pass: RenderPass, sort_depth: f32) -> DrawItem {
+		DrawItem {
+			sort_depth,
+			..DrawItem::new(ids.mesh, ids.shaders[shader]).with_pass(pass)
+		}
+	}
+
+	fn sorted_depths(mut items: Vec<DrawItem>) -> Vec<f32> {
+		items.sort_by(compare_items);
+		items.iter().map(|item| item.sort_depth).collect()
+	}
+
+	#[test]
+	fn opaque_near_first() {
+		let ids = ids();
+		let items = vec![
+			item(&ids, 0, RenderPass::Opaque, 50.0),
+			item(&ids, 0, RenderPass::Opaque, 1.0),
+			item(&ids, 0, RenderPass::Opaque, 20.0),
+		];
+
+		assert_eq!(sorted_depths(items), [1.0, 20.0, 50.0]);
+	}
+
+	#[test]
+	fn opaque_groups_state_within_bucket() {
+		let ids = ids();
+		let mut items = vec![
+			item(&ids, 1, RenderPass::Opaque, 1.0),
+			item(&ids, 0, RenderPass::Opaque, 2.0),
+			item(&ids, 1, RenderPass::Opaque, 3.0),
+			item(&ids, 0, RenderPass::Opaque, 4.0),
+		];
+
+		items.sort_by(compare_items);
+
+		let shaders = items.iter().map(|item| item.shader).collect(): Vec<_>;
+		assert_eq!(shaders, [ids.shaders[0], ids.shaders[0], ids.shaders[1], ids.shaders[1]]);
+	}
+
+	#[test]
+	fn transparent_far_first() {
+		let ids = ids();
+		let items = vec![
+			item(&ids, 0, RenderPass::Transparent, 5.0),
+			item(&ids, 1, RenderPass::Transparent, 30.0),
+			item(&ids, 0, RenderPass::Transparent, 10.0),
+		];
+
+		assert_eq!(sorted_depths(items), [30.0, 10.0, 5.0]);
+	}
+
+	#[test]
+	fn overlay_keeps_submission_order() {
+		let ids = ids();
+		let items = vec![
+			item(&ids, 1, RenderPass::Overlay, 5.0),
+			item(&ids, 0, RenderPass::Overlay, 30.0),
+			item(&ids, 1, RenderPass::Overlay, 10.0),
+			item(&ids, 0, RenderPass::Overlay, 1.0),
+		];
+
+		assert_eq!(sorted_depths(items), [5.0, 30.0, 10.0, 1.0]);
+	}
+
+	#[test]
+	fn passes_and_layers_come_first() {
+		let ids = ids();
+		let items = vec![
+			item(&ids, 0, RenderPass::Overlay, 1.0),
+			item(&ids, 0, RenderPass::Transparent, 2.0),
+			item(&ids, 0, RenderPass::Opaque, 3.0).with_layer(1),
+			item(&ids, 0, RenderPass::Opaque, 4.0),
+		];
+
+		assert_eq!(sorted_depths(items), [4.0, 3.0, 2.0, 1.0]);
+	}
+}
@@ -0,0 +1,69 @@
+/// A handle into a `ResourceStore`. The generation is bumped every time a slot is freed,
+/// so handles to destroyed resources are detected rather than aliasing whatever reuses the slot
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ResourceKey {
+	index: u32,
+	generation: u32,
+}
+
+
+struct Slot<T> {
+	generation: u32,
+	value: Option<T>,
+}
+
+pub(super) struct ResourceStore<T> {
+	slots: Vec<Slot<T>>,
+	free_slots: Vec<u32>,
+}
+
+impl<T> ResourceStore<T> {
+	pub(super) fn new() -> Self {
+		ResourceStore {
+			slots: Vec::new(),
+			free_slots: Vec::new(),
+		}
+	}
+
+	pub(super) fn insert(&mut self, value: T) -> ResourceKey {
+		if let Some(index) = self.free_slots.pop() {
+			let slot = &mut self.slots[index as usize];
+			slot.value = Some(value);
+			return ResourceKey { index, generation: slot.generation }
+		}
+
+		self.slots.push(Slot { generation: 0, value: Some(value) });
+		ResourceKey { index: self.slots.len() as u32 - 1, generation: 0 }
+	}
+
+	pub(super) fn remove(&mut self, key: ResourceKey) -> Option<T> {
+		let slot = self.slots.get_mut(key.index as usize)?;
+		if slot.generation != key.generation { return None }
+
+		let value = slot.value.take()?;
+		slot.generation = slot.generation.wrapping_add(1);
+		self.free_slots.push(key.index);
+		Some(value)
+	}
+
+	pub(super) fn get(&self, key: ResourceKey) -> Option<&T> {
+		self.slots.get(key.index as usize)
+			.filter(|slot| slot.generation == key.generation)
+			.and_then(|slot| slot.value.as_ref())
+	}
+
+	pub(super) fn get_mut(&mut self, key: ResourceKey) -> Option<&mut T> {
+		self.slots.get_mut(key.index as usize)
+			.filter(|slot| slot.generation == key.generation)
+			.and_then(|slot| slot.value.as_mut())
+	}
+
+	pub(super) fn iter_mut(&mut self) -> impl Iterator<Item=(ResourceKey, &'_ mut T)> + '_ {
+		self.slots.iter_mut()
+			.enumerate()
+			.filter_map(|(index, slot)| {
+				let key = ResourceKey { index: index as u32, generation: slot.generation };
+				slot.value.as_mut().map(move |value| (key, value))
+			})
+	}
+}
@@ -0,0 +1,118 @@
+use crate::prelude::*;
+use super::core::Core;
+use super::image::Image;
+
+use std::path::PathBuf;
+
+pub const SCREENSHOT_DIR: &str = "screenshots";
+
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CaptureBuffer {
+	Color,
+	Depth,
+	Stencil,
+}
+
+impl CaptureBuffer {
+	pub const ALL: [CaptureBuffer; 3] = [CaptureBuffer::Color, CaptureBuffer::Depth, CaptureBuffer::Stencil];
+
+	pub fn name(self) -> &'static str {
+		match self {
+			CaptureBuffer::Color => "color",
+			CaptureBuffer::Depth => "depth",
+			CaptureBuffer::Stencil => "stencil",
+		}
+	}
+
+	pub fn from_name(name: &str) -> Option<CaptureBuffer> {
+		CaptureBuffer::ALL.iter().cloned().find(|b| b.name() == name)
+	}
+}
+
+
+/// Reads back the current render target - the bound framebuffer or the window - and writes
+/// each requested buffer to `screenshots/screenshot-<timestamp>[-depth|-stencil].png`.
+/// Should be called after drawing and before swapping
+pub fn save_screenshot(core: &mut Core, buffers: &[CaptureBuffer]) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+	std::fs::create_dir_all(SCREENSHOT_DIR)?;
+
+	let timestamp = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?;
+	let base_name = format!("screenshot-{}-{:03}", timestamp.as_secs(), timestamp.subsec_millis());
+
+	let mut paths = Vec::new();
+
+	for &buffer in buffers {
+		let image = capture(core, buffer)?;
+
+		let path = match buffer {
+			CaptureBuffer::Color => PathBuf::from(SCREENSHOT_DIR).join(format!("{}.png", base_name)),
+			_ => PathBuf::from(SCREENSHOT_DIR).join(format!("{}-{}.png", base_name, buffer.name())),
+		};
+
+		image.save_png(&path)?;
+		paths.push(path);
+	}
+
+	Ok(paths)
+}
+
+
+/// Reads back a buffer of the current render target, visualising depth and stencil as colour
+pub fn capture(core: &mut Core, buffer: CaptureBuffer) -> Result<Image, Box<dyn Error>> {
+	let size = core.render_target_size();
+
+	let image = match buffer {
+		CaptureBuffer::Color => core.read_pixels(),
+
+		CaptureBuffer::Depth => {
+			let depth = core.read_depth().ok_or("Render target has no depth buffer")?;
+			Image::new(size, visualise_depth(&depth))
+		}
+
+		CaptureBuffer::Stencil => {
+			let stencil = core.read_stencil().ok_or("Render target has no stencil buffer")?;
+			Image::new(size, visualise_stencil(&stencil))
+		}
+	};
+
+	Ok(image)
+}
+
+
+/// Stretches the range of written depth values to full brightness, near being brightest,
+/// since perspective depth is bunched up close to 1.0. Cleared depth is black
+fn visualise_depth(depth: &[f32]) -> Vec<u8> {
+	let (min, max) = depth.iter()
+		.filter(|&&d| d < 1.0)
+		.fold((1.0f32, 0.0f32), |(min, max), &d| (min.min(d), max.max(d)));
+
+	let range = (max - min).max(0.000_001);
+
+	depth.iter()
+		.flat_map(|&d| {
+			let v = if d >= 1.0 { 0 } else { (255.0 - (d - min) / range * 223.0) as u8 };
+			vec![v, v, v, 255]
+		})
+		.collect()
+}
+
+/// Zero is black, every other value gets a distinct colour
+fn visualise_stencil(stencil: &[u8]) -> Vec<u8> {
+	const PALETTE: [[u8; 3]; 7] = [
+		[255, 0, 0], [0, 255, 0], [0, 0, 255],
+		[255, 255, 0], [0, 255, 255], [255, 0, 255],
+		[255, 255, 255],
+	];
+
+	stencil.iter()
+		.flat_map(|&s| {
+			let [r, g, b] = match s {
+				0 => [0, 0, 0],
+				_ => PALETTE[(s as usize - 1) % PALETTE.len()],
+			};
+
+			vec![r, g, b, 255]
+		})
+		.collect()
+}
@@ -1,35 +1,263 @@
+use super::resource::ResourceKey;
+use super::uniform_buffer::{CAMERA_BLOCK_NAME, CAMERA_BLOCK_BINDING};
+use std::collections::{HashMap, HashSet};
+use std::time::SystemTime;
 
+mod preprocessor;
+mod error;
 
+use preprocessor::{Preprocessed, IncludeSource};
+use super::vertex::{Vertex, AttributeBinding};
+
+pub use self::error::{ShaderError, ShaderStage, ShaderErrorLocation};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ShaderID(pub(super) ResourceKey);
+
+
+/// Shader source embedded in the binary, optionally along with the path it was embedded from
+/// so that it can be reloaded from disk during development.
+/// Usually created with `shader_source!`
+#[derive(Copy, Clone, Debug)]
+pub struct ShaderSource {
+	pub embedded: &'static str,
+	pub path: Option<&'static str>,
+}
+
+impl From<&'static str> for ShaderSource {
+	fn from(embedded: &'static str) -> ShaderSource {
+		ShaderSource { embedded, path: None }
+	}
+}
+
+impl ShaderSource {
+	/// Reads the source from disk if it has a path, falling back to the embedded source
+	fn read(&self) -> Result<String, ShaderError> {
+		match self.path {
+			Some(path) => std::fs::read_to_string(path)
+				.map_err(|err| ShaderError::Source(format!("Failed to read '{}': {}", path, err))),
+			None => Ok(self.embedded.to_owned()),
+		}
+	}
+
+	fn modified_time(&self) -> Option<SystemTime> {
+		modified_time(self.path?)
+	}
+
+	fn file_name(&self) -> &'static str {
+		self.path
+			.map(|path| path.rsplit(|c: char| c == '/' || c == '\\').next().unwrap_or(path))
+			.unwrap_or("<embedded>")
+	}
+
+	fn preprocess(&self, source: &str, defines: &[(String, String)], include_source: IncludeSource) -> Result<Preprocessed, ShaderError> {
+		preprocessor::preprocess(source, self.file_name(), defines, include_source)
+			.map_err(ShaderError::Source)
+	}
+}
+
+/// Embeds a shader from `src/` and remembers its path for hot reloading.
+/// e.g., `shader_source!("shaders/fog_vert.glsl")`
+#[macro_export]
+macro_rules! shader_source {
+	($path:literal) => {
+		$crate::gfx::shader::ShaderSource {
+			embedded: include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/src/", $path)),
+			path: Some(concat!(env!("CARGO_MANIFEST_DIR"), "/src/", $path)),
+		}
+	}
+}
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
-pub struct ShaderID(pub(super) usize);
 
 pub(super) struct Shader {
 	pub(super) handle: u32,
-	pub(super) attribute_count: u32,
+
+	vertex_source: ShaderSource,
+	fragment_source: ShaderSource,
+	attribs: Vec<String>,
+	defines: Vec<(String, String)>,
+
+	/// The layout of the vertex type the shader was created for, to validate against on reload
+	vertex_bindings: Vec<AttributeBinding>,
+
+	/// Snippets pulled in by either source via `#include`, as of the last (re)load
+	includes: Vec<String>,
+
+	/// The most recent modification time of any source file, as of the last (re)load
+	source_modified_time: Option<SystemTime>,
+
+	/// Locations of all active uniforms, queried once at link time. Array uniforms are
+	/// stored under their base name, without the `[0]` suffix
+	uniform_locations: HashMap<String, i32>,
+
+	/// Names that have been asked for but aren't active, so each is only warned about once
+	unknown_uniforms: HashSet<String>,
 }
 
 impl Shader {
-	pub(super) fn new(vsrc: &str, fsrc: &str, attribs: &[&str]) -> Shader {
-		let vsh = compile_shader(vsrc, gl::VERTEX_SHADER);
-		let fsh = compile_shader(fsrc, gl::FRAGMENT_SHADER);
+	pub(super) fn new<V: Vertex>(vertex_source: ShaderSource, fragment_source: ShaderSource, attribs: &[&str], defines: &[(&str, &str)])
+		-> Result<Shader, ShaderError>
+	{
+		Shader::with_bindings(vertex_source, fragment_source, attribs, defines, V::descriptor().bindings().to_vec())
+	}
+
+	/// Like `new`, but validated against an explicit set of attribute bindings, e.g., a vertex
+	/// type's followed by an instance type's
+	pub(super) fn with_bindings(vertex_source: ShaderSource, fragment_source: ShaderSource, attribs: &[&str],
+		defines: &[(&str, &str)], vertex_bindings: Vec<AttributeBinding>) -> Result<Shader, ShaderError>
+	{
+		let attribs = attribs.iter().map(|&a| a.to_owned()).collect(): Vec<_>;
+		let defines = defines.iter().map(|&(k, v)| (k.to_owned(), v.to_owned())).collect(): Vec<_>;
+
+		let vsrc = vertex_source.preprocess(vertex_source.embedded, &defines, IncludeSource::Embedded)?;
+		let fsrc = fragment_source.preprocess(fragment_source.embedded, &defines, IncludeSource::Embedded)?;
+		let handle = build_program(&vsrc, &fsrc, &attribs, &vertex_bindings)?;
+
+		let mut shader = Shader {
+			handle,
+
+			vertex_source,
+			fragment_source,
+			attribs,
+			defines,
+			vertex_bindings,
+
+			includes: collect_includes(&vsrc, &fsrc),
+			source_modified_time: None,
+
+			uniform_locations: query_uniform_locations(handle),
+			unknown_uniforms: HashSet::new(),
+		};
+
+		shader.source_modified_time = shader.latest_modified_time();
+		Ok(shader)
+	}
+
+	/// Rebuilds the program from disk if any source file has changed since the last attempt.
+	/// Returns None if nothing changed. On failure the previous program is kept
+	pub(super) fn reload_if_changed(&mut self) -> Option<Result<(), ShaderError>> {
+		let modified_time = self.latest_modified_time();
+		if modified_time <= self.source_modified_time {
+			return None
+		}
+
+		// Only retry once the file changes again, rather than spamming errors every poll
+		self.source_modified_time = modified_time;
+
+		Some(self.reload())
+	}
+
+	fn reload(&mut self) -> Result<(), ShaderError> {
+		let vsrc = self.vertex_source.read()?;
+		let fsrc = self.fragment_source.read()?;
+
+		let vsrc = self.vertex_source.preprocess(&vsrc, &self.defines, IncludeSource::Disk)?;
+		let fsrc = self.fragment_source.preprocess(&fsrc, &self.defines, IncludeSource::Disk)?;
+		let handle = build_program(&vsrc, &fsrc, &self.attribs, &self.vertex_bindings)?;
+
+		unsafe {
+			gl::DeleteProgram(self.handle);
+		}
+
+		self.handle = handle;
+		self.includes = collect_includes(&vsrc, &fsrc);
+		self.uniform_locations = query_uniform_locations(handle);
+		self.unknown_uniforms.clear();
+		Ok(())
+	}
+
+	fn latest_modified_time(&self) -> Option<SystemTime> {
+		// Includes are only watched for shaders that are themselves reloadable
+		if self.vertex_source.path.is_none() && self.fragment_source.path.is_none() {
+			return None
+		}
+
+		let include_times = self.includes.iter()
+			.filter_map(|name| modified_time(preprocessor::include_path(name)));
+
+		self.vertex_source.modified_time()
+			.max(self.fragment_source.modified_time())
+			.max(include_times.max())
+	}
+
+	pub(super) fn describe(&self) -> String {
+		let name = |src: &ShaderSource| src.path.unwrap_or("<embedded>");
+		format!("{} + {}", name(&self.vertex_source), name(&self.fragment_source))
+	}
+
+	/// Looks up the location of an active uniform, warning the first time an unknown name
+	/// is requested. Inactive uniforms return -1, which GL silently ignores
+	pub(super) fn uniform_location(&mut self, name: &str) -> i32 {
+		if let Some(&location) = self.uniform_locations.get(name) {
+			return location
+		}
+
+		if self.unknown_uniforms.insert(name.to_owned()) {
+			eprintln!("Warning: shader {} has no active uniform '{}' - either it's misspelled or it was optimised out",
+				self.describe(), name);
+		}
+
+		-1
+	}
+
+	pub(super) fn destroy(self) {
+		unsafe {
+			gl::DeleteProgram(self.handle);
+		}
+	}
+}
+
+
+fn modified_time(path: impl AsRef<std::path::Path>) -> Option<SystemTime> {
+	std::fs::metadata(path).and_then(|md| md.modified()).ok()
+}
+
+fn collect_includes(vsrc: &Preprocessed, fsrc: &Preprocessed) -> Vec<String> {
+	let mut includes = vsrc.includes().to_vec();
+	for include in fsrc.includes() {
+		if !includes.contains(include) {
+			includes.push(include.clone());
+		}
+	}
+
+	includes
+}
 
-		let program = link_shader(vsh, fsh, attribs);
 
-		Shader {
-			handle: program,
-			attribute_count: attribs.len() as _,
+fn build_program(vsrc: &Preprocessed, fsrc: &Preprocessed, attribs: &[String], vertex_bindings: &[AttributeBinding])
+	-> Result<u32, ShaderError>
+{
+	let vsh = compile_shader(vsrc, ShaderStage::Vertex)?;
+	let fsh = match compile_shader(fsrc, ShaderStage::Fragment) {
+		Ok(fsh) => fsh,
+		Err(err) => {
+			unsafe { gl::DeleteShader(vsh) }
+			return Err(err)
 		}
+	};
+
+	let program = link_shader(vsh, fsh, attribs)?;
+
+	if let Err(err) = validate_attributes(program, attribs, vertex_bindings) {
+		unsafe { gl::DeleteProgram(program) }
+		return Err(err)
 	}
+
+	bind_uniform_block(program, CAMERA_BLOCK_NAME, CAMERA_BLOCK_BINDING);
+	Ok(program)
 }
 
-fn compile_shader(src: &str, ty: u32) -> u32 {
+fn compile_shader(preprocessed: &Preprocessed, stage: ShaderStage) -> Result<u32, ShaderError> {
 	use std::ffi::CString;
-	use std::str;
 
 	unsafe {
+		let ty = match stage {
+			ShaderStage::Vertex => gl::VERTEX_SHADER,
+			ShaderStage::Fragment => gl::FRAGMENT_SHADER,
+		};
+
 		let handle = gl::CreateShader(ty);
-		let src = CString::new(src.as_bytes()).unwrap();
+		let src = CString::new(preprocessed.source.as_bytes()).unwrap();
 
 		gl::ShaderSource(handle, 1, &src.as_ptr(), std::ptr::null());
 		gl::CompileShader(handle);
@@ -41,7 +269,7 @@ fn compile_shader(src: &str, ty: u32) -> u32 {
 			let mut length = 0;
 			gl::GetShaderiv(handle, gl::INFO_LOG_LENGTH, &mut length);
 
-			let mut buffer = vec![0u8; length as usize];
+			let mut buffer = vec![0u8; length.max(1) as usize];
 			gl::GetShaderInfoLog(
 				handle,
 				length,
@@ -49,16 +277,18 @@ fn compile_shader(src: &str, ty: u32) -> u32 {
 				buffer.as_mut_ptr() as *mut _
 			);
 
-			let error = str::from_utf8(&buffer[..buffer.len()-1]).unwrap();
+			gl::DeleteShader(handle);
 
-			panic!("Shader compile failed!\n{}", error);
+			let log = String::from_utf8_lossy(&buffer[..buffer.len()-1]);
+			let errors = preprocessed.parse_error_log(&log);
+			return Err(ShaderError::Compile { stage, errors })
 		}
 
-		handle
+		Ok(handle)
 	}
 }
 
-fn link_shader(vsh: u32, fsh: u32, attribs: &[&str]) -> u32 {
+fn link_shader(vsh: u32, fsh: u32, attribs: &[String]) -> Result<u32, ShaderError> {
 	use std::ffi::CString;
 
 	unsafe {
@@ -66,13 +296,16 @@ fn link_shader(vsh: u32, fsh: u32, attribs: &[&str]) -> u32 {
 		gl::AttachShader(handle, vsh);
 		gl::AttachShader(handle, fsh);
 
-		for (i, &a) in attribs.iter().enumerate() {
+		for (i, a) in attribs.iter().enumerate() {
 			let a = CString::new(a.as_bytes()).unwrap();
 			gl::BindAttribLocation(handle, i as u32, a.as_ptr());
 		}
 
 		gl::LinkProgram(handle);
 
+		gl::DeleteShader(vsh);
+		gl::DeleteShader(fsh);
+
 		let mut status = 0;
 		gl::GetProgramiv(handle, gl::LINK_STATUS, &mut status);
 
@@ -80,13 +313,136 @@ fn link_shader(vsh: u32, fsh: u32, attribs: &[&str]) -> u32 {
 			let mut buf = [0u8; 1024];
 			let mut len = 0;
 			gl::GetProgramInfoLog(handle, buf.len() as _, &mut len, buf.as_mut_ptr() as _);
+			gl::DeleteProgram(handle);
 
-			panic!("shader link failed: {}", std::str::from_utf8(&buf[..len as usize]).unwrap());
+			return Err(ShaderError::Link(String::from_utf8_lossy(&buf[..len as usize]).into_owned()))
 		}
 
-		gl::DeleteShader(vsh);
-		gl::DeleteShader(fsh);
+		Ok(handle)
+	}
+}
+
+
+/// Checks that every active attribute has a vertex binding at its location with the same
+/// number of components and the same float/integer-ness, and that every binding has a name
+fn validate_attributes(program: u32, attribs: &[String], vertex_bindings: &[AttributeBinding]) -> Result<(), ShaderError> {
+	use std::ffi::CString;
+
+	if let Some(binding) = vertex_bindings.iter().find(|b| b.position as usize >= attribs.len()) {
+		return Err(ShaderError::AttributeMismatch(format!(
+			"vertex type has an attribute at location {}, but only {} attribute names were given",
+			binding.position, attribs.len())))
+	}
+
+	unsafe {
+		let mut attribute_count = 0;
+		let mut max_name_length = 0;
+		gl::GetProgramiv(program, gl::ACTIVE_ATTRIBUTES, &mut attribute_count);
+		gl::GetProgramiv(program, gl::ACTIVE_ATTRIBUTE_MAX_LENGTH, &mut max_name_length);
+
+		let mut buffer = vec![0u8; max_name_length.max(1) as usize];
+
+		for index in 0..attribute_count as u32 {
+			let mut length = 0;
+			let mut size = 0;
+			let mut ty = 0;
+
+			gl::GetActiveAttrib(program, index, buffer.len() as _, &mut length, &mut size, &mut ty,
+				buffer.as_mut_ptr() as *mut _);
+
+			let name = std::str::from_utf8(&buffer[..length as usize]).unwrap();
+			if name.starts_with("gl_") { continue }
+
+			let c_name = CString::new(name).unwrap();
+			let location = gl::GetAttribLocation(program, c_name.as_ptr());
+
+			let binding = vertex_bindings.iter().find(|b| b.position as i32 == location)
+				.ok_or_else(|| ShaderError::AttributeMismatch(format!(
+					"active attribute '{}' at location {} has no matching vertex attribute", name, location)))?;
+
+			let (components, integer) = attribute_type_layout(ty)
+				.ok_or_else(|| ShaderError::AttributeMismatch(format!(
+					"active attribute '{}' has unsupported type 0x{:x}", name, ty)))?;
+
+			if components != binding.components || integer != binding.ty.is_integer() {
+				return Err(ShaderError::AttributeMismatch(format!(
+					"attribute '{}' is a {}{} in the shader, but the vertex type provides {}x {:?}",
+					name, if integer { "int" } else { "float" }, components,
+					binding.components, binding.ty)))
+			}
+		}
+	}
+
+	Ok(())
+}
+
+/// Component count and integer-ness of a GL attribute type
+fn attribute_type_layout(ty: u32) -> Option<(u32, bool)> {
+	let layout = match ty {
+		gl::FLOAT => (1, false),
+		gl::FLOAT_VEC2 => (2, false),
+		gl::FLOAT_VEC3 => (3, false),
+		gl::FLOAT_VEC4 => (4, false),
+
+		gl::INT | gl::UNSIGNED_INT => (1, true),
+		gl::INT_VEC2 | gl::UNSIGNED_INT_VEC2 => (2, true),
+		gl::INT_VEC3 | gl::UNSIGNED_INT_VEC3 => (3, true),
+		gl::INT_VEC4 | gl::UNSIGNED_INT_VEC4 => (4, true),
+
+		_ => return None,
+	};
+
+	Some(layout)
+}
+
+
+fn query_uniform_locations(program: u32) -> HashMap<String, i32> {
+	use std::ffi::CString;
+
+	unsafe {
+		let mut uniform_count = 0;
+		let mut max_name_length = 0;
+		gl::GetProgramiv(program, gl::ACTIVE_UNIFORMS, &mut uniform_count);
+		gl::GetProgramiv(program, gl::ACTIVE_UNIFORM_MAX_LENGTH, &mut max_name_length);
+
+		let mut buffer = vec![0u8; max_name_length.max(1) as usize];
+
+		(0..uniform_count as u32)
+			.filter_map(|index| {
+				let mut length = 0;
+				let mut size = 0;
+				let mut ty = 0;
+
+				gl::GetActiveUniform(program, index, buffer.len() as _, &mut length, &mut size, &mut ty,
+					buffer.as_mut_ptr() as *mut _);
+
+				let full_name = std::str::from_utf8(&buffer[..length as usize]).unwrap();
+				let name = full_name.strip_suffix("[0]").unwrap_or(full_name);
+
+				// Uniform block members are reported as active uniforms but have no location
+				let c_name = CString::new(full_name).unwrap();
+				let location = gl::GetUniformLocation(program, c_name.as_ptr());
+				if location < 0 { return None }
+
+				Some((name.to_owned(), location))
+			})
+			.collect()
+	}
+}
+
+
+/// Points the named uniform block at a binding point, if the program uses it
+pub(super) fn bind_uniform_block(program: u32, name: &str, binding: u32) -> bool {
+	use std::ffi::CString;
+
+	unsafe {
+		let name = CString::new(name.as_bytes()).unwrap();
+		let index = gl::GetUniformBlockIndex(program, name.as_ptr());
+		if index == gl::INVALID_INDEX {
+			return false
+		}
 
-		handle
+		gl::UniformBlockBinding(program, index, binding);
+		true
 	}
 }
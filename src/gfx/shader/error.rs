@@ -0,0 +1,73 @@
+use std::fmt;
+
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ShaderStage {
+	Vertex,
+	Fragment,
+}
+
+
+/// A single diagnostic from a compile log, with source string numbers mapped back to file names.
+/// `file` and `line` are None for lines in a format that couldn't be parsed
+#[derive(Clone, Debug)]
+pub struct ShaderErrorLocation {
+	pub file: Option<String>,
+	pub line: Option<u32>,
+	pub message: String,
+}
+
+
+pub enum ShaderError {
+	/// Reading a source file or preprocessing it failed
+	Source(String),
+
+	Compile {
+		stage: ShaderStage,
+		errors: Vec<ShaderErrorLocation>,
+	},
+
+	Link(String),
+
+	/// The shader's active attributes don't match the vertex type it was created for
+	AttributeMismatch(String),
+}
+
+
+impl fmt::Display for ShaderErrorLocation {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match (&self.file, self.line) {
+			(Some(file), Some(line)) => write!(f, "{}:{}: {}", file, line, self.message),
+			(Some(file), None) => write!(f, "{}: {}", file, self.message),
+			_ => write!(f, "{}", self.message),
+		}
+	}
+}
+
+impl fmt::Display for ShaderError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			ShaderError::Source(err) => write!(f, "shader source error: {}", err),
+
+			ShaderError::Compile{stage, errors} => {
+				write!(f, "{:?} shader compile failed!", stage)?;
+				for error in errors.iter() {
+					write!(f, "\n{}", error)?;
+				}
+				Ok(())
+			}
+
+			ShaderError::Link(err) => write!(f, "shader link failed: {}", err),
+			ShaderError::AttributeMismatch(err) => write!(f, "shader attribute mismatch: {}", err),
+		}
+	}
+}
+
+// Compile logs are unreadable through the derived Debug, and Debug is what `expect` prints
+impl fmt::Debug for ShaderError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		fmt::Display::fmt(self, f)
+	}
+}
+
+impl std::error::Error for ShaderError {}
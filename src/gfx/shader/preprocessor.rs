@@ -0,0 +1,311 @@
+use std::path::PathBuf;
+use super::error::ShaderErrorLocation;
+
+
+/// Snippets available to `#include`, embedded so that release builds don't need the source tree
+const EMBEDDED_INCLUDES: &[(&str, &str)] = &[
+	("camera.glsl", include_str!("../../shaders/include/camera.glsl")),
+	("fog.glsl", include_str!("../../shaders/include/fog.glsl")),
+	("instance.glsl", include_str!("../../shaders/include/instance.glsl")),
+];
+
+const INCLUDE_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/shaders/include/");
+
+
+/// Where `#include`d snippets are read from
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(super) enum IncludeSource {
+	Embedded,
+	Disk,
+
+	/// Named snippets provided by tests
+	#[cfg(test)]
+	Test(&'static [(&'static str, &'static str)]),
+}
+
+pub(super) fn include_path(name: &str) -> PathBuf {
+	PathBuf::from(INCLUDE_DIR).join(name)
+}
+
+
+/// Preprocessed shader source, ready to hand to GL
+pub(super) struct Preprocessed {
+	pub(super) source: String,
+
+	/// Names of every file that contributed to `source`, indexed by the source string numbers
+	/// used in the emitted `#line` directives. The first is the root file
+	pub(super) files: Vec<String>,
+}
+
+impl Preprocessed {
+	/// Names of the snippets that were included, for watching for changes
+	pub(super) fn includes(&self) -> &[String] {
+		&self.files[1..]
+	}
+
+	/// Parses a compile log, replacing source string numbers with the file names they refer to.
+	/// Handles the `0:12(5): error`, `0(12) : error` and `ERROR: 0:12: ` styles drivers use
+	pub(super) fn parse_error_log(&self, log: &str) -> Vec<ShaderErrorLocation> {
+		log.lines()
+			.map(str::trim)
+			.filter(|line| !line.is_empty())
+			.map(|line| {
+				self.parse_error_line(line)
+					.unwrap_or_else(|| ShaderErrorLocation { file: None, line: None, message: line.to_owned() })
+			})
+			.collect()
+	}
+
+	fn parse_error_line(&self, line: &str) -> Option<ShaderErrorLocation> {
+		let rest = line.strip_prefix("ERROR: ")
+			.or_else(|| line.strip_prefix("WARNING: "))
+			.unwrap_or(line);
+
+		let (file_idx, rest) = split_number(rest)?;
+		let file = self.files.get(file_idx as usize)?.clone();
+
+		let (line_number, rest) = if let Some(rest) = rest.strip_prefix(':') {
+			split_number(rest)?
+		} else {
+			let (line_number, rest) = split_number(rest.strip_prefix('(')?)?;
+			(line_number, rest.strip_prefix(')')?)
+		};
+
+		// Skip an optional column number
+		let rest = match rest.strip_prefix('(').and_then(split_number) {
+			Some((_, rest)) => rest.strip_prefix(')').unwrap_or(rest),
+			None => rest,
+		};
+
+		let message = rest.trim_start().trim_start_matches(':').trim_start();
+
+		Some(ShaderErrorLocation {
+			file: Some(file),
+			line: Some(line_number),
+			message: message.to_owned(),
+		})
+	}
+}
+
+
+/// Expands `#include "name"` directives and injects `defines` after the `#version` directive.
+/// `#line` directives are emitted around every include so that error logs can be mapped back
+/// to the file they came from. Each snippet is only included once per shader
+pub(super) fn preprocess(source: &str, file_name: &str, defines: &[(String, String)], include_source: IncludeSource)
+	-> Result<Preprocessed, String>
+{
+	let mut preprocessor = Preprocessor {
+		output: String::new(),
+		files: vec![file_name.to_owned()],
+		include_source,
+		glsl_version: 110,
+	};
+
+	let mut lines = source.lines().enumerate().peekable();
+
+	// Everything up to and including #version has to come before any defines
+	while let Some(&(_, line)) = lines.peek() {
+		let trimmed = line.trim();
+		if !trimmed.is_empty() && !trimmed.starts_with("//") && !trimmed.starts_with("#version") {
+			break
+		}
+
+		if let Some(version) = trimmed.strip_prefix("#version") {
+			preprocessor.glsl_version = version.split_whitespace().next()
+				.and_then(|v| v.parse().ok())
+				.ok_or_else(|| format!("{}: malformed #version directive", file_name))?;
+		}
+
+		preprocessor.output.push_str(line);
+		preprocessor.output.push('\n');
+		lines.next();
+	}
+
+	for (name, value) in defines {
+		preprocessor.output.push_str(&format!("#define {} {}\n", name, value));
+	}
+
+	if let Some(&(line_idx, _)) = lines.peek() {
+		preprocessor.push_line_directive(line_idx + 1, 0);
+	}
+
+	preprocessor.process_lines(lines, 0)?;
+
+	Ok(Preprocessed {
+		source: preprocessor.output,
+		files: preprocessor.files,
+	})
+}
+
+
+struct Preprocessor {
+	output: String,
+	files: Vec<String>,
+	include_source: IncludeSource,
+	glsl_version: u32,
+}
+
+impl Preprocessor {
+	fn process_lines<'s>(&mut self, lines: impl Iterator<Item=(usize, &'s str)>, file_idx: usize) -> Result<(), String> {
+		for (line_idx, line) in lines {
+			let include = line.trim().strip_prefix("#include");
+
+			let name = match include {
+				Some(name) => parse_include_name(name)
+					.ok_or_else(|| format!("{}:{}: malformed #include", self.files[file_idx], line_idx + 1))?,
+
+				None => {
+					self.output.push_str(line);
+					self.output.push('\n');
+					continue
+				}
+			};
+
+			if self.files.iter().any(|f| f == name) {
+				// Keep line numbering intact
+				self.output.push('\n');
+				continue
+			}
+
+			let source = self.read_include(name)
+				.map_err(|err| format!("{}:{}: {}", self.files[file_idx], line_idx + 1, err))?;
+
+			let include_idx = self.files.len();
+			self.files.push(name.to_owned());
+
+			self.push_line_directive(1, include_idx);
+			self.process_lines(source.lines().enumerate(), include_idx)?;
+			self.push_line_directive(line_idx + 2, file_idx);
+		}
+
+		Ok(())
+	}
+
+	fn read_include(&self, name: &str) -> Result<String, String> {
+		match self.include_source {
+			IncludeSource::Embedded => EMBEDDED_INCLUDES.iter()
+				.find(|&&(n, _)| n == name)
+				.map(|&(_, source)| source.to_owned())
+				.ok_or_else(|| format!("unknown include '{}'", name)),
+
+			IncludeSource::Disk => {
+				let path = include_path(name);
+				std::fs::read_to_string(&path)
+					.map_err(|err| format!("failed to read include '{}': {}", path.display(), err))
+			}
+
+			#[cfg(test)]
+			IncludeSource::Test(snippets) => snippets.iter()
+				.find(|&&(n, _)| n == name)
+				.map(|&(_, source)| source.to_owned())
+				.ok_or_else(|| format!("unknown include '{}'", name)),
+		}
+	}
+
+	/// Makes the next line report as `line` of source string `file_idx`
+	fn push_line_directive(&mut self, line: usize, file_idx: usize) {
+		// Before GLSL 3.30, #line sets the number of the directive itself rather than the line after it
+		let line = if self.glsl_version < 330 { line - 1 } else { line };
+		self.output.push_str(&format!("#line {} {}\n", line, file_idx));
+	}
+}
+
+
+fn parse_include_name(s: &str) -> Option<&str> {
+	let s = s.trim();
+	let s = s.strip_prefix('"')?.strip_suffix('"')?;
+	if s.is_empty() { None } else { Some(s) }
+}
+
+fn split_number(s: &str) -> Option<(u32, &str)> {
+	let digits_end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+	let number = s[..digits_end].parse().ok()?;
+	Some((number, &s[digits_end..]))
+}
+
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const SNIPPETS: &[(&str, &str)] = &[
+		("a.glsl", "float a() { return 1.0; }"),
+		("b.glsl", "#include \"c.glsl\"\nfloat b() { return c(); }"),
+		("c.glsl", "#include \"b.glsl\"\nfloat c() { return 2.0; }"),
+	];
+
+	fn run(source: &str, defines: &[(&str, &str)]) -> Result<Preprocessed, String> {
+		let defines = defines.iter()
+			.map(|&(name, value)| (name.to_owned(), value.to_owned()))
+			.collect(): Vec<_>;
+
+		preprocess(source, "root.glsl", &defines, IncludeSource::Test(SNIPPETS))
+	}
+
+	#[test]
+	fn expands_includes() {
+		let result = run("#version 330\n#include \"a.glsl\"\nvoid main() {}", &[]).unwrap();
+
+		assert_eq!(result.source, "#version 330\n#line 2 0\n#line 1 1\nfloat a() { return 1.0; }\n#line 3 0\nvoid main() {}\n");
+		assert_eq!(result.includes(), ["a.glsl"]);
+	}
+
+	#[test]
+	fn missing_include() {
+		let err = run("#version 330\n#include \"missing.glsl\"", &[]).err().unwrap();
+
+		assert!(err.starts_with("root.glsl:2:"), "{}", err);
+		assert!(err.contains("missing.glsl"), "{}", err);
+	}
+
+	#[test]
+	fn include_cycle_includes_each_once() {
+		let result = run("#version 330\n#include \"b.glsl\"\n#include \"root.glsl\"", &[]).unwrap();
+
+		assert_eq!(result.includes(), ["b.glsl", "c.glsl"]);
+		assert_eq!(result.source.matches("float b()").count(), 1);
+		assert_eq!(result.source.matches("float c()").count(), 1);
+	}
+
+	#[test]
+	fn injects_defines_after_version() {
+		let result = run("// header\n#version 130\nvoid main() {}", &[("FOO", "1"), ("BAR", "2")]).unwrap();
+
+		// Before GLSL 3.30 #line numbers the directive itself, so is one less than the next line
+		assert_eq!(result.source, "// header\n#version 130\n#define FOO 1\n#define BAR 2\n#line 2 0\nvoid main() {}\n");
+	}
+
+	fn error_locations(log: &str) -> Vec<(Option<String>, Option<u32>, String)> {
+		let preprocessed = Preprocessed {
+			source: String::new(),
+			files: vec!["root.glsl".to_owned(), "a.glsl".to_owned()],
+		};
+
+		preprocessed.parse_error_log(log).into_iter()
+			.map(|loc| (loc.file, loc.line, loc.message))
+			.collect()
+	}
+
+	#[test]
+	fn maps_nvidia_errors() {
+		assert_eq!(error_locations("1(12) : error C0000: syntax error"),
+			[(Some("a.glsl".to_owned()), Some(12), "error C0000: syntax error".to_owned())]);
+	}
+
+	#[test]
+	fn maps_mesa_errors() {
+		assert_eq!(error_locations("0:7(5): error: `x' undeclared\nERROR: 1:3: 'y' : undeclared identifier"), [
+			(Some("root.glsl".to_owned()), Some(7), "error: `x' undeclared".to_owned()),
+			(Some("a.glsl".to_owned()), Some(3), "'y' : undeclared identifier".to_owned()),
+		]);
+	}
+
+	#[test]
+	fn keeps_unparseable_lines() {
+		assert_eq!(error_locations("something went wrong\n5:1: bad file index"), [
+			(None, None, "something went wrong".to_owned()),
+			(None, None, "5:1: bad file index".to_owned()),
+		]);
+	}
+}
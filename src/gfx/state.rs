@@ -0,0 +1,69 @@
+use crate::prelude::*;
+use std::collections::HashMap;
+
+use super::core::{BlendMode, DepthFunc, StencilParams};
+
+
+/// GL state as last set through `Core`, so that redundant calls can be skipped.
+/// `None` means the state is unknown, e.g., because it hasn't been set yet, or because
+/// something changed it outside of `Core`
+#[derive(Default)]
+pub(super) struct StateCache {
+	pub(super) depth: Option<Option<DepthFunc>>,
+	pub(super) blend_mode: Option<BlendMode>,
+	pub(super) stencil: Option<Option<StencilParams>>,
+	pub(super) color_write: Option<bool>,
+	pub(super) viewport: Option<Vec2i>,
+
+	pub(super) program: Option<u32>,
+	pub(super) framebuffer: Option<u32>,
+
+	pub(super) active_texture_slot: Option<u32>,
+
+	/// Texture handles, keyed by slot and target
+	pub(super) textures: HashMap<(u32, u32), u32>,
+}
+
+impl StateCache {
+	/// Should be called after anything that binds or deletes textures without going through
+	/// the cache - creating, uploading to or destroying textures all bind to the active slot
+	pub(super) fn invalidate_textures(&mut self) {
+		self.textures.clear();
+	}
+}
+
+
+/// Counters for the work submitted to GL, reset with `Core::take_frame_stats`
+#[derive(Copy, Clone, Debug, Default)]
+pub struct FrameStats {
+	pub draw_calls: u32,
+
+	/// Shader, mesh, framebuffer and texture binds, and fixed function state actually sent to GL
+	pub state_changes: u32,
+
+	/// State changes skipped because the state was already set
+	pub redundant_state_changes: u32,
+
+	/// Vertex, index, texture and uniform buffer data
+	pub bytes_uploaded: usize,
+}
+
+impl FrameStats {
+	/// Counts a state change, returning whether it needs to be made
+	pub(super) fn record_state_change(&mut self, needed: bool) -> bool {
+		if needed {
+			self.state_changes += 1;
+		} else {
+			self.redundant_state_changes += 1;
+		}
+
+		needed
+	}
+}
+
+impl std::fmt::Display for FrameStats {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{} draw calls, {} state changes ({} redundant skipped), {} bytes uploaded",
+			self.draw_calls, self.state_changes, self.redundant_state_changes, self.bytes_uploaded)
+	}
+}
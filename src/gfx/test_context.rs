@@ -0,0 +1,59 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::window::HeadlessContext;
+use super::Gfx;
+
+
+// GL function pointers are global, and each context is made current on the thread that created
+// it, so tests that render must take turns
+static CONTEXT_LOCK: AtomicBool = AtomicBool::new(false);
+
+struct ContextLock;
+
+impl ContextLock {
+	fn acquire() -> ContextLock {
+		while CONTEXT_LOCK.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {
+			std::thread::yield_now();
+		}
+
+		ContextLock
+	}
+}
+
+impl Drop for ContextLock {
+	fn drop(&mut self) {
+		CONTEXT_LOCK.store(false, Ordering::Release);
+	}
+}
+
+
+/// A `Gfx` rendering into a headless context, for tests that need a GL context.
+/// Holds the context lock until dropped
+pub(crate) struct HeadlessGfx {
+	// Fields are dropped in order - Gfx before the context, and the context before the lock
+	pub gfx: Gfx,
+	_context: HeadlessContext,
+	_lock: ContextLock,
+}
+
+impl HeadlessGfx {
+	/// Returns None, so that the calling test can skip, if no context can be created,
+	/// e.g., on a machine with no GL drivers
+	pub fn new(test_name: &str) -> Option<HeadlessGfx> {
+		let lock = ContextLock::acquire();
+
+		let context = match HeadlessContext::new() {
+			Ok(context) => context,
+			Err(err) => {
+				eprintln!("Skipping {}: couldn't create headless GL context: {}", test_name, err);
+				return None
+			}
+		};
+
+		Some(HeadlessGfx {
+			gfx: Gfx::new(),
+			_context: context,
+			_lock: lock,
+		})
+	}
+}
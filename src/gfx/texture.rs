@@ -0,0 +1,115 @@
+use crate::prelude::*;
+use super::image::Image;
+use super::resource::ResourceKey;
+
+
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub struct TextureID(pub(super) ResourceKey);
+
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TextureFilter {
+	Nearest,
+	Linear,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TextureWrap {
+	Clamp,
+	Repeat,
+	MirroredRepeat,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct TextureSettings {
+	pub filter: TextureFilter,
+	pub wrap: TextureWrap,
+	pub mipmaps: bool,
+}
+
+impl Default for TextureSettings {
+	fn default() -> Self {
+		TextureSettings {
+			filter: TextureFilter::Linear,
+			wrap: TextureWrap::Repeat,
+			mipmaps: true,
+		}
+	}
+}
+
+impl TextureSettings {
+	/// Unfiltered and clamped, for pixel art and ui
+	pub fn pixel() -> Self {
+		TextureSettings {
+			filter: TextureFilter::Nearest,
+			wrap: TextureWrap::Clamp,
+			mipmaps: false,
+		}
+	}
+}
+
+
+
+pub(super) struct Texture {
+	pub(super) handle: u32,
+	pub(super) size: Vec2i,
+	settings: TextureSettings,
+}
+
+impl Texture {
+	pub(super) fn new(settings: TextureSettings) -> Texture {
+		let mut handle = 0;
+
+		unsafe {
+			gl::GenTextures(1, &mut handle);
+			gl::BindTexture(gl::TEXTURE_2D, handle);
+
+			let (min_filter, mag_filter) = match (settings.filter, settings.mipmaps) {
+				(TextureFilter::Nearest, false) => (gl::NEAREST, gl::NEAREST),
+				(TextureFilter::Nearest, true) => (gl::NEAREST_MIPMAP_NEAREST, gl::NEAREST),
+				(TextureFilter::Linear, false) => (gl::LINEAR, gl::LINEAR),
+				(TextureFilter::Linear, true) => (gl::LINEAR_MIPMAP_LINEAR, gl::LINEAR),
+			};
+
+			let wrap = match settings.wrap {
+				TextureWrap::Clamp => gl::CLAMP_TO_EDGE,
+				TextureWrap::Repeat => gl::REPEAT,
+				TextureWrap::MirroredRepeat => gl::MIRRORED_REPEAT,
+			};
+
+			gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, min_filter as _);
+			gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, mag_filter as _);
+			gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, wrap as _);
+			gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, wrap as _);
+		}
+
+		Texture {
+			handle,
+			size: Vec2i::zero(),
+			settings,
+		}
+	}
+
+	/// Uploads `image` such that uv (0, 0) samples its bottom left corner
+	pub(super) fn upload(&mut self, image: &Image) {
+		let pixels = image.flipped_rows();
+		self.size = image.size;
+
+		unsafe {
+			gl::BindTexture(gl::TEXTURE_2D, self.handle);
+			gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+			gl::TexImage2D(gl::TEXTURE_2D, 0, gl::RGBA8 as _, image.size.x, image.size.y, 0,
+				gl::RGBA, gl::UNSIGNED_BYTE, pixels.as_ptr() as *const _);
+
+			if self.settings.mipmaps {
+				gl::GenerateMipmap(gl::TEXTURE_2D);
+			}
+		}
+	}
+
+	pub(super) fn destroy(self) {
+		unsafe {
+			gl::DeleteTextures(1, &self.handle);
+		}
+	}
+}
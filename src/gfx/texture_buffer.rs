@@ -1,9 +1,10 @@
 
 use std::marker::PhantomData;
+use super::resource::ResourceKey;
 
 
 #[derive(Copy, Clone, Debug)]
-pub struct TextureBufferID<T: Copy>(pub(super) usize, pub(super) PhantomData<*const T>);
+pub struct TextureBufferID<T: Copy>(pub(super) ResourceKey, pub(super) PhantomData<*const T>);
 
 
 // Manual implementations required because of PhantomData
@@ -44,4 +45,11 @@ impl TextureBuffer {
 
 		TextureBuffer {texture_id, buffer_id}
 	}
+
+	pub(super) fn destroy(self) {
+		unsafe {
+			gl::DeleteTextures(1, &self.texture_id);
+			gl::DeleteBuffers(1, &self.buffer_id);
+		}
+	}
 }
\ No newline at end of file
@@ -0,0 +1,51 @@
+use std::marker::PhantomData;
+use super::resource::ResourceKey;
+
+
+/// The block every shader gets bound to automatically, see `gfx::camera::CameraUniforms`
+pub const CAMERA_BLOCK_NAME: &str = "CameraBlock";
+pub const CAMERA_BLOCK_BINDING: u32 = 0;
+
+
+#[derive(Copy, Clone, Debug)]
+pub struct UniformBufferID<T: Copy>(pub(super) ResourceKey, pub(super) PhantomData<*const T>);
+
+
+// Manual implementations required because of PhantomData
+// see: https://github.com/rust-lang/rust/issues/26925
+impl<T: Copy> std::hash::Hash for UniformBufferID<T> {
+	#[inline]
+	fn hash<H: std::hash::Hasher>(&self, h: &mut H) { self.0.hash(h) }
+}
+
+impl<T: Copy> std::cmp::PartialEq for UniformBufferID<T> {
+    fn eq(&self, o: &UniformBufferID<T>) -> bool { self.0.eq(&o.0) }
+}
+
+impl<T: Copy> std::cmp::Eq for UniformBufferID<T> {}
+
+
+
+/// Backing storage for a uniform block. The contents are expected to match the std140
+/// layout of the block they're bound to
+pub(super) struct UniformBuffer {
+	pub(super) handle: u32,
+}
+
+impl UniformBuffer {
+	pub(super) fn new() -> UniformBuffer {
+		let mut handle = 0;
+
+		unsafe {
+			gl::GenBuffers(1, &mut handle);
+		}
+
+		UniformBuffer {handle}
+	}
+
+	pub(super) fn destroy(self) {
+		unsafe {
+			gl::DeleteBuffers(1, &self.handle);
+		}
+	}
+}
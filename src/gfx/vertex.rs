@@ -6,11 +6,59 @@ pub trait Vertex : Copy {
 } 
 
 
-struct AttributeBinding {
-	position: u32,
-	components: u32, // assume floats
+/// How an attribute is stored in the vertex buffer and how the shader sees it
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AttributeType {
+	F32,
+
+	/// Unsigned bytes/shorts mapped to [0, 1] floats in the shader, e.g., for packed colours
+	U8Normalized,
+	U16Normalized,
+
+	/// Integer attributes, read with `in ivec`/`in uvec` in the shader
+	U8,
+	U16,
+	I32,
 }
 
+impl AttributeType {
+	fn size(self) -> usize {
+		match self {
+			AttributeType::F32 | AttributeType::I32 => 4,
+			AttributeType::U8Normalized | AttributeType::U8 => 1,
+			AttributeType::U16Normalized | AttributeType::U16 => 2,
+		}
+	}
+
+	fn gl_type(self) -> u32 {
+		match self {
+			AttributeType::F32 => gl::FLOAT,
+			AttributeType::U8Normalized | AttributeType::U8 => gl::UNSIGNED_BYTE,
+			AttributeType::U16Normalized | AttributeType::U16 => gl::UNSIGNED_SHORT,
+			AttributeType::I32 => gl::INT,
+		}
+	}
+
+	pub(super) fn is_integer(self) -> bool {
+		matches!(self, AttributeType::U8 | AttributeType::U16 | AttributeType::I32)
+	}
+
+	fn is_normalized(self) -> bool {
+		matches!(self, AttributeType::U8Normalized | AttributeType::U16Normalized)
+	}
+}
+
+
+#[derive(Copy, Clone, Debug)]
+pub(super) struct AttributeBinding {
+	pub(super) position: u32,
+	pub(super) components: u32,
+	pub(super) ty: AttributeType,
+}
+
+/// Describes the layout of a vertex type. Attributes are expected to be laid out in the
+/// order they are added, without padding between them
+#[derive(Clone)]
 pub struct Descriptor {
 	bindings: Vec<AttributeBinding>,
 	stride: usize,
@@ -22,40 +70,84 @@ impl Descriptor {
 	}
 
 	pub fn from(comps: &[u32]) -> Self {
-		let mut bindings = Vec::with_capacity(comps.len());
+		comps.iter().enumerate()
+			.fold(Descriptor::new(), |desc, (i, &cs)| desc.add(i as u32, cs))
+	}
 
-		for (i, &cs) in comps.iter().enumerate() {
-			bindings.push(AttributeBinding{
-				position: i as u32,
-				components: cs
-			});
-		}
+	pub fn add(self, position: u32, components: u32) -> Self {
+		self.add_typed(position, components, AttributeType::F32)
+	}
 
-		let stride = comps.iter().sum::<u32>() as usize * 4;
+	pub fn add_typed(mut self, position: u32, components: u32, ty: AttributeType) -> Self {
+		self.bindings.push(AttributeBinding{ position, components, ty });
+		self.stride += components as usize * ty.size();
+		self
+	}
+
+	pub(super) fn bindings(&self) -> &[AttributeBinding] {
+		&self.bindings
+	}
 
-		Descriptor{ bindings, stride }
+	/// One past the highest attribute position, i.e., where attributes added after these can start
+	pub(super) fn location_count(&self) -> u32 {
+		self.bindings.iter().map(|b| b.position + 1).max().unwrap_or(0)
 	}
 
-	pub fn add(mut self, position: u32, components: u32) -> Self {
-		self.bindings.push(AttributeBinding{ position, components });
-		self.stride += components as usize * 4;
+	/// Shifts all attribute positions up by `base`, e.g., to place instance attributes after
+	/// the attributes of the mesh they're drawn with
+	pub(super) fn offset_positions(mut self, base: u32) -> Self {
+		for binding in self.bindings.iter_mut() {
+			binding.position += base;
+		}
+
 		self
 	}
 
-	pub fn bind(&self) {
+	/// Enables and specifies all attributes. Only needs to happen once per vertex array,
+	/// with the vertex array and vertex buffer bound. `stride` should be the size of the vertex
+	/// type, which may include trailing padding not accounted for by the bindings
+	pub(super) fn configure(&self, stride: usize) {
+		self.configure_with_divisor(stride, 0);
+	}
+
+	/// Like `configure`, but for attributes that advance once per instance rather than per vertex
+	pub(super) fn configure_instanced(&self, stride: usize) {
+		self.configure_with_divisor(stride, 1);
+	}
+
+	fn configure_with_divisor(&self, stride: usize, divisor: u32) {
+		assert!(stride >= self.stride, "Vertex descriptor is larger than vertex type");
+
 		let mut offset = 0usize;
 
 		for binding in self.bindings.iter() {
 			unsafe {
-				gl::VertexAttribPointer(
-					binding.position,
-					binding.components as _,
-					gl::FLOAT, 0,
-					self.stride as _,
-					offset as *const _);			
+				gl::EnableVertexAttribArray(binding.position);
+
+				if binding.ty.is_integer() {
+					gl::VertexAttribIPointer(
+						binding.position,
+						binding.components as _,
+						binding.ty.gl_type(),
+						stride as _,
+						offset as *const _);
+				} else {
+					gl::VertexAttribPointer(
+						binding.position,
+						binding.components as _,
+						binding.ty.gl_type(),
+						binding.ty.is_normalized() as _,
+						stride as _,
+						offset as *const _);
+				}
+
+				// Zero is the default, and VertexAttribDivisor needs GL 3.3 or ARB_instanced_arrays
+				if divisor != 0 {
+					gl::VertexAttribDivisor(binding.position, divisor);
+				}
 			}
 			
-			offset += binding.components as usize * 4;
+			offset += binding.components as usize * binding.ty.size();
 		}
 	}
 }
@@ -10,13 +10,32 @@ pub mod view;
 pub mod nav;
 pub mod holo_volume;
 pub mod player_controller;
+pub mod commands;
+pub mod console;
+pub mod debug_overlay;
+pub mod recorder;
+pub mod scene_mesh;
+pub mod camera_path;
 
 use prelude::*;
 
+pub const PROJECT_PATH: &str = "assets/navtest.toy";
+pub const BAKED_NAV_PATH: &str = "assets/navtest.nav";
+
+/// Camera rail played on startup, if the scene has one
+pub const INTRO_RAIL: &str = "intro";
+
 fn main() -> Result<(), Box<dyn Error>> {
+	let args = std::env::args().skip(1).collect(): Vec<_>;
+	if let Some(command) = args.first().filter(|a| !a.starts_with("--")) {
+		return commands::run(command, &args[1..]);
+	}
+
+	let mut recording_settings = recorder::RecordingSettings::from_args(&args)?;
+
 	let mut window = window::Window::new().expect("Failed to create window");
 	let mut gfx = gfx::Gfx::new();
-	// let mut mouse_pos = Vec2::zero();
+	let mut mouse_pos = Vec2::zero();
 
 	window.set_cursor_capture(true);
 
@@ -27,13 +46,13 @@ fn main() -> Result<(), Box<dyn Error>> {
 
 	camera.set_near_far(0.1, 1000.0);
 
-	let scene_shader = gfx.core.new_shader(
-		include_str!("shaders/fog_vert.glsl"),
-		include_str!("shaders/fog_frag.glsl"),
+	let scene_shader = gfx.core.new_shader::<SceneVertex>(
+		crate::shader_source!("shaders/fog_vert.glsl"),
+		crate::shader_source!("shaders/fog_frag.glsl"),
 		&["a_vertex", "a_color", "a_emission"]
-	);
+	)?;
 
-	let project_data = std::fs::read("assets/navtest.toy")?;
+	let project_data = std::fs::read(PROJECT_PATH)?;
 	let project = toy::load(&project_data)?;
 	let scene = project.find_scene("main")
 		.expect("Couldn't find scene 'main'");
@@ -43,12 +62,9 @@ fn main() -> Result<(), Box<dyn Error>> {
 		camera.set_yaw(ent.rotation.yaw());
 	}
 
-	let scene_static_mesh = build_scene_mesh(&mut gfx.core, scene);
+	let mut scene_mesh = scene_mesh::SceneMesh::build(&mut gfx.core, scene);
 
-	let nav_mesh = {
-		let nav_ent = scene.entities().find(|e| e.name.starts_with("NAV_")).expect("can't find nav");
-		nav::NavMesh::from_entity(nav_ent)
-	};
+	let nav_mesh = load_nav_mesh(scene);
 
 	// println!("nav mesh {:#?}", nav_mesh);
 
@@ -76,7 +92,16 @@ fn main() -> Result<(), Box<dyn Error>> {
 	}
 
 
+	let camera_rails = camera_path::CameraRail::load_all(scene);
+
+	// The player can't move or look around while a cutscene is playing
+	let mut cutscene = camera_rails.get(INTRO_RAIL)
+		.map(|rail| camera_path::Cutscene::new(rail.clone(), &camera));
+	let mut skip_cutscene = false;
+
 	let mut player_controller = player_controller::PlayerController::new();
+	let mut debug_overlay = debug_overlay::DebugOverlay::load();
+	let mut console = console::Console::new();
 
 	let mut view_screen_view = view::ViewScreen::new(&mut gfx.core, &project);
 	let mut lab_view = view::Lab::new(&mut gfx.core, &project);
@@ -86,8 +111,24 @@ fn main() -> Result<(), Box<dyn Error>> {
 	let mut prev_capture_mouse = true;
 	let mut capture_mouse = true;
 
+	// Buffers to capture at the end of the frame
+	let mut screenshot_request: Option<Vec<gfx::screenshot::CaptureBuffer>> = None;
+
+	let mut recorder: Option<recorder::Recorder> = None;
+	let mut toggle_recording = false;
+
+	let mut prev_frame_time = std::time::Instant::now();
+
+	// GL work submitted during the previous frame
+	let mut frame_stats = gfx::state::FrameStats::default();
+	let mut cull_stats = scene_mesh::CullStats::default();
+
 	while running {
 		let window_size = window.size();
+
+		if let Some(settings) = recording_settings.take() {
+			recorder = Some(recorder::Recorder::start(settings, window_size)?);
+		}
 		let window_focussed = window.focussed();
 
 		window.poll_events(|event| {
@@ -99,7 +140,7 @@ fn main() -> Result<(), Box<dyn Error>> {
 				}
 
 				match event {
-					DeviceEvent::MouseMotion{delta} if capture_mouse => {
+					DeviceEvent::MouseMotion{delta} if capture_mouse && cutscene.is_none() => {
 						let pitch_limit = PI/2.0;
 
 						let (delta_yaw, delta_pitch) = delta;
@@ -122,6 +163,10 @@ fn main() -> Result<(), Box<dyn Error>> {
 
 							Some(VirtualKeyCode::V) if down => player_controller.toggle_fly_mode(),
 
+							Some(VirtualKeyCode::Space) if down => {
+								skip_cutscene = true;
+							}
+
 							Some(VirtualKeyCode::Escape) => {
 								running = false;
 							}
@@ -151,6 +196,23 @@ fn main() -> Result<(), Box<dyn Error>> {
 								}
 							}
 
+							Some(VirtualKeyCode::F8) if down => gfx.core.profiler().toggle_overlay(),
+
+							Some(VirtualKeyCode::F9) if down => match gfx.core.profiler().save_trace() {
+								Ok(path) => println!("Saved profiler trace to '{}'", path.display()),
+								Err(err) => eprintln!("Failed to save profiler trace: {}", err),
+							}
+
+							Some(VirtualKeyCode::F11) if down => {
+								toggle_recording = true;
+							}
+
+							Some(VirtualKeyCode::F12) if down => {
+								screenshot_request = Some(vec![gfx::screenshot::CaptureBuffer::Color]);
+							}
+
+							Some(key) if down => { debug_overlay.handle_key(key); }
+
 							_ => {}
 						}
 					}
@@ -164,145 +226,224 @@ fn main() -> Result<(), Box<dyn Error>> {
 						running = false;
 					}
 
+					WindowEvent::CursorMoved{position, ..} => {
+						mouse_pos = Vec2::new(position.x as f32, position.y as f32);
+					}
+
 					_ => {}
 				}
 			}
 		});
 
-		if capture_mouse != prev_capture_mouse {
-			window.set_cursor_capture(capture_mouse);
-			prev_capture_mouse = capture_mouse;
-		}
+		while let Some(command) = console.poll() {
+			let result = match command.split_whitespace().next() {
+				Some("screenshot") => parse_screenshot_command(&command)
+					.map(|buffers| screenshot_request = Some(buffers)),
 
-		gfx.core.set_viewport(window_size);
-		gfx.core.set_bg_color(Color::grey(0.1));
-		gfx.core.clear();
+				Some("stats") => {
+					println!("{}", frame_stats);
+					println!("{}", cull_stats);
+					Ok(())
+				}
 
-		camera.update(window_size);
-		player_controller.update(&mut camera, &nav_mesh);
+				Some(cmd @ "hide") | Some(cmd @ "show") => match command.split_whitespace().nth(1) {
+					Some(name) if scene_mesh.set_hidden(name, cmd == "hide") => Ok(()),
+					Some(name) => Err(format!("No scene entity named '{}'", name)),
+					None => Err(format!("Usage: {} <entity name>", cmd)),
+				}
 
-		gfx.core.use_shader(scene_shader);
-		gfx.core.set_uniform_mat4("u_proj_view", &camera.projection_view());
-		gfx.core.set_uniform_mat4("u_view", &camera.view_matrix());
+				Some("cutscene") => match command.split_whitespace().nth(1) {
+					Some(name) => camera_rails.get(name)
+						.map(|rail| cutscene = Some(camera_path::Cutscene::new(rail.clone(), &camera)))
+						.ok_or_else(|| format!("No camera rail named '{}'", name)),
 
-		gfx.core.set_blend_mode(gfx::core::BlendMode::None);
-		gfx.core.draw_mesh(scene_static_mesh);
+					None => {
+						let mut names = camera_rails.keys().map(String::as_str).collect(): Vec<_>;
+						names.sort_unstable();
+						println!("camera rails: {}", names.join(", "));
+						Ok(())
+					}
+				}
 
-		// draw_nav_mesh(&mut gfx.debug, &nav_mesh);
-		// draw_nav_intersect(&mut gfx.debug, &nav_mesh, &camera, player_controller.nav_face());
+				_ => debug_overlay.run_command(&command),
+			};
 
-		// for teleporter in teleporters.values() {
-		// 	gfx.debug.point(teleporter.pos, Color::rgb(1.0, 0.0, 1.0));
+			if let Err(err) = result {
+				eprintln!("{}", err);
+			}
+		}
 
-		// 	let target = teleporters.get(&teleporter.target)
-		// 		.expect("Teleporter missing target!");
+		if toggle_recording {
+			toggle_recording = false;
 
-		// 	gfx.debug.line(teleporter.pos, target.pos, Color::rgb(0.7, 0.0, 0.7));
-		// }
+			match recorder.take() {
+				Some(active) => if let Err(err) = active.finish() {
+					eprintln!("Failed to finish recording: {}", err);
+				}
 
-		gfx.anim.draw(&mut gfx.core, &camera);
-		gfx.anim.clear();
+				None => match recorder::Recorder::start(recorder::RecordingSettings::new(), window_size) {
+					Ok(new_recorder) => recorder = Some(new_recorder),
+					Err(err) => eprintln!("Failed to start recording: {}", err),
+				}
+			}
+		}
 
-		view_screen_view.draw(&mut gfx.core, &camera);
-		lab_view.draw(&mut gfx.core);
+		// While recording, step by a fixed amount per frame so captures play back at the right speed
+		let now = std::time::Instant::now();
+		let dt = match &recorder {
+			Some(recorder) => recorder.dt(),
+			None => (now - prev_frame_time).as_secs_f32().min(0.1),
+		};
+		prev_frame_time = now;
 
-		gfx.debug.draw(&mut gfx.core, &camera);
+		if capture_mouse != prev_capture_mouse {
+			window.set_cursor_capture(capture_mouse);
+			prev_capture_mouse = capture_mouse;
+		}
 
-		window.swap();
-	}
+		gfx.core.profiler().begin_frame();
 
-	Ok(())
-}
+		gfx.core.poll_shader_changes();
 
-// fn window_to_screen(window_size: Vec2i, pos: Vec2) -> Vec2 {
-// 	let window_half = window_size.to_vec2() / 2.0;
-// 	(pos - window_half) / window_half * Vec2::new(1.0, -1.0)
-// }
+		gfx.core.set_viewport(window_size);
+		gfx.core.set_bg_color(Color::grey(0.1));
+		gfx.core.clear();
 
+		gfx.core.profiler().begin_scope("update");
+		camera.update(window_size);
 
+		if let Some(active) = &mut cutscene {
+			if std::mem::take(&mut skip_cutscene) {
+				active.skip(&camera);
+			}
 
-fn draw_nav_mesh(debug: &mut gfx::debug::Debug, nav: &nav::NavMesh) {
-	for v in nav.vertices.iter() {
-		debug.point(v.position, Color::rgb(1.0, 0.0, 1.0));
-	}
+			active.update(&mut camera, dt);
 
-	for &nav::NavFace{start_edge, center, ..} in nav.faces.iter() {
-		debug.point(center, Color::rgb(1.0, 1.0, 0.5));
+			if active.is_finished() {
+				cutscene = None;
+			}
+		} else {
+			skip_cutscene = false;
+			player_controller.update(&mut camera, &nav_mesh, dt);
+		}
+		gfx.core.profiler().end_scope();
 
-		for (edge_idx, edge) in nav.iter_edge_loop(start_edge) {
-			let (pos_a, pos_b) = nav.edge_vertex_positions(edge_idx);
+		gfx.core.profiler().begin_scope("scene");
+		gfx.core.set_camera(&camera);
 
-			let pos_a = (0.1).ease_linear(pos_a, center);
-			let pos_b = (0.1).ease_linear(pos_b, center);
+		cull_stats = scene_mesh.submit(&mut gfx.core, &mut gfx.queue, scene_shader, &camera);
+		lab_view.submit(&mut gfx.queue, scene_shader, &camera);
+		gfx.queue.execute(&mut gfx.core);
+		gfx.core.profiler().end_scope();
 
-			let edge_dir = (pos_b - pos_a).normalize();
+		let teleporter_links = teleporters.values()
+			.map(|teleporter| {
+				let target = teleporters.get(&teleporter.target)
+					.expect("Teleporter missing target!");
 
-			let edge_col = if edge.twin.is_some() {
-				Color::rgba(0.2, 0.6, 0.3, 0.5)
-			} else {
-				Color::rgba(0.5, 0.2, 0.2, 0.5)
-			};
+				(teleporter.pos, target.pos)
+			});
 
-			debug.line(pos_a, pos_b, edge_col);
-			debug.line(pos_b, pos_b - edge_dir * 0.1 + Vec3::from_y(0.1), edge_col);
-			debug.line(pos_b, pos_b - edge_dir * 0.1 - Vec3::from_y(0.1), edge_col);
-		}
-	}
-}
+		// Pick through the cursor when it's free, or the middle of the screen while looking around
+		let pick_ray = if capture_mouse {
+			camera.center_ray()
+		} else {
+			camera.ray_from_window(mouse_pos)
+		};
 
+		debug_overlay.draw(&mut gfx.debug, &nav_mesh, &scene_mesh, &camera, &player_controller, &pick_ray, teleporter_links);
 
-fn draw_nav_intersect(debug: &mut gfx::debug::Debug, nav: &nav::NavMesh, camera: &gfx::camera::Camera, player_nav_face: Option<usize>) {
-	let cam_pos = camera.position();
-	let cam_down = Vec3::from_y(-1.0);
+		gfx.core.profiler().begin_scope("animation");
+		gfx.anim.draw(&mut gfx.core);
+		gfx.anim.clear();
+		gfx.core.profiler().end_scope();
+
+		gfx.core.profiler().begin_scope("view_screen");
+		view_screen_view.draw(&mut gfx.core, &camera, dt);
+		gfx.core.profiler().end_scope();
+
+		gfx.core.profiler().begin_scope("debug");
+		gfx.core.profiler().draw_overlay(&mut gfx.debug);
+		gfx.debug.submit(&mut gfx.core, &mut gfx.queue);
+		gfx.queue.execute(&mut gfx.core);
+		gfx.core.profiler().end_scope();
+
+		if let Some(buffers) = screenshot_request.take() {
+			match gfx::screenshot::save_screenshot(&mut gfx.core, &buffers) {
+				Ok(paths) => for path in paths {
+					println!("Saved screenshot to '{}'", path.display());
+				}
 
-	if let Some(face_idx) = player_nav_face {
-		let face = &nav.faces[face_idx];
+				Err(err) => eprintln!("Failed to save screenshot: {}", err),
+			}
+		}
 
-		let intersect = match util::intersect_plane(face.plane, cam_pos, cam_down) {
-			Some(intersect) => intersect,
-			None => return
-		};
+		if let Some(active) = &mut recorder {
+			let result = active.capture_frame(&mut gfx.core);
 
-		debug.line(intersect, face.center, Color::rgb(0.6, 1.0, 0.4));
+			if result.is_err() || active.is_finished() {
+				if let Err(err) = result {
+					eprintln!("Failed to capture frame: {}", err);
+				}
 
-		for (edge_idx, edge) in nav.iter_edge_loop(face.start_edge) {
-			if edge.twin.is_none() {
-				let (va, vb) = nav.edge_vertex_positions(edge_idx);
-				debug.line(va + Vec3::from_y(0.1), vb + Vec3::from_y(0.1), Color::rgb(0.0, 1.0, 0.7));
+				if let Err(err) = recorder.take().unwrap().finish() {
+					eprintln!("Failed to finish recording: {}", err);
+				}
 			}
+		}
 
-			let vertex = &nav.vertices[edge.vertex];
-			if vertex.outgoing_barrier.is_none() { continue }
+		gfx.core.profiler().end_frame();
+		frame_stats = gfx.core.take_frame_stats();
 
-			let outgoing_barrier_idx = vertex.outgoing_barrier.unwrap();
-			let outgoing_barrier = &nav.edges[outgoing_barrier_idx];
+		window.swap();
+	}
 
+	// Release GPU resources while the context is still alive
+	view_screen_view.destroy(&mut gfx.core);
+	lab_view.destroy(&mut gfx.core);
+	scene_mesh.destroy(&mut gfx.core);
+	gfx.core.destroy_shader(scene_shader);
 
-			let mut prev_incoming_edge_idx = outgoing_barrier.prev;
-			let mut prev_incoming_edge = &nav.edges[prev_incoming_edge_idx];
+	if let Some(recorder) = recorder {
+		recorder.finish()?;
+	}
 
-			while let Some(twin_idx) = prev_incoming_edge.twin {
-				prev_incoming_edge_idx = nav.edges[twin_idx].prev;
-				prev_incoming_edge = &nav.edges[prev_incoming_edge_idx];
-			}
+	Ok(())
+}
 
+/// Handles console commands of the form `screenshot [all | color | depth | stencil]...`
+fn parse_screenshot_command(command: &str) -> Result<Vec<gfx::screenshot::CaptureBuffer>, String> {
+	use gfx::screenshot::CaptureBuffer;
 
-			let incoming_normal = nav.projected_edge_normal(prev_incoming_edge_idx);
-			let outgoing_normal = nav.projected_edge_normal(outgoing_barrier_idx);
+	let args = command.split_whitespace().skip(1).collect(): Vec<_>;
 
-			if incoming_normal.perp().dot(outgoing_normal) <= 0.0 {
-				let (va, vb) = nav.edge_vertex_positions(prev_incoming_edge_idx);
-				debug.line(va, vb, Color::rgb(1.0, 0.3, 0.5));
+	match args.as_slice() {
+		[] => Ok(vec![CaptureBuffer::Color]),
+		["all"] => Ok(CaptureBuffer::ALL.to_vec()),
+		names => names.iter()
+			.map(|&name| CaptureBuffer::from_name(name)
+				.ok_or_else(|| format!("Unknown buffer '{}'. Usage: screenshot [all | color | depth | stencil]...", name)))
+			.collect(),
+	}
+}
 
-				let (va, vb) = nav.edge_vertex_positions(outgoing_barrier_idx);
-				debug.line(va, vb, Color::rgb(1.0, 0.3, 0.5));
-			}
+/// Prefers the nav mesh baked by `skelet bake-nav`, falling back to building it from
+/// the scene with the default params if the baked version is missing or out of date.
+/// Baked meshes are validated against the params they were baked with
+fn load_nav_mesh(scene: toy::SceneRef<'_>) -> nav::NavMesh {
+	let source = commands::nav_source(scene);
+
+	match nav::NavMesh::load(BAKED_NAV_PATH, |params| source.hash(params)) {
+		Ok(nav_mesh) => nav_mesh,
+		Err(err) => {
+			eprintln!("Couldn't use baked nav mesh '{}': {}", BAKED_NAV_PATH, err);
+			eprintln!("Building nav mesh from scene - run `skelet bake-nav` to skip this");
+			source.build(&nav::bake::BakeParams::default())
 		}
 	}
 }
 
 
-
 #[repr(C)]
 #[derive(Copy, Clone, Debug)]
 pub struct SceneVertex {
@@ -326,48 +467,59 @@ impl gfx::vertex::Vertex for SceneVertex {
 
 
 
+/// Entities that make up the static geometry of a scene - everything with a mesh that isn't
+/// hidden (`_` prefixed) or special (`PREFIX_` named, e.g. NAV_, TP_, HOLO_)
+pub fn static_scene_entities<'t>(scene: toy::SceneRef<'t>) -> impl Iterator<Item=toy::EntityRef<'t>> {
+	scene.entities()
+		.filter(|entity| !entity.name.starts_with('_'))
+		.filter(|entity| {
+			// Skip special entities
+			match entity.name.split_once('_') {
+				Some((prefix, _)) => !prefix.chars().all(|c| c.is_ascii_uppercase()),
+				None => true,
+			}
+		})
+		.filter(|entity| entity.mesh_data().is_some())
+}
+
+
 pub fn build_scene_mesh(core: &mut gfx::core::Core, scene: toy::SceneRef<'_>) -> gfx::mesh::MeshID<SceneVertex> {
 	let mesh_id = core.new_mesh();
 	let mut mb = gfx::mesh_builder::MeshBuilder::new(mesh_id);
 
-	for entity in scene.entities() {
-		if entity.name.starts_with('_') { continue }
-
-		// Skip special entities
-		if let Some((prefix, _)) = entity.name.split_once('_') {
-			if prefix.chars().all(|c| c.is_ascii_uppercase()) {
-				continue;
-			}
-		}
+	for entity in static_scene_entities(scene) {
+		let verts = scene_entity_vertices(&entity);
+		mb.add_geometry(&verts, &entity.mesh_data().unwrap().indices);
+	}
 
-		let mesh_data = match entity.mesh_data() {
-			Some(md) => md,
-			None => continue,
-		};
+	mb.commit(core);
+	mesh_id
+}
 
-		let transform = entity.transform();
 
-		let color_data = if let Some(color_data) = mesh_data.color_data(toy::DEFAULT_COLOR_DATA_NAME) {
-			either::Either::Left(color_data.data.iter().cloned())
-		} else {
-			either::Either::Right(std::iter::repeat(Vec4::splat(1.0)))
-		};
+/// World space vertices for one of `static_scene_entities`, to go with its mesh's indices
+pub fn scene_entity_vertices(entity: &toy::EntityRef<'_>) -> Vec<SceneVertex> {
+	scene_mesh_vertices(entity.mesh_data().unwrap(), &entity.transform())
+}
 
-		let emission_data = if let Some(color_data) = mesh_data.color_data("emission") {
-			either::Either::Left(color_data.data.iter().map(|v| v.x))
-		} else {
-			either::Either::Right(std::iter::repeat(0.0))
-		};
 
-		let verts = mesh_data.positions.iter()
-			.zip(color_data)
-			.zip(emission_data)
-			.map(|((&pos, color), emission)| SceneVertex::new(transform * pos, color, emission))
-			.collect(): Vec<_>;
+/// Vertices of `mesh_data` transformed by `transform`, to go with its indices
+pub fn scene_mesh_vertices(mesh_data: &toy::MeshData, transform: &Mat4) -> Vec<SceneVertex> {
+	let color_data = if let Some(color_data) = mesh_data.color_data(toy::DEFAULT_COLOR_DATA_NAME) {
+		either::Either::Left(color_data.data.iter().cloned())
+	} else {
+		either::Either::Right(std::iter::repeat(Vec4::splat(1.0)))
+	};
 
-		mb.add_geometry(&verts, &mesh_data.indices);
-	}
+	let emission_data = if let Some(color_data) = mesh_data.color_data("emission") {
+		either::Either::Left(color_data.data.iter().map(|v| v.x))
+	} else {
+		either::Either::Right(std::iter::repeat(0.0))
+	};
 
-	mb.commit(core);
-	mesh_id
+	mesh_data.positions.iter()
+		.zip(color_data)
+		.zip(emission_data)
+		.map(|((&pos, color), emission)| SceneVertex::new(*transform * pos, color, emission))
+		.collect()
 }
\ No newline at end of file
@@ -1,4 +1,9 @@
 use crate::prelude::*;
+use crate::util::Ray;
+
+pub mod serialize;
+pub mod bake;
+pub mod export;
 
 
 pub type NavFaceID = usize;
@@ -45,29 +50,49 @@ impl NavMesh {
 			.expect("entity passed to NavMesh missing mesh data");
 
 		let transform = entity.transform();
-		let vertices = mesh_data.positions.iter()
-			.map(|&pos| NavVertex {
-				position: transform * pos,
+		let positions = mesh_data.positions.iter()
+			.map(|&pos| transform * pos)
+			.collect(): Vec<_>;
+
+		NavMesh::from_triangles(&positions, &mesh_data.indices[..])
+	}
+
+	/// Builds a nav mesh from a triangle list. Triangles are expected to wind CCW when viewed from above.
+	/// Adjacent coplanar triangles are merged into convex polygons to keep the face count down
+	pub fn from_triangles<I: Copy + Into<usize>>(positions: &[Vec3], indices: &[I]) -> NavMesh {
+		let triangles = indices.chunks(3)
+			.map(|tri| vec![tri[0].into(), tri[1].into(), tri[2].into()])
+			.collect(): Vec<_>;
+
+		let polygons = merge_coplanar_polygons(positions, triangles);
+		NavMesh::from_polygons(positions, &polygons)
+	}
+
+	/// Builds a nav mesh from a list of convex polygons, each wound CCW when viewed from above
+	pub fn from_polygons(positions: &[Vec3], polygons: &[Vec<usize>]) -> NavMesh {
+		let vertices = positions.iter()
+			.map(|&position| NavVertex {
+				position,
 				outgoing_edge: 0,
 				outgoing_barrier: None
 			})
 			.collect(): Vec<_>;
 
-		let mut edges = Vec::with_capacity(mesh_data.indices.len());
-		let mut faces = Vec::with_capacity(mesh_data.indices.len() / 3);
+		let edge_count = polygons.iter().map(Vec::len).sum();
+		let mut edges = Vec::with_capacity(edge_count);
+		let mut faces = Vec::with_capacity(polygons.len());
 
-		for triangle in mesh_data.indices.chunks(3) {
+		for polygon in polygons.iter() {
 			let start_edge = edges.len();
 			let face = faces.len();
+			let num_edges = polygon.len();
 
-			let points = [
-				vertices[triangle[0] as usize].position,
-				vertices[triangle[1] as usize].position,
-				vertices[triangle[2] as usize].position,
-			];
+			let points = polygon.iter()
+				.map(|&v| vertices[v].position)
+				.collect(): Vec<_>;
 
-			let plane = Plane::from_points(points[0], points[1], points[2]);
-			let center = points.iter().sum(): Vec3 / 3.0;
+			let plane = polygon_plane(&points);
+			let center = points.iter().sum(): Vec3 / num_edges as f32;
 
 			faces.push(NavFace {
 				start_edge,
@@ -75,29 +100,15 @@ impl NavMesh {
 				center,
 			});
 
-			edges.push(NavHalfEdge {
-				vertex: triangle[0] as usize,
-				next: start_edge+1,
-				prev: start_edge+2,
-				twin: None,
-				face,
-			});
-
-			edges.push(NavHalfEdge {
-				vertex: triangle[1] as usize,
-				next: start_edge+2,
-				prev: start_edge,
-				twin: None,
-				face,
-			});
-
-			edges.push(NavHalfEdge {
-				vertex: triangle[2] as usize,
-				next: start_edge,
-				prev: start_edge+1,
-				twin: None,
-				face,
-			});
+			for (idx, &vertex) in polygon.iter().enumerate() {
+				edges.push(NavHalfEdge {
+					vertex,
+					next: start_edge + (idx + 1) % num_edges,
+					prev: start_edge + (idx + num_edges - 1) % num_edges,
+					twin: None,
+					face,
+				});
+			}
 		}
 
 		let mut nav_mesh = NavMesh {
@@ -225,5 +236,270 @@ impl NavMesh {
 
 		true
 	}
+
+	/// Finds the nearest face hit by `ray` and the distance to it
+	pub fn raycast(&self, ray: &Ray) -> Option<(NavFaceID, f32)> {
+		self.faces.iter().enumerate()
+			.filter_map(|(face_idx, face)| {
+				let points = self.iter_edge_loop_vertices(face.start_edge)
+					.map(|(_, vertex)| vertex.position)
+					.collect(): Vec<_>;
+
+				// Faces are convex, so can be treated as triangle fans
+				(1..points.len()-1)
+					.filter_map(|idx| ray.intersect_triangle(points[0], points[idx], points[idx+1]))
+					.min_by_key(|t| t.ordify())
+					.map(|t| (face_idx, t))
+			})
+			.min_by_key(|(_, t)| t.ordify())
+	}
+
+	/// Finds the pair of barrier edges (incoming, outgoing) that meet at `vertex_idx`,
+	/// if the vertex lies on the boundary of the mesh
+	pub fn vertex_barriers(&self, vertex_idx: NavVertexID) -> Option<(NavEdgeID, NavEdgeID)> {
+		let outgoing_barrier_idx = self.vertices[vertex_idx].outgoing_barrier?;
+		let outgoing_barrier = &self.edges[outgoing_barrier_idx];
+
+		let mut prev_incoming_edge_idx = outgoing_barrier.prev;
+		let mut prev_incoming_edge = &self.edges[prev_incoming_edge_idx];
+
+		while let Some(twin_idx) = prev_incoming_edge.twin {
+			prev_incoming_edge_idx = self.edges[twin_idx].prev;
+			prev_incoming_edge = &self.edges[prev_incoming_edge_idx];
+		}
+
+		Some((prev_incoming_edge_idx, outgoing_barrier_idx))
+	}
+
+	/// Whether the barriers meeting at `vertex_idx` form a concave corner, in which case they
+	/// should be collided with as planes rather than as a point
+	pub fn is_concave_barrier_vertex(&self, vertex_idx: NavVertexID) -> bool {
+		match self.vertex_barriers(vertex_idx) {
+			Some((incoming_idx, outgoing_idx)) => {
+				let incoming_normal = self.projected_edge_normal(incoming_idx);
+				let outgoing_normal = self.projected_edge_normal(outgoing_idx);
+				incoming_normal.perp().dot(outgoing_normal) <= 0.0
+			}
+
+			None => false,
+		}
+	}
 }
 
+
+
+
+/// Finds the plane of a convex polygon, using the corner with the largest area so that
+/// collinear vertices don't produce a degenerate plane
+fn polygon_plane(points: &[Vec3]) -> Plane {
+	let (_, idx) = (1..points.len()-1)
+		.map(|idx| ((points[idx] - points[0]).cross(points[idx+1] - points[0]).length(), idx))
+		.max_by_key(|(area, _)| area.ordify())
+		.unwrap();
+
+	Plane::from_points(points[0], points[idx], points[idx+1])
+}
+
+
+/// Greedily merges polygons sharing an edge, so long as they are coplanar and the result
+/// remains convex when projected onto the xz plane
+fn merge_coplanar_polygons(positions: &[Vec3], mut polygons: Vec<Vec<usize>>) -> Vec<Vec<usize>> {
+	use std::collections::HashMap;
+
+	let projected_polygon = |polygon: &[usize]| polygon.iter()
+		.map(|&v| positions[v].to_xz())
+		.collect(): Vec<_>;
+
+	let planes = polygons.iter()
+		.map(|polygon| polygon.iter().map(|&v| positions[v]).collect(): Vec<_>)
+		.map(|points| polygon_plane(&points))
+		.collect(): Vec<_>;
+
+	let mut alive = vec![true; polygons.len()];
+
+	loop {
+		let mut edge_to_polygon = HashMap::new();
+
+		for (polygon_idx, polygon) in polygons.iter().enumerate().filter(|&(idx, _)| alive[idx]) {
+			for (idx, &vertex) in polygon.iter().enumerate() {
+				let next_vertex = polygon[(idx + 1) % polygon.len()];
+				edge_to_polygon.insert((vertex, next_vertex), polygon_idx);
+			}
+		}
+
+		let mut merged_any = false;
+
+		for polygon_idx in 0..polygons.len() {
+			if !alive[polygon_idx] { continue }
+
+			let polygon = &polygons[polygon_idx];
+
+			// Merging invalidates the edges of both polygons, so only merge once per pass
+			let merge = (0..polygon.len())
+				.map(|idx| (polygon[idx], polygon[(idx + 1) % polygon.len()]))
+				.filter_map(|(a, b)| {
+					let &other_idx = edge_to_polygon.get(&(b, a))?;
+					if other_idx == polygon_idx || !alive[other_idx] { return None }
+					if !planes_coplanar(planes[polygon_idx], planes[other_idx]) { return None }
+
+					let merged = join_polygons(polygon, &polygons[other_idx], a, b)?;
+					if !is_projected_convex(&projected_polygon(&merged)) { return None }
+
+					Some((other_idx, merged))
+				})
+				.next();
+
+			if let Some((other_idx, merged)) = merge {
+				polygons[polygon_idx] = merged;
+				alive[other_idx] = false;
+				merged_any = true;
+			}
+		}
+
+		if !merged_any { break }
+	}
+
+	polygons.into_iter()
+		.zip(alive)
+		.filter(|&(_, alive)| alive)
+		.map(|(polygon, _)| polygon)
+		.collect()
+}
+
+fn planes_coplanar(a: Plane, b: Plane) -> bool {
+	a.normal.dot(b.normal) > 0.999 && (a.length - b.length).abs() < 0.01
+}
+
+/// Joins polygon `p` containing edge `a`->`b` with polygon `q` containing the edge `b`->`a`.
+/// Fails if the polygons share any other vertices
+fn join_polygons(p: &[usize], q: &[usize], a: usize, b: usize) -> Option<Vec<usize>> {
+	let p_start = p.iter().position(|&v| v == b)?;
+	let q_start = q.iter().position(|&v| v == a)?;
+
+	// From b around p to a, then from after a around q to before b
+	let merged = (0..p.len()).map(|idx| p[(p_start + idx) % p.len()])
+		.chain((1..q.len()-1).map(|idx| q[(q_start + idx) % q.len()]))
+		.collect(): Vec<_>;
+
+	let mut unique = merged.clone();
+	unique.sort();
+	unique.dedup();
+
+	if unique.len() != merged.len() {
+		return None
+	}
+
+	Some(merged)
+}
+
+fn is_projected_convex(points: &[Vec2]) -> bool {
+	let cross = |a: Vec2, b: Vec2| a.x * b.y - a.y * b.x;
+	let num_points = points.len();
+
+	let winding = (0..num_points)
+		.map(|idx| cross(points[idx], points[(idx + 1) % num_points]))
+		.sum(): f32;
+
+	if winding.abs() < 0.0001 {
+		return false
+	}
+
+	(0..num_points).all(|idx| {
+		let a = points[idx];
+		let b = points[(idx + 1) % num_points];
+		let c = points[(idx + 2) % num_points];
+
+		// Allow collinear vertices, since they're common along the borders of merged faces
+		cross((b - a).normalize(), (c - b).normalize()) * winding.signum() >= -0.0001
+	})
+}
+
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Vertices on a flat grid on the xz plane, indexed `x + z * size`
+	fn grid_positions(size: usize) -> Vec<Vec3> {
+		(0..size*size)
+			.map(|idx| Vec3::new((idx % size) as f32, 0.0, -((idx / size) as f32)))
+			.collect()
+	}
+
+	/// The two triangles of the grid cell at (`x`, `z`), wound CCW from above
+	fn cell_triangles(size: usize, x: usize, z: usize) -> Vec<Vec<usize>> {
+		let v = |x, z| x + z * size;
+
+		vec![
+			vec![v(x, z), v(x+1, z), v(x+1, z+1)],
+			vec![v(x, z), v(x+1, z+1), v(x, z+1)],
+		]
+	}
+
+	fn assert_convex(positions: &[Vec3], polygons: &[Vec<usize>]) {
+		for polygon in polygons.iter() {
+			let points = polygon.iter().map(|&v| positions[v].to_xz()).collect(): Vec<_>;
+			assert!(is_projected_convex(&points), "Merged polygon isn't convex: {:?}", points);
+		}
+	}
+
+	#[test]
+	fn coplanar_quad_merges() {
+		let positions = grid_positions(2);
+		let merged = merge_coplanar_polygons(&positions, cell_triangles(2, 0, 0));
+
+		assert_eq!(merged.len(), 1);
+		assert_eq!(merged[0].len(), 4);
+	}
+
+	#[test]
+	fn concave_shape_merges_into_convex_faces() {
+		// An L of three cells
+		let positions = grid_positions(3);
+		let triangles = [(0, 0), (1, 0), (0, 1)].iter()
+			.flat_map(|&(x, z)| cell_triangles(3, x, z))
+			.collect(): Vec<_>;
+
+		let merged = merge_coplanar_polygons(&positions, triangles);
+
+		assert!(merged.len() > 1 && merged.len() < 6, "Expected some but not all triangles to merge, got {:?}", merged);
+		assert_convex(&positions, &merged);
+	}
+
+	#[test]
+	fn non_coplanar_triangles_dont_merge() {
+		// Raise the far side of the second cell, so the two cells are at an angle to each other
+		let mut positions = grid_positions(3);
+		for position in positions.iter_mut().filter(|p| p.x > 1.5) {
+			position.y = 1.0;
+		}
+
+		let triangles = [(0, 0), (1, 0)].iter()
+			.flat_map(|&(x, z)| cell_triangles(3, x, z))
+			.collect(): Vec<_>;
+
+		let merged = merge_coplanar_polygons(&positions, triangles);
+
+		assert_eq!(merged.len(), 2);
+		assert_convex(&positions, &merged);
+	}
+
+	#[test]
+	fn merged_faces_stay_linked() {
+		let positions = grid_positions(3);
+		let indices = [(0, 0), (1, 0), (0, 1)].iter()
+			.flat_map(|&(x, z)| cell_triangles(3, x, z))
+			.flatten()
+			.collect(): Vec<usize>;
+
+		let nav = NavMesh::from_triangles(&positions, &indices);
+
+		assert!(nav.faces.len() > 1);
+
+		for face in nav.faces.iter() {
+			assert!(nav.iter_edge_loop(face.start_edge).any(|(_, edge)| edge.twin.is_some()),
+				"Face at {:?} isn't linked to its neighbours", face.center);
+		}
+	}
+}
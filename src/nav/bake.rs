@@ -0,0 +1,542 @@
+use crate::prelude::*;
+use crate::player_controller::PLAYER_HEIGHT;
+use super::NavMesh;
+
+use std::collections::HashMap;
+
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BakeParams {
+	/// Horizontal size of each voxel column
+	pub cell_size: f32,
+
+	pub agent_height: f32,
+	pub agent_radius: f32,
+
+	/// Maximum height difference between neighbouring cells that an agent can step across
+	pub agent_step: f32,
+
+	/// Maximum walkable slope in radians
+	pub max_slope: f32,
+}
+
+impl Default for BakeParams {
+	fn default() -> Self {
+		BakeParams {
+			cell_size: 0.25,
+
+			agent_height: PLAYER_HEIGHT,
+			agent_radius: 0.3,
+			agent_step: 0.3,
+
+			max_slope: PI / 4.0,
+		}
+	}
+}
+
+
+/// Bakes a nav mesh from the static geometry of `scene` - the same entities that make up
+/// the scene mesh. Used when a scene has no hand authored NAV_ mesh
+pub fn bake_scene(scene: toy::SceneRef<'_>, params: &BakeParams) -> NavMesh {
+	let mut triangles = Vec::new();
+
+	for entity in crate::static_scene_entities(scene) {
+		let mesh_data = entity.mesh_data().unwrap();
+		let transform = entity.transform();
+
+		let positions = mesh_data.positions.iter()
+			.map(|&pos| transform * pos)
+			.collect(): Vec<_>;
+
+		triangles.extend(mesh_data.indices.chunks(3)
+			.map(|tri| [
+				positions[tri[0] as usize],
+				positions[tri[1] as usize],
+				positions[tri[2] as usize],
+			]));
+	}
+
+	bake_triangles(&triangles, params)
+}
+
+
+/// Voxelises `triangles` into a heightfield, finds the surfaces an agent described by `params`
+/// can stand on, and triangulates them into a nav mesh with one quad per walkable cell.
+/// Coplanar cells are then merged into larger polygons by `NavMesh::from_triangles`
+pub fn bake_triangles(triangles: &[[Vec3; 3]], params: &BakeParams) -> NavMesh {
+	if triangles.is_empty() {
+		return NavMesh::from_triangles::<usize>(&[], &[])
+	}
+
+	let mut heightfield = Heightfield::new(triangles, params.cell_size);
+
+	let min_walkable_normal_y = params.max_slope.cos();
+	for &[a, b, c] in triangles {
+		let normal = (b - a).cross(c - a).normalize();
+		heightfield.rasterize_triangle([a, b, c], normal.y >= min_walkable_normal_y);
+	}
+
+	heightfield.filter_low_clearance(params.agent_height);
+
+	let walkable = WalkableSurface::new(&heightfield, params);
+	walkable.triangulate()
+}
+
+
+
+#[derive(Copy, Clone, Debug)]
+struct Span {
+	min: f32,
+	max: f32,
+	walkable: bool,
+}
+
+/// Columns of solid spans over a regular grid on the xz plane
+struct Heightfield {
+	origin: Vec2,
+	cell_size: f32,
+	width: usize,
+	depth: usize,
+
+	/// Spans in each column, sorted bottom to top and non-overlapping
+	columns: Vec<Vec<Span>>,
+}
+
+impl Heightfield {
+	fn new(triangles: &[[Vec3; 3]], cell_size: f32) -> Heightfield {
+		let mut min = Vec2::splat(f32::INFINITY);
+		let mut max = Vec2::splat(-f32::INFINITY);
+
+		for v in triangles.iter().flat_map(|tri| tri.iter()) {
+			min = Vec2::new(min.x.min(v.x), min.y.min(v.z));
+			max = Vec2::new(max.x.max(v.x), max.y.max(v.z));
+		}
+
+		let width = ((max.x - min.x) / cell_size).ceil() as usize + 1;
+		let depth = ((max.y - min.y) / cell_size).ceil() as usize + 1;
+
+		Heightfield {
+			origin: min,
+			cell_size,
+			width,
+			depth,
+
+			columns: vec![Vec::new(); width * depth],
+		}
+	}
+
+	fn column_index(&self, x: usize, z: usize) -> usize { x + z * self.width }
+
+	fn cell_coord(&self, v: f32, origin: f32, limit: usize) -> usize {
+		(((v - origin) / self.cell_size).floor().max(0.0) as usize).min(limit - 1)
+	}
+
+	fn rasterize_triangle(&mut self, triangle: [Vec3; 3], walkable: bool) {
+		let (min_x, max_x, min_z, max_z) = triangle.iter()
+			.fold((f32::INFINITY, -f32::INFINITY, f32::INFINITY, -f32::INFINITY), |(x0, x1, z0, z1), v| {
+				(x0.min(v.x), x1.max(v.x), z0.min(v.z), z1.max(v.z))
+			});
+
+		let cell_x_start = self.cell_coord(min_x, self.origin.x, self.width);
+		let cell_x_end = self.cell_coord(max_x, self.origin.x, self.width);
+		let cell_z_start = self.cell_coord(min_z, self.origin.y, self.depth);
+		let cell_z_end = self.cell_coord(max_z, self.origin.y, self.depth);
+
+		for cell_z in cell_z_start..=cell_z_end {
+			let z0 = self.origin.y + cell_z as f32 * self.cell_size;
+			let z1 = z0 + self.cell_size;
+
+			for cell_x in cell_x_start..=cell_x_end {
+				let x0 = self.origin.x + cell_x as f32 * self.cell_size;
+				let x1 = x0 + self.cell_size;
+
+				let clipped = clip_polygon(&triangle, |v| v.x - x0);
+				let clipped = clip_polygon(&clipped, |v| x1 - v.x);
+				let clipped = clip_polygon(&clipped, |v| v.z - z0);
+				let clipped = clip_polygon(&clipped, |v| z1 - v.z);
+
+				if clipped.is_empty() { continue }
+
+				let (min, max) = clipped.iter()
+					.fold((f32::INFINITY, -f32::INFINITY), |(min, max), v| (min.min(v.y), max.max(v.y)));
+
+				let column_idx = self.column_index(cell_x, cell_z);
+				add_span(&mut self.columns[column_idx], Span {min, max, walkable});
+			}
+		}
+	}
+
+	/// Marks spans without enough headroom for an agent as unwalkable
+	fn filter_low_clearance(&mut self, agent_height: f32) {
+		for column in self.columns.iter_mut() {
+			for idx in 0..column.len() {
+				let ceiling = column.get(idx+1).map_or(f32::INFINITY, |s| s.min);
+				if ceiling - column[idx].max < agent_height {
+					column[idx].walkable = false;
+				}
+			}
+		}
+	}
+}
+
+
+/// Distance within which the tops of two merged spans are considered the same surface
+const SPAN_MERGE_THRESHOLD: f32 = 0.05;
+
+fn add_span(column: &mut Vec<Span>, mut span: Span) {
+	let mut idx = 0;
+
+	while idx < column.len() {
+		let existing = column[idx];
+
+		if existing.max < span.min {
+			idx += 1;
+			continue
+		}
+
+		if existing.min > span.max {
+			break
+		}
+
+		// Overlapping, merge into new span
+		if (existing.max - span.max).abs() <= SPAN_MERGE_THRESHOLD {
+			span.walkable |= existing.walkable;
+		} else if existing.max > span.max {
+			span.walkable = existing.walkable;
+		}
+
+		span.min = span.min.min(existing.min);
+		span.max = span.max.max(existing.max);
+		column.remove(idx);
+	}
+
+	column.insert(idx, span);
+}
+
+
+/// Sutherland-Hodgman clip of `polygon` against the half space where `distance` is positive
+fn clip_polygon(polygon: &[Vec3], distance: impl Fn(Vec3) -> f32) -> Vec<Vec3> {
+	let mut result = Vec::with_capacity(polygon.len() + 1);
+
+	for (idx, &a) in polygon.iter().enumerate() {
+		let b = polygon[(idx + 1) % polygon.len()];
+		let (dist_a, dist_b) = (distance(a), distance(b));
+
+		if dist_a >= 0.0 {
+			result.push(a);
+		}
+
+		if (dist_a >= 0.0) != (dist_b >= 0.0) {
+			let t = dist_a / (dist_a - dist_b);
+			result.push(a + (b - a) * t);
+		}
+	}
+
+	result
+}
+
+
+
+#[derive(Copy, Clone, Debug)]
+struct WalkableCell {
+	x: usize,
+	z: usize,
+	height: f32,
+}
+
+/// The walkable tops of heightfield spans, eroded by agent radius
+struct WalkableSurface {
+	width: usize,
+	depth: usize,
+	origin: Vec2,
+	cell_size: f32,
+	agent_step: f32,
+
+	cells: Vec<WalkableCell>,
+
+	/// Indices into `cells` for each column
+	columns: Vec<Vec<usize>>,
+}
+
+impl WalkableSurface {
+	fn new(heightfield: &Heightfield, params: &BakeParams) -> WalkableSurface {
+		let mut surface = WalkableSurface {
+			width: heightfield.width,
+			depth: heightfield.depth,
+			origin: heightfield.origin,
+			cell_size: heightfield.cell_size,
+			agent_step: params.agent_step,
+
+			cells: Vec::new(),
+			columns: vec![Vec::new(); heightfield.columns.len()],
+		};
+
+		for z in 0..heightfield.depth {
+			for x in 0..heightfield.width {
+				let column_idx = heightfield.column_index(x, z);
+
+				for span in heightfield.columns[column_idx].iter().filter(|s| s.walkable) {
+					surface.columns[column_idx].push(surface.cells.len());
+					surface.cells.push(WalkableCell {x, z, height: span.max});
+				}
+			}
+		}
+
+		let erosion_steps = (params.agent_radius / params.cell_size).ceil() as usize;
+		for _ in 0..erosion_steps {
+			surface.erode();
+		}
+
+		surface
+	}
+
+	/// Finds the cell in the column at (`x`+`dx`, `z`+`dz`) that an agent standing on `cell_idx` could step onto
+	fn neighbour(&self, cell_idx: usize, dx: isize, dz: isize) -> Option<usize> {
+		let cell = &self.cells[cell_idx];
+		let x = cell.x as isize + dx;
+		let z = cell.z as isize + dz;
+
+		if x < 0 || z < 0 || x >= self.width as isize || z >= self.depth as isize {
+			return None
+		}
+
+		self.columns[x as usize + z as usize * self.width].iter()
+			.map(|&idx| (idx, (self.cells[idx].height - cell.height).abs()))
+			.filter(|&(_, diff)| diff <= self.agent_step)
+			.min_by_key(|&(_, diff)| diff.ordify())
+			.map(|(idx, _)| idx)
+	}
+
+	/// Removes every cell that isn't connected on all four sides
+	fn erode(&mut self) {
+		let boundary_cells = self.columns.iter()
+			.flatten()
+			.cloned()
+			.filter(|&idx| {
+				[(-1, 0), (1, 0), (0, -1), (0, 1)].iter()
+					.any(|&(dx, dz)| self.neighbour(idx, dx, dz).is_none())
+			})
+			.collect(): std::collections::HashSet<_>;
+
+		for column in self.columns.iter_mut() {
+			column.retain(|idx| !boundary_cells.contains(idx));
+		}
+	}
+
+	fn triangulate(&self) -> NavMesh {
+		let mut positions = Vec::new();
+		let mut indices = Vec::new();
+
+		// Maps (corner index, cell index) to a vertex shared by every connected cell around that corner
+		let mut corner_vertices: HashMap<(usize, usize), usize> = HashMap::new();
+
+		for corner_z in 0..=self.depth {
+			for corner_x in 0..=self.width {
+				self.build_corner_vertices(corner_x, corner_z, &mut positions, &mut corner_vertices);
+			}
+		}
+
+		let corner_index = |x: usize, z: usize| x + z * (self.width + 1);
+
+		for (cell_idx, cell) in self.live_cells() {
+			let WalkableCell{x, z, ..} = *cell;
+			let vertex = |cx, cz| corner_vertices[&(corner_index(cx, cz), cell_idx)];
+
+			let v00 = vertex(x, z);
+			let v10 = vertex(x+1, z);
+			let v11 = vertex(x+1, z+1);
+			let v01 = vertex(x, z+1);
+
+			indices.extend_from_slice(&[v00, v01, v11]);
+			indices.extend_from_slice(&[v00, v11, v10]);
+		}
+
+		NavMesh::from_triangles(&positions, &indices[..])
+	}
+
+	fn live_cells(&self) -> impl Iterator<Item=(usize, &'_ WalkableCell)> + '_ {
+		self.columns.iter()
+			.flatten()
+			.map(move |&idx| (idx, &self.cells[idx]))
+	}
+
+	/// Groups the cells touching a grid corner into sets connected to each other around that corner,
+	/// and creates a vertex for each set at their average height.
+	/// Grouping only by direct neighbours means that cells touching diagonally don't share a vertex,
+	/// which would otherwise lead to vertices with multiple outgoing barriers
+	fn build_corner_vertices(&self, corner_x: usize, corner_z: usize,
+		positions: &mut Vec<Vec3>, corner_vertices: &mut HashMap<(usize, usize), usize>)
+	{
+		let corner_idx = corner_x + corner_z * (self.width + 1);
+
+		// Columns around the corner in circular order, so consecutive entries are direct neighbours
+		let around = [(-1, -1), (0, -1), (0, 0), (-1, 0)];
+
+		let mut cells = Vec::new();
+		for &(dx, dz) in around.iter() {
+			let x = corner_x as isize + dx;
+			let z = corner_z as isize + dz;
+
+			if x < 0 || z < 0 || x >= self.width as isize || z >= self.depth as isize {
+				continue
+			}
+
+			cells.extend_from_slice(&self.columns[x as usize + z as usize * self.width]);
+		}
+
+		if cells.is_empty() { return }
+
+		// Union find over cells around this corner
+		let mut parents = (0..cells.len()).collect(): Vec<_>;
+
+		fn find(parents: &mut [usize], mut idx: usize) -> usize {
+			while parents[idx] != idx {
+				parents[idx] = parents[parents[idx]];
+				idx = parents[idx];
+			}
+			idx
+		}
+
+		for (local_idx, &cell_idx) in cells.iter().enumerate() {
+			let cell = &self.cells[cell_idx];
+			let offset_x = cell.x as isize - corner_x as isize;
+			let offset_z = cell.z as isize - corner_z as isize;
+
+			let position_around = around.iter()
+				.position(|&offset| offset == (offset_x, offset_z))
+				.unwrap();
+
+			// Only need to link forward around the circle - the previous column links back to us
+			let (next_x, next_z) = around[(position_around + 1) % around.len()];
+			let neighbour = self.neighbour(cell_idx, next_x - offset_x, next_z - offset_z);
+
+			if let Some(neighbour_local) = neighbour.and_then(|n| cells.iter().position(|&c| c == n)) {
+				let root_a = find(&mut parents, local_idx);
+				let root_b = find(&mut parents, neighbour_local);
+				parents[root_a] = root_b;
+			}
+		}
+
+		let mut group_vertices: HashMap<usize, usize> = HashMap::new();
+		let mut group_heights: HashMap<usize, (f32, usize)> = HashMap::new();
+
+		for local_idx in 0..cells.len() {
+			let root = find(&mut parents, local_idx);
+			let entry = group_heights.entry(root).or_insert((0.0, 0));
+			entry.0 += self.cells[cells[local_idx]].height;
+			entry.1 += 1;
+		}
+
+		let corner_pos = self.origin + Vec2::new(corner_x as f32, corner_z as f32) * self.cell_size;
+
+		for (local_idx, &cell_idx) in cells.iter().enumerate() {
+			let root = find(&mut parents, local_idx);
+
+			let vertex = *group_vertices.entry(root).or_insert_with(|| {
+				let (height_sum, count) = group_heights[&root];
+				positions.push(Vec3::new(corner_pos.x, height_sum / count as f32, corner_pos.y));
+				positions.len() - 1
+			});
+
+			corner_vertices.insert((corner_idx, cell_idx), vertex);
+		}
+	}
+}
+
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Two triangles covering the rectangle between `min` and `max` on the xz plane at `height`, facing up
+	fn floor(min: Vec2, max: Vec2, height: f32) -> Vec<[Vec3; 3]> {
+		let corner = |x: f32, z: f32| Vec3::new(x, height, z);
+
+		vec![
+			[corner(min.x, max.y), corner(max.x, max.y), corner(max.x, min.y)],
+			[corner(min.x, max.y), corner(max.x, min.y), corner(min.x, min.y)],
+		]
+	}
+
+	/// Number of groups of faces connected by shared edges
+	fn connected_components(nav: &NavMesh) -> usize {
+		let mut component = vec![None; nav.faces.len()];
+		let mut count = 0;
+
+		for start in 0..nav.faces.len() {
+			if component[start].is_some() { continue }
+
+			let mut stack = vec![start];
+			component[start] = Some(count);
+
+			while let Some(face_idx) = stack.pop() {
+				for (_, edge) in nav.iter_edge_loop(nav.faces[face_idx].start_edge) {
+					let neighbour = match edge.twin {
+						Some(twin) => nav.edges[twin].face,
+						None => continue,
+					};
+
+					if component[neighbour].is_none() {
+						component[neighbour] = Some(count);
+						stack.push(neighbour);
+					}
+				}
+			}
+
+			count += 1;
+		}
+
+		count
+	}
+
+	#[test]
+	fn flat_floor_is_walkable() {
+		let triangles = floor(Vec2::zero(), Vec2::splat(4.0), 0.0);
+		let nav = bake_triangles(&triangles, &BakeParams::default());
+
+		assert!(!nav.faces.is_empty());
+		assert_eq!(connected_components(&nav), 1);
+
+		for face in nav.faces.iter() {
+			assert!(face.plane.normal.y > 0.99, "Face isn't flat: {:?}", face.plane);
+			assert!(face.center.y.abs() < 0.01, "Face isn't on the floor: {:?}", face.center);
+		}
+	}
+
+	#[test]
+	fn high_step_splits_floor() {
+		let params = BakeParams::default();
+
+		let mut triangles = floor(Vec2::zero(), Vec2::new(4.0, 4.0), 0.0);
+		triangles.extend(floor(Vec2::new(4.0, 0.0), Vec2::new(8.0, 4.0), params.agent_step * 3.0));
+
+		let nav = bake_triangles(&triangles, &params);
+		assert_eq!(connected_components(&nav), 2);
+	}
+
+	#[test]
+	fn low_step_stays_connected() {
+		let params = BakeParams::default();
+
+		let mut triangles = floor(Vec2::zero(), Vec2::new(4.0, 4.0), 0.0);
+		triangles.extend(floor(Vec2::new(4.0, 0.0), Vec2::new(8.0, 4.0), params.agent_step * 0.5));
+
+		let nav = bake_triangles(&triangles, &params);
+		assert_eq!(connected_components(&nav), 1);
+	}
+
+	#[test]
+	fn steep_slope_is_unwalkable() {
+		let params = BakeParams::default();
+
+		// Rises 4 units over 2, well over the default 45 degree limit
+		let triangles = [
+			[Vec3::new(0.0, 0.0, 4.0), Vec3::new(4.0, 0.0, 4.0), Vec3::new(4.0, 4.0, 2.0)],
+			[Vec3::new(0.0, 0.0, 4.0), Vec3::new(4.0, 4.0, 2.0), Vec3::new(0.0, 4.0, 2.0)],
+		];
+
+		let nav = bake_triangles(&triangles, &params);
+		assert!(nav.faces.is_empty());
+	}
+}
@@ -0,0 +1,157 @@
+use crate::prelude::*;
+use super::{NavMesh, NavFace};
+
+use std::io::{self, Write};
+
+
+/// Width of the exported svg in pixels - height is derived from the aspect of the mesh
+const SVG_WIDTH: f32 = 1024.0;
+const SVG_MARGIN: f32 = 32.0;
+
+
+impl NavMesh {
+	/// Writes faces as polygons and barrier edges as lines
+	pub fn write_obj(&self, w: &mut impl Write) -> io::Result<()> {
+		writeln!(w, "# nav mesh: {} vertices, {} faces", self.vertices.len(), self.faces.len())?;
+		writeln!(w, "o nav_mesh")?;
+
+		for vertex in self.vertices.iter() {
+			let Vec3{x, y, z} = vertex.position;
+			writeln!(w, "v {} {} {}", x, y, z)?;
+		}
+
+		writeln!(w, "g faces")?;
+		for &NavFace{start_edge, ..} in self.faces.iter() {
+			write!(w, "f")?;
+			for (vertex_idx, _) in self.iter_edge_loop_vertices(start_edge) {
+				write!(w, " {}", vertex_idx + 1)?;
+			}
+			writeln!(w)?;
+		}
+
+		writeln!(w, "g barriers")?;
+		for (edge_idx, edge) in self.edges.iter().enumerate() {
+			if edge.twin.is_some() { continue }
+
+			let next_vertex = self.edges[edge.next].vertex;
+			writeln!(w, "l {} {}  # edge {}", edge.vertex + 1, next_vertex + 1, edge_idx)?;
+		}
+
+		Ok(())
+	}
+
+	/// Writes a top down view of the mesh, projected onto the xz plane.
+	/// Mirrors the in-game nav debug drawing: half edges are drawn inset towards their face
+	/// and coloured by whether they have a twin, and concave barrier vertices are highlighted
+	pub fn write_svg(&self, w: &mut impl Write) -> io::Result<()> {
+		let (min, max) = self.vertices.iter()
+			.map(|v| v.position.to_xz())
+			.fold((Vec2::splat(f32::INFINITY), Vec2::splat(-f32::INFINITY)), |(min, max), p| {
+				(Vec2::new(min.x.min(p.x), min.y.min(p.y)), Vec2::new(max.x.max(p.x), max.y.max(p.y)))
+			});
+
+		let vertex_color = Color::rgb(1.0, 0.0, 1.0);
+		let face_center_color = Color::rgb(1.0, 1.0, 0.5);
+		let twin_edge_color = Color::rgb(0.2, 0.6, 0.3);
+		let barrier_edge_color = Color::rgb(0.5, 0.2, 0.2);
+		let concave_barrier_color = Color::rgb(1.0, 0.3, 0.5);
+
+		let extent = max - min;
+		let scale = (SVG_WIDTH - SVG_MARGIN * 2.0) / extent.x.max(extent.y).max(0.001);
+		let height = extent.y * scale + SVG_MARGIN * 2.0;
+
+		let to_svg = |p: Vec3| (p.to_xz() - min) * scale + Vec2::splat(SVG_MARGIN);
+
+		writeln!(w, r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" viewBox="0 0 {} {}">"#,
+			SVG_WIDTH, height, SVG_WIDTH, height)?;
+
+		writeln!(w, "<defs>")?;
+		for &(name, color) in [("twin", twin_edge_color), ("barrier", barrier_edge_color)].iter() {
+			writeln!(w, r#"<marker id="arrow-{}" viewBox="0 0 10 10" refX="10" refY="5" markerWidth="4" markerHeight="4" orient="auto">"#, name)?;
+			writeln!(w, r#"<path d="M0,0 L10,5 L0,10 z" fill="{}"/></marker>"#, svg_color(color))?;
+		}
+		writeln!(w, "</defs>")?;
+
+		writeln!(w, r#"<rect width="100%" height="100%" fill="{}"/>"#, svg_color(Color::grey(0.1)))?;
+
+		// Faces
+		writeln!(w, r#"<g fill="{}" fill-opacity="0.15">"#, svg_color(Color::grey(0.8)))?;
+		for &NavFace{start_edge, ..} in self.faces.iter() {
+			let points = self.iter_edge_loop_vertices(start_edge)
+				.map(|(_, v)| to_svg(v.position))
+				.map(|p| format!("{:.2},{:.2}", p.x, p.y))
+				.collect(): Vec<_>;
+
+			writeln!(w, r#"<polygon points="{}"/>"#, points.join(" "))?;
+		}
+		writeln!(w, "</g>")?;
+
+		// Half edges
+		writeln!(w, r#"<g stroke-width="1.5" stroke-opacity="0.8">"#)?;
+		for &NavFace{start_edge, center, ..} in self.faces.iter() {
+			for (edge_idx, edge) in self.iter_edge_loop(start_edge) {
+				let (pos_a, pos_b) = self.edge_vertex_positions(edge_idx);
+
+				let pos_a = to_svg((0.1).ease_linear(pos_a, center));
+				let pos_b = to_svg((0.1).ease_linear(pos_b, center));
+
+				let (name, color) = if edge.twin.is_some() {
+					("twin", twin_edge_color)
+				} else {
+					("barrier", barrier_edge_color)
+				};
+
+				writeln!(w, r#"<line x1="{:.2}" y1="{:.2}" x2="{:.2}" y2="{:.2}" stroke="{}" marker-end="url(#arrow-{})"/>"#,
+					pos_a.x, pos_a.y, pos_b.x, pos_b.y, svg_color(color), name)?;
+			}
+		}
+		writeln!(w, "</g>")?;
+
+		// Concave barrier vertices and the barriers that meet at them
+		writeln!(w, r#"<g stroke="{0}" fill="{0}" stroke-width="2">"#, svg_color(concave_barrier_color))?;
+		for vertex_idx in 0..self.vertices.len() {
+			if !self.is_concave_barrier_vertex(vertex_idx) { continue }
+
+			let (incoming_idx, outgoing_idx) = self.vertex_barriers(vertex_idx).unwrap();
+
+			for &barrier_idx in [incoming_idx, outgoing_idx].iter() {
+				let (pos_a, pos_b) = self.edge_vertex_positions(barrier_idx);
+				let (pos_a, pos_b) = (to_svg(pos_a), to_svg(pos_b));
+
+				writeln!(w, r#"<line x1="{:.2}" y1="{:.2}" x2="{:.2}" y2="{:.2}"/>"#,
+					pos_a.x, pos_a.y, pos_b.x, pos_b.y)?;
+			}
+
+			let pos = to_svg(self.vertices[vertex_idx].position);
+			writeln!(w, r#"<circle cx="{:.2}" cy="{:.2}" r="5"/>"#, pos.x, pos.y)?;
+		}
+		writeln!(w, "</g>")?;
+
+		// Vertices
+		writeln!(w, r#"<g fill="{}">"#, svg_color(vertex_color))?;
+		for vertex in self.vertices.iter() {
+			let pos = to_svg(vertex.position);
+			writeln!(w, r#"<circle cx="{:.2}" cy="{:.2}" r="2"/>"#, pos.x, pos.y)?;
+		}
+		writeln!(w, "</g>")?;
+
+		// Face centers and indices
+		writeln!(w, r#"<g fill="{}" font-family="monospace" font-size="10" text-anchor="middle">"#,
+			svg_color(face_center_color))?;
+		for (face_idx, face) in self.faces.iter().enumerate() {
+			let pos = to_svg(face.center);
+			writeln!(w, r#"<circle cx="{:.2}" cy="{:.2}" r="2"/>"#, pos.x, pos.y)?;
+			writeln!(w, r#"<text x="{:.2}" y="{:.2}">{}</text>"#, pos.x, pos.y - 4.0, face_idx)?;
+		}
+		writeln!(w, "</g>")?;
+
+		writeln!(w, "</svg>")
+	}
+}
+
+
+fn svg_color(color: Color) -> String {
+	let (r, g, b, _) = color.to_tuple();
+	let to_byte = |v: f32| (v.clamp(0.0, 1.0) * 255.0) as u8;
+	format!("#{:02x}{:02x}{:02x}", to_byte(r), to_byte(g), to_byte(b))
+}
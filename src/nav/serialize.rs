@@ -0,0 +1,407 @@
+use crate::prelude::*;
+use super::{NavMesh, NavVertex, NavHalfEdge, NavFace};
+use super::bake::BakeParams;
+
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+
+const MAGIC: &[u8; 4] = b"NAVM";
+
+/// Bump whenever the layout written by `NavMesh::write_baked` changes,
+/// or when nav mesh construction changes enough that old bakes should be rebuilt
+pub const FORMAT_VERSION: u32 = 3;
+
+const NO_EDGE: u32 = !0;
+
+
+impl NavMesh {
+	pub fn save(&self, path: impl AsRef<Path>, source_hash: u64, params: &BakeParams) -> Result<(), Box<dyn Error>> {
+		let mut file = io::BufWriter::new(std::fs::File::create(path)?);
+		self.write_baked(&mut file, source_hash, params)?;
+		file.flush()?;
+		Ok(())
+	}
+
+	/// Loads a baked nav mesh, failing if it was baked with a different format version or is stale.
+	/// `expected_source_hash` is given the params the mesh was baked with, and should hash
+	/// the current source with them
+	pub fn load(path: impl AsRef<Path>, expected_source_hash: impl FnOnce(&BakeParams) -> u64) -> Result<NavMesh, Box<dyn Error>> {
+		let mut file = io::BufReader::new(std::fs::File::open(path)?);
+		NavMesh::read_baked(&mut file, expected_source_hash)
+	}
+
+	/// `params` are stored so that a bake made with non-default params can still be validated
+	pub fn write_baked(&self, w: &mut impl Write, source_hash: u64, params: &BakeParams) -> io::Result<()> {
+		w.write_all(MAGIC)?;
+		write_u32(w, FORMAT_VERSION)?;
+
+		let BakeParams{cell_size, agent_height, agent_radius, agent_step, max_slope} = *params;
+		for &value in [cell_size, agent_height, agent_radius, agent_step, max_slope].iter() {
+			write_f32(w, value)?;
+		}
+
+		write_u64(w, source_hash)?;
+
+		write_u32(w, self.vertices.len() as u32)?;
+		write_u32(w, self.edges.len() as u32)?;
+		write_u32(w, self.faces.len() as u32)?;
+
+		for vertex in self.vertices.iter() {
+			write_vec3(w, vertex.position)?;
+			write_u32(w, vertex.outgoing_edge as u32)?;
+			write_u32(w, vertex.outgoing_barrier.map_or(NO_EDGE, |e| e as u32))?;
+		}
+
+		for edge in self.edges.iter() {
+			write_u32(w, edge.vertex as u32)?;
+			write_u32(w, edge.next as u32)?;
+			write_u32(w, edge.prev as u32)?;
+			write_u32(w, edge.twin.map_or(NO_EDGE, |e| e as u32))?;
+			write_u32(w, edge.face as u32)?;
+		}
+
+		for face in self.faces.iter() {
+			write_u32(w, face.start_edge as u32)?;
+			write_vec3(w, face.plane.normal)?;
+			write_f32(w, face.plane.length)?;
+			write_vec3(w, face.center)?;
+		}
+
+		Ok(())
+	}
+
+	pub fn read_baked(r: &mut impl Read, expected_source_hash: impl FnOnce(&BakeParams) -> u64) -> Result<NavMesh, Box<dyn Error>> {
+		let mut magic = [0u8; 4];
+		r.read_exact(&mut magic)?;
+		if &magic != MAGIC {
+			return Err("Not a baked nav mesh".into())
+		}
+
+		let version = read_u32(r)?;
+		if version != FORMAT_VERSION {
+			return Err(format!("Baked nav mesh has version {}, expected {}", version, FORMAT_VERSION).into())
+		}
+
+		let params = BakeParams {
+			cell_size: read_f32(r)?,
+			agent_height: read_f32(r)?,
+			agent_radius: read_f32(r)?,
+			agent_step: read_f32(r)?,
+			max_slope: read_f32(r)?,
+		};
+
+		let source_hash = read_u64(r)?;
+		let expected_source_hash = expected_source_hash(&params);
+		if source_hash != expected_source_hash {
+			return Err(format!("Baked nav mesh is stale (source hash {:016x}, expected {:016x})",
+				source_hash, expected_source_hash).into())
+		}
+
+		let vertex_count = read_u32(r)? as usize;
+		let edge_count = read_u32(r)? as usize;
+		let face_count = read_u32(r)? as usize;
+
+		let read_index = |r: &mut dyn Read, limit: usize, what: &str| -> Result<usize, Box<dyn Error>> {
+			let idx = read_u32(r)? as usize;
+			if idx >= limit {
+				return Err(format!("Baked nav mesh has out of range {} index {}", what, idx).into())
+			}
+			Ok(idx)
+		};
+
+		let read_optional_edge = |r: &mut dyn Read| -> Result<Option<usize>, Box<dyn Error>> {
+			match read_u32(r)? {
+				NO_EDGE => Ok(None),
+				idx if (idx as usize) < edge_count => Ok(Some(idx as usize)),
+				idx => Err(format!("Baked nav mesh has out of range edge index {}", idx).into()),
+			}
+		};
+
+		// Counts aren't trusted for preallocation - a corrupt count should fail to read, not allocate
+		let mut vertices = Vec::new();
+		for _ in 0..vertex_count {
+			vertices.push(NavVertex {
+				position: read_vec3(r)?,
+				outgoing_edge: read_index(r, edge_count, "edge")?,
+				outgoing_barrier: read_optional_edge(r)?,
+			});
+		}
+
+		let mut edges = Vec::new();
+		for _ in 0..edge_count {
+			edges.push(NavHalfEdge {
+				vertex: read_index(r, vertex_count, "vertex")?,
+				next: read_index(r, edge_count, "edge")?,
+				prev: read_index(r, edge_count, "edge")?,
+				twin: read_optional_edge(r)?,
+				face: read_index(r, face_count, "face")?,
+			});
+		}
+
+		let mut faces = Vec::new();
+		for _ in 0..face_count {
+			let start_edge = read_index(r, edge_count, "edge")?;
+			let normal = read_vec3(r)?;
+			let length = read_f32(r)?;
+			let center = read_vec3(r)?;
+
+			faces.push(NavFace {
+				start_edge,
+				plane: Plane { normal, length },
+				center,
+			});
+		}
+
+		let nav_mesh = NavMesh {
+			vertices,
+			edges,
+			faces,
+		};
+
+		nav_mesh.validate_topology()?;
+		Ok(nav_mesh)
+	}
+
+	/// Checks that edge loops are closed and consistent, since otherwise walking them
+	/// would never terminate. Indices are assumed to be in range
+	fn validate_topology(&self) -> Result<(), String> {
+		for (edge_idx, edge) in self.edges.iter().enumerate() {
+			if self.edges[edge.next].prev != edge_idx || self.edges[edge.prev].next != edge_idx {
+				return Err(format!("Baked nav mesh edge {} has mismatched next and prev edges", edge_idx))
+			}
+
+			if self.edges[edge.next].face != edge.face {
+				return Err(format!("Baked nav mesh edge {} leaves its face's edge loop", edge_idx))
+			}
+
+			if let Some(twin) = edge.twin {
+				if self.edges[twin].twin != Some(edge_idx) {
+					return Err(format!("Baked nav mesh edge {} has a twin that doesn't point back", edge_idx))
+				}
+			}
+		}
+
+		for (face_idx, face) in self.faces.iter().enumerate() {
+			if self.edges[face.start_edge].face != face_idx {
+				return Err(format!("Baked nav mesh face {} starts on an edge of another face", face_idx))
+			}
+		}
+
+		Ok(())
+	}
+}
+
+
+/// Hashes the worldspace geometry of the entities a nav mesh was built from, and the params
+/// it was baked with if it was baked, so that baked nav meshes can be invalidated when either changes.
+/// Uses FNV-1a so the result is stable across builds and platforms
+pub fn source_hash<'t>(entities: impl IntoIterator<Item=toy::EntityRef<'t>>, bake_params: Option<&BakeParams>) -> u64 {
+	let mut hasher = FnvHasher::new();
+
+	for entity in entities {
+		let mesh_data = match entity.mesh_data() {
+			Some(md) => md,
+			None => continue,
+		};
+
+		let transform = entity.transform();
+
+		for &pos in mesh_data.positions.iter() {
+			let Vec3{x, y, z} = transform * pos;
+			hasher.write(&x.to_bits().to_le_bytes());
+			hasher.write(&y.to_bits().to_le_bytes());
+			hasher.write(&z.to_bits().to_le_bytes());
+		}
+
+		for &index in mesh_data.indices.iter() {
+			hasher.write(&index.to_le_bytes());
+		}
+	}
+
+	if let Some(params) = bake_params {
+		let BakeParams{cell_size, agent_height, agent_radius, agent_step, max_slope} = *params;
+
+		for &value in [cell_size, agent_height, agent_radius, agent_step, max_slope].iter() {
+			hasher.write(&value.to_bits().to_le_bytes());
+		}
+	}
+
+	hasher.finish()
+}
+
+
+struct FnvHasher(u64);
+
+impl FnvHasher {
+	fn new() -> FnvHasher { FnvHasher(0xcbf2_9ce4_8422_2325) }
+
+	fn write(&mut self, bytes: &[u8]) {
+		for &b in bytes {
+			self.0 ^= b as u64;
+			self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+		}
+	}
+
+	fn finish(&self) -> u64 { self.0 }
+}
+
+
+
+fn write_u32(w: &mut impl Write, v: u32) -> io::Result<()> { w.write_all(&v.to_le_bytes()) }
+fn write_u64(w: &mut impl Write, v: u64) -> io::Result<()> { w.write_all(&v.to_le_bytes()) }
+fn write_f32(w: &mut impl Write, v: f32) -> io::Result<()> { w.write_all(&v.to_le_bytes()) }
+
+fn write_vec3(w: &mut impl Write, Vec3{x, y, z}: Vec3) -> io::Result<()> {
+	write_f32(w, x)?;
+	write_f32(w, y)?;
+	write_f32(w, z)
+}
+
+fn read_u32(r: &mut (impl Read + ?Sized)) -> io::Result<u32> {
+	let mut bytes = [0u8; 4];
+	r.read_exact(&mut bytes)?;
+	Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(r: &mut (impl Read + ?Sized)) -> io::Result<u64> {
+	let mut bytes = [0u8; 8];
+	r.read_exact(&mut bytes)?;
+	Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_f32(r: &mut (impl Read + ?Sized)) -> io::Result<f32> {
+	let mut bytes = [0u8; 4];
+	r.read_exact(&mut bytes)?;
+	Ok(f32::from_le_bytes(bytes))
+}
+
+fn read_vec3(r: &mut (impl Read + ?Sized)) -> io::Result<Vec3> {
+	Ok(Vec3::new(read_f32(r)?, read_f32(r)?, read_f32(r)?))
+}
+
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const HASH: u64 = 0x1234_5678_9abc_def0;
+
+	/// Magic, version, bake params and hash
+	const HEADER_SIZE: usize = 4 + 4 + 5*4 + 8;
+	const COUNTS_SIZE: usize = 3*4;
+	const VERTEX_SIZE: usize = 3*4 + 4 + 4;
+
+	/// Two triangles sharing an edge, so there are twins, barriers and more than one face
+	fn test_nav_mesh() -> NavMesh {
+		let positions = [
+			Vec3::new(0.0, 0.0, 0.0),
+			Vec3::new(1.0, 0.0, 0.0),
+			Vec3::new(1.0, 0.0, -1.0),
+			Vec3::new(0.0, 0.0, -1.0),
+		];
+
+		NavMesh::from_polygons(&positions, &[vec![0, 1, 2], vec![0, 2, 3]])
+	}
+
+	fn baked_bytes(nav_mesh: &NavMesh) -> Vec<u8> {
+		let mut bytes = Vec::new();
+		nav_mesh.write_baked(&mut bytes, HASH, &BakeParams::default()).unwrap();
+		bytes
+	}
+
+	#[test]
+	fn round_trip() {
+		let nav_mesh = test_nav_mesh();
+		let bytes = baked_bytes(&nav_mesh);
+
+		let loaded = NavMesh::read_baked(&mut bytes.as_slice(), |_| HASH).unwrap();
+		assert_eq!(format!("{:?}", loaded), format!("{:?}", nav_mesh));
+	}
+
+	#[test]
+	fn wrong_magic() {
+		let mut bytes = baked_bytes(&test_nav_mesh());
+		bytes[0] = b'X';
+		assert!(NavMesh::read_baked(&mut bytes.as_slice(), |_| HASH).is_err());
+	}
+
+	#[test]
+	fn wrong_version() {
+		let mut bytes = baked_bytes(&test_nav_mesh());
+		bytes[4..8].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+		assert!(NavMesh::read_baked(&mut bytes.as_slice(), |_| HASH).is_err());
+	}
+
+	#[test]
+	fn wrong_hash() {
+		let bytes = baked_bytes(&test_nav_mesh());
+		assert!(NavMesh::read_baked(&mut bytes.as_slice(), |_| !HASH).is_err());
+	}
+
+	#[test]
+	fn truncated() {
+		let bytes = baked_bytes(&test_nav_mesh());
+
+		for len in [0, 3, 20, bytes.len() - 1].iter().cloned() {
+			assert!(NavMesh::read_baked(&mut &bytes[..len], |_| HASH).is_err(), "Truncated to {} bytes", len);
+		}
+	}
+
+	#[test]
+	fn out_of_range_index() {
+		let mut bytes = baked_bytes(&test_nav_mesh());
+
+		// The first vertex's position, then its outgoing edge
+		let outgoing_edge_offset = HEADER_SIZE + COUNTS_SIZE + 3*4;
+		bytes[outgoing_edge_offset..outgoing_edge_offset+4].copy_from_slice(&1000u32.to_le_bytes());
+
+		assert!(NavMesh::read_baked(&mut bytes.as_slice(), |_| HASH).is_err());
+	}
+
+	#[test]
+	fn huge_count() {
+		let mut bytes = baked_bytes(&test_nav_mesh());
+
+		for count_idx in 0..3 {
+			let offset = HEADER_SIZE + count_idx*4;
+			let mut bytes = bytes.clone();
+			bytes[offset..offset+4].copy_from_slice(&u32::MAX.to_le_bytes());
+			assert!(NavMesh::read_baked(&mut bytes.as_slice(), |_| HASH).is_err(), "Count {} set to u32::MAX", count_idx);
+		}
+
+		// With no data following at all
+		bytes.truncate(HEADER_SIZE + COUNTS_SIZE);
+		bytes[HEADER_SIZE..HEADER_SIZE+4].copy_from_slice(&u32::MAX.to_le_bytes());
+		assert!(NavMesh::read_baked(&mut bytes.as_slice(), |_| HASH).is_err());
+	}
+
+	#[test]
+	fn broken_next_loop() {
+		let nav_mesh = test_nav_mesh();
+		let mut bytes = baked_bytes(&nav_mesh);
+
+		// Point the first edge's next at itself - every index is still in range
+		let edges_offset = HEADER_SIZE + COUNTS_SIZE + nav_mesh.vertices.len() * VERTEX_SIZE;
+		let next_offset = edges_offset + 4;
+		bytes[next_offset..next_offset+4].copy_from_slice(&0u32.to_le_bytes());
+
+		assert!(NavMesh::read_baked(&mut bytes.as_slice(), |_| HASH).is_err());
+	}
+
+	#[test]
+	fn stored_params_are_hashed() {
+		let params = BakeParams { cell_size: 0.5, ..BakeParams::default() };
+
+		let mut bytes = Vec::new();
+		test_nav_mesh().write_baked(&mut bytes, HASH, &params).unwrap();
+
+		let loaded = NavMesh::read_baked(&mut bytes.as_slice(), |stored| {
+			assert_eq!(*stored, params);
+			HASH
+		});
+
+		assert!(loaded.is_ok());
+	}
+}
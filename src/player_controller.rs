@@ -8,6 +8,15 @@ pub const PLAYER_HEIGHT: f32 = 2.0;
 pub const PLAYER_HEIGHT_VEC: Vec3 = Vec3::from_y(PLAYER_HEIGHT);
 
 
+/// The result of the most recent attempt to move along the nav mesh, for debug visualisation
+#[derive(Copy, Clone, Debug)]
+pub struct SlideInfo {
+	pub start_pos: Vec2,
+	pub desired_delta: Vec2,
+	pub resolved_delta: Vec2,
+}
+
+
 #[derive(Debug)]
 pub struct PlayerController {
 	pub go_forward: bool,
@@ -19,6 +28,7 @@ pub struct PlayerController {
 	fly_mode: bool,
 
 	current_nav_face: Option<NavFaceID>,
+	last_slide: Option<SlideInfo>,
 }
 
 impl PlayerController {
@@ -33,10 +43,12 @@ impl PlayerController {
 			fly_mode: false,
 
 			current_nav_face: None,
+			last_slide: None,
 		}
 	}
 
 	pub fn nav_face(&self) -> Option<NavFaceID> { self.current_nav_face }
+	pub fn last_slide(&self) -> Option<SlideInfo> { self.last_slide }
 
 	pub fn set_face(&mut self, face_id: NavFaceID) {
 		self.current_nav_face = Some(face_id);
@@ -50,8 +62,9 @@ impl PlayerController {
 		}
 	}
 
-	pub fn update(&mut self, camera: &mut Camera, nav_mesh: &NavMesh) {
-		let speed = if self.go_fast { 10.0 } else { 6.0 } / 60.0;
+	/// `dt` is the time in seconds since the last update
+	pub fn update(&mut self, camera: &mut Camera, nav_mesh: &NavMesh, dt: f32) {
+		let speed = if self.go_fast { 10.0 } else { 6.0 } * dt;
 		let speed = speed * if self.fly_mode { 3.0 } else { 1.0 };
 
 		if self.fly_mode {
@@ -65,6 +78,7 @@ impl PlayerController {
 			if self.go_right { camera_delta += right; }
 
 			camera.set_position(camera.position() + camera_delta * speed);
+			self.last_slide = None;
 
 		} else {
 			if self.current_nav_face.is_none() {
@@ -83,13 +97,22 @@ impl PlayerController {
 				if self.go_left { camera_delta -= right; }
 				if self.go_right { camera_delta += right; }
 
+				let start_pos_2d = camera.position().to_xz();
+				let desired_delta = camera_delta * speed;
+
 				let new_pos_2d = slide_player_along_barriers(
 					&nav_mesh,
 					face_idx,
-					camera.position().to_xz(),
-					camera_delta * speed
+					start_pos_2d,
+					desired_delta
 				);
 
+				self.last_slide = Some(SlideInfo {
+					start_pos: start_pos_2d,
+					desired_delta,
+					resolved_delta: new_pos_2d - start_pos_2d,
+				});
+
 				let new_face_idx = transition_player_across_edges(&nav_mesh, face_idx, new_pos_2d);
 
 				self.current_nav_face = Some(new_face_idx);
@@ -150,29 +173,14 @@ fn slide_player_along_barriers(
 		
 		// Find any barriers connected to this edge's vertex
 		let vertex = &nav.vertices[edge.vertex];
-		if vertex.outgoing_barrier.is_none() {
-			continue
-		}
-
-		let outgoing_barrier_idx = vertex.outgoing_barrier.unwrap();
-		let outgoing_barrier = &nav.edges[outgoing_barrier_idx];
-
-		let mut prev_incoming_edge_idx = outgoing_barrier.prev;
-		let mut prev_incoming_edge = &nav.edges[prev_incoming_edge_idx];
-
-		while let Some(twin_idx) = prev_incoming_edge.twin {
-			prev_incoming_edge_idx = nav.edges[twin_idx].prev;
-			prev_incoming_edge = &nav.edges[prev_incoming_edge_idx];
-		}
-
-		let incoming_barrier_idx = prev_incoming_edge_idx;
+		let (incoming_barrier_idx, outgoing_barrier_idx) = match nav.vertex_barriers(edge.vertex) {
+			Some(barriers) => barriers,
+			None => continue,
+		};
 
 
 		// Test concavity - if vertex is concave then collide with barriers as planes
-		let incoming_normal = nav.projected_edge_normal(incoming_barrier_idx);
-		let outgoing_normal = nav.projected_edge_normal(outgoing_barrier_idx);
-
-		if incoming_normal.perp().dot(outgoing_normal) <= 0.0 {
+		if nav.is_concave_barrier_vertex(edge.vertex) {
 			let (va, vb) = nav.projected_edge_vertex_positions(incoming_barrier_idx);
 			delta += projected_plane_rejection(va, vb, start_pos + delta);
 
@@ -180,6 +188,9 @@ fn slide_player_along_barriers(
 			delta += projected_plane_rejection(va, vb, start_pos + delta);
 		} else {
 			// Vertex is convex
+			let incoming_normal = nav.projected_edge_normal(incoming_barrier_idx);
+			let outgoing_normal = nav.projected_edge_normal(outgoing_barrier_idx);
+
 			let end_pos = start_pos + delta;
 			let vertex_delta = end_pos - vertex.position.to_xz();
 
@@ -0,0 +1,274 @@
+use crate::prelude::*;
+use crate::gfx::core::Core;
+
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+
+pub const RECORDING_DIR: &str = "recordings";
+
+
+#[derive(Clone, Debug)]
+pub enum RecordingOutput {
+	/// Numbered PNGs written to a directory
+	Images(PathBuf),
+
+	/// Raw top-row-first RGBA frames written to the stdin of a shell command.
+	/// `{width}`, `{height}` and `{fps}` are substituted when recording starts, e.g.,
+	/// `ffmpeg -f rawvideo -pix_fmt rgba -s {width}x{height} -r {fps} -i - capture.mp4`
+	Pipe(String),
+}
+
+
+#[derive(Clone, Debug)]
+pub struct RecordingSettings {
+	pub fps: u32,
+
+	/// Length of the recording in simulated seconds, or None to record until stopped
+	pub duration: Option<f32>,
+
+	pub output: RecordingOutput,
+}
+
+impl RecordingSettings {
+	/// 30fps PNGs into a new timestamped directory, until stopped
+	pub fn new() -> RecordingSettings {
+		let timestamp = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)
+			.map_or(0, |t| t.as_secs());
+
+		RecordingSettings {
+			fps: 30,
+			duration: None,
+			output: RecordingOutput::Images(PathBuf::from(RECORDING_DIR).join(format!("recording-{}", timestamp))),
+		}
+	}
+
+	/// Parses `--record [dir]`, `--record-pipe <command>`, `--record-fps <fps>` and
+	/// `--record-seconds <seconds>`. Returns None if recording wasn't requested
+	pub fn from_args(args: &[String]) -> Result<Option<RecordingSettings>, Box<dyn Error>> {
+		let mut settings = RecordingSettings::new();
+		let mut requested = false;
+
+		let mut args = args.iter().map(String::as_str).peekable();
+
+		while let Some(arg) = args.next() {
+			match arg {
+				"--record" => {
+					requested = true;
+					if args.peek().map_or(false, |a| !a.starts_with("--")) {
+						let dir = args.next().unwrap();
+						settings.output = RecordingOutput::Images(dir.into());
+					}
+				}
+
+				"--record-pipe" => {
+					requested = true;
+					let command = args.next().ok_or("--record-pipe requires a command")?;
+					settings.output = RecordingOutput::Pipe(command.to_owned());
+				}
+
+				"--record-fps" => {
+					let fps = args.next().ok_or("--record-fps requires a value")?;
+					settings.fps = fps.parse()?;
+					if settings.fps == 0 {
+						return Err("--record-fps must be greater than zero".into())
+					}
+				}
+
+				"--record-seconds" => {
+					let seconds: f32 = args.next().ok_or("--record-seconds requires a value")?.parse()?;
+					// Also rejects NaN, which would never be reached
+					if !(seconds > 0.0) {
+						return Err("--record-seconds must be greater than zero".into())
+					}
+
+					settings.duration = Some(seconds);
+				}
+
+				_ => return Err(format!("Unknown option '{}'", arg).into()),
+			}
+		}
+
+		Ok(Some(settings).filter(|_| requested))
+	}
+}
+
+
+enum FrameSink {
+	Images(PathBuf),
+	Pipe(Child),
+}
+
+
+/// Captures every frame of the window while active. The game should be stepped by `dt()`
+/// rather than real time while recording, so that captures play back at the right speed
+/// however long each frame actually takes to render
+pub struct Recorder {
+	settings: RecordingSettings,
+	size: Vec2i,
+	frame_index: u32,
+	sink: FrameSink,
+}
+
+impl Recorder {
+	/// `size` is the size of the frames that will be captured, which must stay fixed
+	pub fn start(settings: RecordingSettings, size: Vec2i) -> Result<Recorder, Box<dyn Error>> {
+		let sink = match &settings.output {
+			RecordingOutput::Images(dir) => {
+				std::fs::create_dir_all(dir)?;
+				FrameSink::Images(dir.clone())
+			}
+
+			RecordingOutput::Pipe(command) => {
+				let command = command
+					.replace("{width}", &size.x.to_string())
+					.replace("{height}", &size.y.to_string())
+					.replace("{fps}", &settings.fps.to_string());
+
+				FrameSink::Pipe(shell_command(&command).stdin(Stdio::piped()).spawn()?)
+			}
+		};
+
+		println!("Recording {}x{} at {}fps to {:?}", size.x, size.y, settings.fps, settings.output);
+
+		Ok(Recorder {
+			settings,
+			size,
+			frame_index: 0,
+			sink,
+		})
+	}
+
+	/// The fixed timestep the game should be advanced by each frame
+	pub fn dt(&self) -> f32 {
+		1.0 / self.settings.fps as f32
+	}
+
+	pub fn frame_count(&self) -> u32 { self.frame_index }
+
+	pub fn is_finished(&self) -> bool {
+		match self.settings.duration {
+			Some(duration) => self.frame_index as f32 >= duration * self.settings.fps as f32,
+			None => false,
+		}
+	}
+
+	/// Reads back the window and writes it out. Should be called after drawing and before swapping,
+	/// with no framebuffer bound
+	pub fn capture_frame(&mut self, core: &mut Core) -> Result<(), Box<dyn Error>> {
+		if core.bound_framebuffer().is_some() {
+			return Err("Recorder captures the window, but a framebuffer is bound".into())
+		}
+
+		let image = core.read_pixels();
+		if image.size != self.size {
+			return Err(format!("Window was resized from {:?} to {:?} during recording", self.size, image.size).into())
+		}
+
+		match &mut self.sink {
+			FrameSink::Images(dir) => {
+				image.save_png(dir.join(format!("frame-{:05}.png", self.frame_index)))?;
+			}
+
+			FrameSink::Pipe(child) => {
+				let stdin = child.stdin.as_mut().ok_or("Encoder stdin closed")?;
+				stdin.write_all(&image.data)?;
+			}
+		}
+
+		self.frame_index += 1;
+		Ok(())
+	}
+
+	/// Closes the encoder pipe, if any, and waits for it to finish writing
+	pub fn finish(self) -> Result<(), Box<dyn Error>> {
+		if let FrameSink::Pipe(mut child) = self.sink {
+			drop(child.stdin.take());
+
+			let status = child.wait()?;
+			if !status.success() {
+				return Err(format!("Encoder exited with {}", status).into())
+			}
+		}
+
+		println!("Recorded {} frames", self.frame_index);
+		Ok(())
+	}
+}
+
+
+#[cfg(unix)]
+fn shell_command(command: &str) -> Command {
+	let mut cmd = Command::new("sh");
+	cmd.arg("-c").arg(command);
+	cmd
+}
+
+#[cfg(not(unix))]
+fn shell_command(command: &str) -> Command {
+	let mut cmd = Command::new("cmd");
+	cmd.arg("/C").arg(command);
+	cmd
+}
+
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn parse(args: &[&str]) -> Result<Option<RecordingSettings>, Box<dyn Error>> {
+		let args = args.iter().map(|a| a.to_string()).collect(): Vec<_>;
+		RecordingSettings::from_args(&args)
+	}
+
+	#[test]
+	fn not_requested() {
+		assert!(parse(&[]).unwrap().is_none());
+		assert!(parse(&["--record-fps", "60"]).unwrap().is_none());
+	}
+
+	#[test]
+	fn record_without_dir() {
+		let settings = parse(&["--record", "--record-fps", "60"]).unwrap().unwrap();
+		assert_eq!(settings.fps, 60);
+
+		match settings.output {
+			RecordingOutput::Images(dir) => assert!(dir.starts_with(RECORDING_DIR)),
+			output => panic!("unexpected output {:?}", output),
+		}
+	}
+
+	#[test]
+	fn record_with_dir() {
+		let settings = parse(&["--record", "captures", "--record-seconds", "2.5"]).unwrap().unwrap();
+		assert_eq!(settings.duration, Some(2.5));
+
+		match settings.output {
+			RecordingOutput::Images(dir) => assert_eq!(dir, PathBuf::from("captures")),
+			output => panic!("unexpected output {:?}", output),
+		}
+	}
+
+	#[test]
+	fn missing_values() {
+		assert!(parse(&["--record-pipe"]).is_err());
+		assert!(parse(&["--record", "--record-fps"]).is_err());
+		assert!(parse(&["--record", "--record-seconds"]).is_err());
+	}
+
+	#[test]
+	fn invalid_values() {
+		assert!(parse(&["--record", "--record-fps", "0"]).is_err());
+		assert!(parse(&["--record", "--record-fps", "fast"]).is_err());
+		assert!(parse(&["--record", "--record-seconds", "0"]).is_err());
+		assert!(parse(&["--record", "--record-seconds", "-1"]).is_err());
+		assert!(parse(&["--record", "--record-seconds", "NaN"]).is_err());
+	}
+
+	#[test]
+	fn unknown_option() {
+		let err = parse(&["--record", "dir", "extra"]).err().unwrap();
+		assert_eq!(err.to_string(), "Unknown option 'extra'");
+	}
+}
@@ -0,0 +1,340 @@
+use crate::prelude::*;
+use crate::gfx::core::Core;
+use crate::gfx::mesh::MeshID;
+use crate::gfx::instance_buffer::{InstanceBufferID, TransformInstance, TRANSFORM_INSTANCE_ATTRIBUTES};
+use crate::gfx::mesh_builder::MeshBuilder;
+use crate::gfx::shader::{ShaderID, ShaderError};
+use crate::gfx::camera::{Camera, Frustum};
+use crate::gfx::render_queue::{RenderQueue, DrawItem, UniformValue};
+use crate::util::{Aabb, Ray};
+use crate::SceneVertex;
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+
+pub struct SceneMeshEntity {
+	pub name: String,
+
+	/// The entity's elements within the scene mesh, or within its prop's mesh if it is instanced
+	pub range: Range<u32>,
+
+	/// World space bounds of the entity's geometry
+	pub bounds: Aabb,
+
+	pub hidden: bool,
+
+	/// Set if the entity shares its mesh with other entities, and so is drawn instanced
+	pub prop: Option<PropInstance>,
+
+	/// The entity's world space triangles within the picking data
+	pick_range: Range<usize>,
+}
+
+
+#[derive(Copy, Clone, Debug)]
+pub struct PropInstance {
+	/// Index of the prop in the scene mesh
+	pub prop: usize,
+	pub transform: Mat4,
+}
+
+
+/// A mesh shared by several entities, e.g., repeated crates or lights, drawn in one instanced draw
+struct Prop {
+	mesh: MeshID<SceneVertex>,
+
+	/// Refilled with the visible instances by `SceneMesh::submit`.
+	/// None if instancing isn't supported, in which case instances are drawn one by one
+	instances: Option<InstanceBufferID<TransformInstance>>,
+}
+
+impl Prop {
+	fn new(core: &mut Core, mesh_data: &toy::MeshData) -> Prop {
+		let mesh = core.new_mesh();
+		let verts = crate::scene_mesh_vertices(mesh_data, &Mat4::ident());
+		core.update_mesh(mesh, &verts, &mesh_data.indices);
+
+		let instances = if core.capabilities().instancing {
+			Some(core.new_instance_buffer())
+		} else {
+			None
+		};
+
+		Prop { mesh, instances }
+	}
+}
+
+
+/// Static scene geometry in a single mesh, like `build_scene_mesh`, but keeping track of which
+/// elements belong to which entity so that entities can be culled or hidden individually.
+/// Entities that share a mesh are kept out of the scene mesh and drawn instanced instead
+pub struct SceneMesh {
+	pub mesh: MeshID<SceneVertex>,
+	entities: Vec<SceneMeshEntity>,
+	props: Vec<Prop>,
+
+	/// An instanced variant of the scene shader, or one taking a per-draw transform
+	/// if instancing isn't supported
+	prop_shader: ShaderID,
+
+	// World space triangles of every entity, kept for picking
+	positions: Vec<Vec3>,
+	indices: Vec<u32>,
+}
+
+impl SceneMesh {
+	pub fn build(core: &mut Core, scene: toy::SceneRef<'_>) -> SceneMesh {
+		let scene_entities = crate::static_scene_entities(scene).collect(): Vec<_>;
+
+		// Entities created from the same mesh share mesh data
+		let mut mesh_users: HashMap<*const toy::MeshData, u32> = HashMap::new();
+		for entity in scene_entities.iter() {
+			*mesh_users.entry(entity.mesh_data().unwrap() as *const _).or_default() += 1;
+		}
+
+		let mesh = core.new_mesh();
+		let mut mb = MeshBuilder::new(mesh);
+		let mut entities = Vec::new();
+		let mut props = Vec::new();
+		let mut prop_indices: HashMap<*const toy::MeshData, usize> = HashMap::new();
+
+		let mut positions = Vec::new();
+		let mut indices = Vec::new();
+
+		for entity in scene_entities.iter() {
+			let mesh_data = entity.mesh_data().unwrap();
+			let verts = crate::scene_entity_vertices(entity);
+			let bounds = match Aabb::from_points(verts.iter().map(|v| v.pos)) {
+				Some(bounds) => bounds,
+				None => continue,
+			};
+
+			let pick_start = indices.len();
+			let base_vertex = positions.len() as u32;
+			positions.extend(verts.iter().map(|v| v.pos));
+			indices.extend(mesh_data.indices.iter().map(|&i| i as u32 + base_vertex));
+
+			let key = mesh_data as *const toy::MeshData;
+
+			let (range, prop) = if mesh_users[&key] > 1 {
+				let prop = *prop_indices.entry(key).or_insert_with(|| {
+					props.push(Prop::new(core, mesh_data));
+					props.len() - 1
+				});
+
+				(0..mesh_data.indices.len() as u32, Some(PropInstance { prop, transform: entity.transform() }))
+
+			} else {
+				let start = mb.es.len() as u32;
+				mb.add_geometry(&verts, &mesh_data.indices);
+				(start..mb.es.len() as u32, None)
+			};
+
+			entities.push(SceneMeshEntity {
+				name: entity.name.to_owned(),
+				range,
+				bounds,
+				hidden: false,
+				prop,
+				pick_range: pick_start..indices.len(),
+			});
+		}
+
+		mb.commit(core);
+
+		let prop_shader = new_prop_shader(core).expect("Failed to create prop shader");
+
+		SceneMesh {
+			mesh,
+			entities,
+			props,
+			prop_shader,
+
+			positions,
+			indices,
+		}
+	}
+
+	pub fn destroy(self, core: &mut Core) {
+		core.destroy_mesh(self.mesh);
+		core.destroy_shader(self.prop_shader);
+
+		for prop in self.props {
+			core.destroy_mesh(prop.mesh);
+
+			if let Some(instances) = prop.instances {
+				core.destroy_instance_buffer(instances);
+			}
+		}
+	}
+
+	pub fn entities(&self) -> &[SceneMeshEntity] { &self.entities }
+
+	/// Returns false if there is no entity called `name`
+	pub fn set_hidden(&mut self, name: &str, hidden: bool) -> bool {
+		let mut found = false;
+
+		for entity in self.entities.iter_mut().filter(|e| e.name == name) {
+			entity.hidden = hidden;
+			found = true;
+		}
+
+		found
+	}
+
+	/// Finds the nearest visible entity hit by `ray` and the distance to it
+	pub fn raycast(&self, ray: &Ray) -> Option<(&SceneMeshEntity, f32)> {
+		self.entities.iter()
+			.filter(|entity| !entity.hidden)
+			.filter(|entity| ray.intersect_aabb(&entity.bounds).is_some())
+			.filter_map(|entity| {
+				self.indices[entity.pick_range.clone()].chunks(3)
+					.filter_map(|tri| ray.intersect_triangle(
+						self.positions[tri[0] as usize],
+						self.positions[tri[1] as usize],
+						self.positions[tri[2] as usize]
+					))
+					.min_by_key(|t| t.ordify())
+					.map(|t| (entity, t))
+			})
+			.min_by_key(|(_, t)| t.ordify())
+	}
+
+	/// Submits the entities that are visible from `camera`. Runs of adjacent visible entities
+	/// are submitted as a single draw, and the visible instances of each prop as a single instanced draw
+	pub fn submit(&self, core: &mut Core, queue: &mut RenderQueue, shader: ShaderID, camera: &Camera) -> CullStats {
+		let frustum = camera.frustum();
+		let mut stats = CullStats::default();
+
+		// Range of the current run, and the center of its nearest entity
+		let mut run: Option<(Range<u32>, Vec3)> = None;
+
+		let mut prop_instances = vec![Vec::new(); self.props.len()];
+
+		// Center of the nearest visible instance of each prop
+		let mut prop_positions = vec![None; self.props.len()];
+
+		for entity in self.entities.iter() {
+			// Props aren't in the scene mesh, so don't break up runs
+			if let Some(PropInstance{prop, transform}) = &entity.prop {
+				if !self.is_visible(entity, &frustum, &mut stats) {
+					continue
+				}
+
+				if self.props[*prop].instances.is_none() {
+					queue.submit(DrawItem::new(self.props[*prop].mesh, self.prop_shader)
+						.with_uniform("u_object", UniformValue::Mat4(*transform))
+						.at_position(entity.bounds.center(), camera));
+
+					continue
+				}
+
+				prop_instances[*prop].push(TransformInstance::new(transform, Color::rgb(1.0, 1.0, 1.0)));
+
+				let center = entity.bounds.center();
+				let position = prop_positions[*prop].get_or_insert(center);
+				if (center - camera.position()).length() < (*position - camera.position()).length() {
+					*position = center;
+				}
+
+				continue
+			}
+
+			if !self.is_visible(entity, &frustum, &mut stats) {
+				if let Some((range, position)) = run.take() {
+					queue.submit(self.draw_item(shader, range, position, camera));
+				}
+
+				continue
+			}
+
+			let center = entity.bounds.center();
+
+			run = match run.take() {
+				Some((range, position)) => {
+					let nearest = if (center - camera.position()).length() < (position - camera.position()).length() {
+						center
+					} else {
+						position
+					};
+
+					Some((range.start..entity.range.end, nearest))
+				}
+
+				None => Some((entity.range.clone(), center)),
+			};
+		}
+
+		if let Some((range, position)) = run {
+			queue.submit(self.draw_item(shader, range, position, camera));
+		}
+
+		for ((prop, instances), position) in self.props.iter().zip(&prop_instances).zip(&prop_positions) {
+			let (buffer, position) = match (prop.instances, position) {
+				(Some(buffer), Some(position)) => (buffer, *position),
+				_ => continue,
+			};
+
+			core.update_instance_buffer(buffer, instances);
+
+			queue.submit(DrawItem::new(prop.mesh, self.prop_shader)
+				.with_instances(buffer)
+				.at_position(position, camera));
+		}
+
+		stats
+	}
+
+	fn is_visible(&self, entity: &SceneMeshEntity, frustum: &Frustum, stats: &mut CullStats) -> bool {
+		if entity.hidden {
+			stats.hidden += 1;
+			false
+		} else if !frustum.contains_aabb(&entity.bounds) {
+			stats.culled += 1;
+			false
+		} else {
+			stats.visible += 1;
+			true
+		}
+	}
+
+	fn draw_item(&self, shader: ShaderID, range: Range<u32>, position: Vec3, camera: &Camera) -> DrawItem {
+		DrawItem::new(self.mesh, shader)
+			.with_range(range)
+			.at_position(position, camera)
+	}
+}
+
+
+/// The scene shader with per-instance transforms, or without instancing support, a per-draw `u_object` transform
+fn new_prop_shader(core: &mut Core) -> Result<ShaderID, ShaderError> {
+	let vsrc = crate::shader_source!("shaders/fog_vert.glsl");
+	let fsrc = crate::shader_source!("shaders/fog_frag.glsl");
+	let attribs = ["a_vertex", "a_color", "a_emission"];
+
+	if core.capabilities().instancing {
+		let attribs = attribs.iter()
+			.chain(&TRANSFORM_INSTANCE_ATTRIBUTES)
+			.cloned()
+			.collect(): Vec<_>;
+
+		core.new_instanced_shader::<SceneVertex, TransformInstance>(vsrc, fsrc, &attribs)
+	} else {
+		core.new_shader_variant::<SceneVertex>(vsrc, fsrc, &attribs, &[("OBJECT_TRANSFORM", "1")])
+	}
+}
+
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct CullStats {
+	pub visible: u32,
+	pub culled: u32,
+	pub hidden: u32,
+}
+
+impl std::fmt::Display for CullStats {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{} entities visible, {} culled, {} hidden", self.visible, self.culled, self.hidden)
+	}
+}
@@ -1,6 +1,6 @@
-#version 130
+#version 140
 
-uniform mat4 u_proj_view;
+#include "camera.glsl"
 
 attribute vec3 a_vertex;
 attribute vec4 a_color;
@@ -8,6 +8,10 @@ attribute vec4 a_color;
 varying vec4 v_color;
 
 void main() {
+#ifdef SCREEN_SPACE
+	gl_Position = vec4(a_vertex, 1.0);
+#else
 	gl_Position = u_proj_view * vec4(a_vertex, 1.0);
+#endif
 	v_color = a_color;
 }
@@ -1,7 +1,12 @@
-#version 130
+#version 140
 
-uniform mat4 u_proj_view;
-uniform mat4 u_view;
+#include "camera.glsl"
+
+#if defined(INSTANCED)
+#include "instance.glsl"
+#elif defined(OBJECT_TRANSFORM)
+uniform mat4 u_object;
+#endif
 
 attribute vec3 a_vertex;
 attribute vec4 a_color;
@@ -12,10 +17,20 @@ varying vec3 v_view_pos;
 varying float v_emission;
 
 void main() {
-	gl_Position = u_proj_view * vec4(a_vertex, 1.0);
+#if defined(INSTANCED)
+	vec4 world_pos = instance_transform() * vec4(a_vertex, 1.0);
+	v_color = a_color * a_instance_color;
+#elif defined(OBJECT_TRANSFORM)
+	vec4 world_pos = u_object * vec4(a_vertex, 1.0);
+	v_color = a_color;
+#else
+	vec4 world_pos = vec4(a_vertex, 1.0);
 	v_color = a_color;
+#endif
+
+	gl_Position = u_proj_view * world_pos;
 	v_emission = 1.0 / (1.0 + a_emission);
 	// v_emission = a_emission;
 
-	v_view_pos = (u_view * vec4(a_vertex, 1.0)).xyz;
+	v_view_pos = (u_view * world_pos).xyz;
 }
@@ -0,0 +1,7 @@
+// Matches gfx::camera::CameraUniforms
+layout(std140) uniform CameraBlock {
+	mat4 u_proj_view;
+	mat4 u_view;
+	mat4 u_projection;
+	vec4 u_camera_position;
+};
@@ -0,0 +1,17 @@
+#ifndef FOG_DISTANCE
+#define FOG_DISTANCE 50.0
+#endif
+
+const vec3 FOG_COLOR = vec3(0.1, 0.12, 0.11);
+
+// `emission` is in the form written by fog_vert - 1.0 / (1.0 + emission)
+vec3 apply_fog(vec3 color, vec3 view_pos, float emission) {
+	emission = 1.0 / emission - 1.0;
+	emission *= emission;
+
+	float fog_factor = length(view_pos) / FOG_DISTANCE;
+	fog_factor = pow(fog_factor, 1.0/3.0);
+	fog_factor = clamp(fog_factor, 0.0, 0.9);
+
+	return mix(color, FOG_COLOR, fog_factor * (1.0 - emission));
+}
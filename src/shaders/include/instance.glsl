@@ -0,0 +1,10 @@
+// Matches gfx::instance_buffer::TransformInstance
+in vec4 a_instance_row_0;
+in vec4 a_instance_row_1;
+in vec4 a_instance_row_2;
+in vec4 a_instance_color;
+
+mat4 instance_transform() {
+	mat4 transposed = mat4(a_instance_row_0, a_instance_row_1, a_instance_row_2, vec4(0.0, 0.0, 0.0, 1.0));
+	return transpose(transposed);
+}
@@ -1,22 +1,25 @@
 #version 140
 
-uniform mat4 u_proj_view;
-uniform mat4 u_object;
+#include "camera.glsl"
+#include "instance.glsl"
+
 uniform samplerBuffer u_bone_tex;
-uniform int u_bone_offset;
 
-attribute vec3 a_vertex;
-attribute vec4 a_color;
-attribute vec3 a_bone_indices;
-attribute vec3 a_bone_weights;
+in vec3 a_vertex;
+in vec4 a_color;
+in ivec3 a_bone_indices;
+in vec3 a_bone_weights;
+
+// Where this instance's bone frames start in u_bone_tex
+in int a_bone_offset;
 
-varying vec4 v_color;
+out vec4 v_color;
 
 
-mat4x3 read_bone(in float index) {
-	vec4 row_0 = texelFetch(u_bone_tex, int(u_bone_offset + index)*3+0);
-	vec4 row_1 = texelFetch(u_bone_tex, int(u_bone_offset + index)*3+1);
-	vec4 row_2 = texelFetch(u_bone_tex, int(u_bone_offset + index)*3+2);
+mat4x3 read_bone(in int index) {
+	vec4 row_0 = texelFetch(u_bone_tex, (a_bone_offset + index)*3+0);
+	vec4 row_1 = texelFetch(u_bone_tex, (a_bone_offset + index)*3+1);
+	vec4 row_2 = texelFetch(u_bone_tex, (a_bone_offset + index)*3+2);
 
 	mat3x4 transposed;
 	transposed[0] = row_0;
@@ -44,6 +47,6 @@ void main() {
 
 	vec3 final_vert = vert_rest + vert_0 + vert_1 + vert_2;
 
-	gl_Position = u_proj_view * (u_object * vec4(final_vert, 1.0));
-	v_color = a_color;
+	gl_Position = u_proj_view * (instance_transform() * vec4(final_vert, 1.0));
+	v_color = a_color * a_instance_color;
 }
@@ -1,15 +1,137 @@
 use crate::prelude::*;
 
+/// Intersects the infinite line through `line_point` with `plane`, in either direction.
+/// See `Ray::intersect_plane` for intersections in front of a point only
 pub fn intersect_plane(plane: Plane, line_point: Vec3, line_direction: Vec3) -> Option<Vec3> {
-	let line_direction = line_direction.normalize();
+	let line = Ray::new(line_point, line_direction);
+	line.line_plane_distance(plane).map(|t| line.at(t))
+}
+
+
+/// A half line. `direction` is normalised, so distances along the ray are in world units
+#[derive(Copy, Clone, Debug)]
+pub struct Ray {
+	pub origin: Vec3,
+	pub direction: Vec3,
+}
+
+impl Ray {
+	pub fn new(origin: Vec3, direction: Vec3) -> Ray {
+		Ray { origin, direction: direction.normalize() }
+	}
+
+	pub fn at(&self, distance: f32) -> Vec3 {
+		self.origin + self.direction * distance
+	}
+
+	/// Signed distance along the ray's line to `plane`, or None if the line is close to parallel with it
+	fn line_plane_distance(&self, plane: Plane) -> Option<f32> {
+		let alignment = plane.normal.dot(self.direction);
+
+		// perpendicular to plane
+		if alignment.abs() < 0.01 {
+			return None;
+		}
+
+		Some((plane.length - plane.normal.dot(self.origin)) / alignment)
+	}
+
+	/// Distance to `plane`, from either side
+	pub fn intersect_plane(&self, plane: Plane) -> Option<f32> {
+		self.line_plane_distance(plane)
+			.filter(|&t| t >= 0.0)
+	}
+
+	/// Distance to the triangle `abc`, from either side (Möller-Trumbore)
+	pub fn intersect_triangle(&self, a: Vec3, b: Vec3, c: Vec3) -> Option<f32> {
+		let edge_ab = b - a;
+		let edge_ac = c - a;
+
+		let p = self.direction.cross(edge_ac);
+		let determinant = edge_ab.dot(p);
+
+		// parallel to triangle
+		if determinant.abs() < 1e-6 {
+			return None;
+		}
+
+		let inv_determinant = 1.0 / determinant;
+		let to_origin = self.origin - a;
+
+		let u = to_origin.dot(p) * inv_determinant;
+		if !(0.0..=1.0).contains(&u) {
+			return None;
+		}
+
+		let q = to_origin.cross(edge_ab);
+		let v = self.direction.dot(q) * inv_determinant;
+		if v < 0.0 || u + v > 1.0 {
+			return None;
+		}
 
-	// perpendicular to plane
-	if plane.normal.dot(line_direction).abs() < 0.01 {
-		return None;
+		Some(edge_ac.dot(q) * inv_determinant)
+			.filter(|&t| t >= 0.0)
 	}
 
-	let t = (plane.length - plane.normal.dot(line_point)) / plane.normal.dot(line_direction);
-	Some(line_point + line_direction * t)
+	/// Distance to where the ray enters `aabb`, or zero if it starts inside it
+	pub fn intersect_aabb(&self, aabb: &Aabb) -> Option<f32> {
+		let axes = [
+			(self.origin.x, self.direction.x, aabb.min.x, aabb.max.x),
+			(self.origin.y, self.direction.y, aabb.min.y, aabb.max.y),
+			(self.origin.z, self.direction.z, aabb.min.z, aabb.max.z),
+		];
+
+		let mut near = 0.0f32;
+		let mut far = f32::INFINITY;
+
+		// Clip the ray against each pair of slabs
+		for &(origin, direction, min, max) in axes.iter() {
+			if direction.abs() < 1e-6 {
+				if !(min..=max).contains(&origin) {
+					return None;
+				}
+
+				continue
+			}
+
+			let t_min = (min - origin) / direction;
+			let t_max = (max - origin) / direction;
+
+			near = near.max(t_min.min(t_max));
+			far = far.min(t_min.max(t_max));
+
+			if near > far {
+				return None;
+			}
+		}
+
+		Some(near)
+	}
+}
+
+
+/// Axis aligned bounding box
+#[derive(Copy, Clone, Debug)]
+pub struct Aabb {
+	pub min: Vec3,
+	pub max: Vec3,
+}
+
+impl Aabb {
+	/// The smallest box containing all of `points`, or None if there are none
+	pub fn from_points(points: impl IntoIterator<Item=Vec3>) -> Option<Aabb> {
+		let mut points = points.into_iter();
+		let first = points.next()?;
+
+		Some(points.fold(Aabb { min: first, max: first }, |aabb, p| Aabb {
+			min: Vec3::new(aabb.min.x.min(p.x), aabb.min.y.min(p.y), aabb.min.z.min(p.z)),
+			max: Vec3::new(aabb.max.x.max(p.x), aabb.max.y.max(p.y), aabb.max.z.max(p.z)),
+		}))
+	}
+
+	pub fn center(&self) -> Vec3 {
+		(self.min + self.max) / 2.0
+	}
 }
 
 
@@ -19,4 +141,133 @@ pub fn intersect_plane(plane: Plane, line_point: Vec3, line_direction: Vec3) ->
 
 // 	intersect_plane(plane_point, plane_normal, line_point, line_direction)
 // 		.expect("Camera forward perpendicular to ground plane")
-// }
\ No newline at end of file
+// }
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn assert_near(a: f32, b: f32) {
+		assert!((a - b).abs() < 1e-4, "{} != {}", a, b);
+	}
+
+	fn assert_near_vec(a: Vec3, b: Vec3) {
+		assert!((a - b).length() < 1e-4, "{:?} != {:?}", a, b);
+	}
+
+	// Triangle in the z = -5 plane, around the -z axis
+	fn triangle() -> [Vec3; 3] {
+		[Vec3::new(-1.0, -1.0, -5.0), Vec3::new(1.0, -1.0, -5.0), Vec3::new(0.0, 1.0, -5.0)]
+	}
+
+	#[test]
+	fn ray_hits_triangle() {
+		let [a, b, c] = triangle();
+		let ray = Ray::new(Vec3::zero(), Vec3::new(0.0, 0.0, -2.0));
+
+		assert_near(ray.intersect_triangle(a, b, c).unwrap(), 5.0);
+	}
+
+	#[test]
+	fn ray_misses_triangle() {
+		let [a, b, c] = triangle();
+
+		let beside = Ray::new(Vec3::new(2.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+		assert!(beside.intersect_triangle(a, b, c).is_none());
+
+		let behind = Ray::new(Vec3::zero(), Vec3::new(0.0, 0.0, 1.0));
+		assert!(behind.intersect_triangle(a, b, c).is_none());
+	}
+
+	#[test]
+	fn ray_hits_triangle_back_face() {
+		let [a, b, c] = triangle();
+		let ray = Ray::new(Vec3::new(0.0, 0.0, -10.0), Vec3::new(0.0, 0.0, 1.0));
+
+		assert_near(ray.intersect_triangle(a, b, c).unwrap(), 5.0);
+	}
+
+	#[test]
+	fn ray_parallel_to_triangle() {
+		let [a, b, c] = triangle();
+
+		// In the triangle's plane, passing straight through it
+		let ray = Ray::new(Vec3::new(-5.0, 0.0, -5.0), Vec3::new(1.0, 0.0, 0.0));
+		assert!(ray.intersect_triangle(a, b, c).is_none());
+	}
+
+	fn unit_box() -> Aabb {
+		Aabb { min: Vec3::splat(-1.0), max: Vec3::splat(1.0) }
+	}
+
+	#[test]
+	fn ray_hits_aabb() {
+		let ray = Ray::new(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+		assert_near(ray.intersect_aabb(&unit_box()).unwrap(), 4.0);
+
+		let away = Ray::new(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, 1.0));
+		assert!(away.intersect_aabb(&unit_box()).is_none());
+	}
+
+	#[test]
+	fn ray_inside_aabb() {
+		let ray = Ray::new(Vec3::new(0.5, 0.0, 0.0), Vec3::new(1.0, 1.0, 0.0));
+		assert_eq!(ray.intersect_aabb(&unit_box()), Some(0.0));
+	}
+
+	#[test]
+	fn axis_parallel_ray_outside_slab() {
+		// Parallel to the x slabs, but outside them
+		let ray = Ray::new(Vec3::new(2.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+		assert!(ray.intersect_aabb(&unit_box()).is_none());
+
+		// Parallel to the x slabs, and between them
+		let ray = Ray::new(Vec3::new(0.5, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+		assert_near(ray.intersect_aabb(&unit_box()).unwrap(), 4.0);
+	}
+
+	// `intersect_plane` as it was before `Ray` was added
+	fn reference_intersect_plane(plane: Plane, line_point: Vec3, line_direction: Vec3) -> Option<Vec3> {
+		let line_direction = line_direction.normalize();
+
+		if plane.normal.dot(line_direction).abs() < 0.01 {
+			return None;
+		}
+
+		let t = (plane.length - plane.normal.dot(line_point)) / plane.normal.dot(line_direction);
+		Some(line_point + line_direction * t)
+	}
+
+	#[test]
+	fn intersect_plane_is_unchanged() {
+		let planes = [
+			Plane { normal: Vec3::new(0.0, 1.0, 0.0), length: 0.0 },
+			Plane { normal: Vec3::new(0.0, 1.0, 0.0), length: 2.5 },
+			Plane { normal: Vec3::new(1.0, 1.0, 0.0).normalize(), length: -1.0 },
+		];
+
+		let lines = [
+			(Vec3::new(0.0, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0)),
+			(Vec3::new(3.0, 5.0, -2.0), Vec3::new(0.0, -3.0, 0.0)),
+
+			// Pointing away from the plane, which the free function still intersects behind the point
+			(Vec3::new(1.0, 5.0, 1.0), Vec3::new(0.2, 1.0, 0.1)),
+
+			// Parallel to the horizontal planes
+			(Vec3::new(0.0, 5.0, 0.0), Vec3::new(1.0, 0.0, 0.0)),
+		];
+
+		for &plane in planes.iter() {
+			for &(point, direction) in lines.iter() {
+				let expected = reference_intersect_plane(plane, point, direction);
+
+				match (intersect_plane(plane, point, direction), expected) {
+					(Some(result), Some(expected)) => assert_near_vec(result, expected),
+					(None, None) => {}
+					(result, expected) => panic!("{:?} != {:?}", result, expected),
+				}
+			}
+		}
+	}
+}
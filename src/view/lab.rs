@@ -2,6 +2,8 @@ use crate::prelude::*;
 use crate::gfx::camera::{self, Camera};
 use crate::gfx::core::Core;
 use crate::gfx::mesh::MeshID;
+use crate::gfx::shader::ShaderID;
+use crate::gfx::render_queue::{RenderQueue, DrawItem};
 use crate::holo_volume::HoloVolume;
 use crate::SceneVertex;
 
@@ -34,8 +36,20 @@ impl Lab {
 		}
 	}
 
-	pub fn draw(&mut self, core: &mut Core) {
-		core.draw_mesh(self.left_holo_mesh);
-		core.draw_mesh(self.right_holo_mesh);
+	pub fn destroy(self, core: &mut Core) {
+		core.destroy_mesh(self.left_holo_mesh);
+		core.destroy_mesh(self.right_holo_mesh);
+	}
+
+	pub fn submit(&self, queue: &mut RenderQueue, shader: ShaderID, camera: &Camera) {
+		let holos = [
+			(self.left_holo_mesh, &self.left_holo_volume),
+			(self.right_holo_mesh, &self.right_holo_volume),
+		];
+
+		for &(mesh, volume) in holos.iter() {
+			let position = volume.transform * Vec3::zero();
+			queue.submit(DrawItem::new(mesh, shader).at_position(position, camera));
+		}
 	}
 }
\ No newline at end of file
@@ -23,6 +23,10 @@ impl MainConsoleHolo {
 		MainConsoleHolo { mesh, holo_volume }
 	}
 
+	pub fn destroy(self, core: &mut Core) {
+		core.destroy_mesh(self.mesh);
+	}
+
 	pub fn draw(&self, core: &mut Core, ship_pos: Vec3) {
 		let transform = self.holo_volume.transform;
 
@@ -4,9 +4,13 @@ use crate::gfx::core::Core;
 use crate::gfx::vertex::BasicVertex;
 use crate::gfx::mesh::MeshID;
 use crate::gfx::shader::ShaderID;
+use crate::camera_path::CameraPose;
 
 use super::main_console_holo::MainConsoleHolo;
 
+/// How quickly the view screen camera catches up with where the player is looking. Higher is snappier
+const LOOK_SMOOTHING_RATE: f32 = 8.0;
+
 pub struct ViewScreen {
 	camera: Camera,
 
@@ -44,22 +48,22 @@ impl ViewScreen {
 		let screen_mesh = core.new_mesh();
 		core.update_mesh(screen_mesh, &screen_verts, &mesh_data.indices);
 
-		let screen_shader = core.new_shader(
-			include_str!("../shaders/basic_vert.glsl"),
-			include_str!("../shaders/color_frag.glsl"),
+		let screen_shader = core.new_shader::<BasicVertex>(
+			crate::shader_source!("shaders/basic_vert.glsl"),
+			crate::shader_source!("shaders/color_frag.glsl"),
 			&["a_vertex"]
-		);
+		).expect("Failed to create view screen shader");
 
 
 		let scene = project.find_scene("space")
 			.expect("Can't find space scene");
 
 		let scene_mesh = crate::build_scene_mesh(core, scene);
-		let scene_shader = core.new_shader(
-			include_str!("../shaders/fog_vert.glsl"),
-			include_str!("../shaders/fog_frag.glsl"),
+		let scene_shader = core.new_shader::<crate::SceneVertex>(
+			crate::shader_source!("shaders/fog_vert.glsl"),
+			crate::shader_source!("shaders/fog_frag.glsl"),
 			&["a_vertex", "a_color", "a_emission"]
-		);
+		).expect("Failed to create view screen scene shader");
 
 
 		let fullscreen_verts = [
@@ -87,17 +91,39 @@ impl ViewScreen {
 	}
 
 
-	pub fn draw(&mut self, core: &mut Core, ply_camera: &Camera) {
+	/// Releases all gpu resources owned by the view
+	pub fn destroy(self, core: &mut Core) {
+		core.destroy_mesh(self.screen_mesh);
+		core.destroy_shader(self.screen_shader);
+		core.destroy_mesh(self.scene_mesh);
+		core.destroy_shader(self.scene_shader);
+		core.destroy_mesh(self.fullscreen_mesh);
+
+		self.main_console_holo.destroy(core);
+	}
+
+
+	pub fn draw(&mut self, core: &mut Core, ply_camera: &Camera, dt: f32) {
 		use crate::gfx::core::StencilParams;
 
 		self.camera.update(ply_camera.viewport());
-		self.camera.set_yaw(ply_camera.yaw());
-		self.camera.set_pitch(ply_camera.pitch());
 
-		let new_position = self.camera.position() + Vec3::from_z(-0.4 / 60.0);
+		let current_pose = CameraPose::from_camera(&self.camera);
+		let target_pose = CameraPose {
+			yaw: ply_camera.yaw(),
+			pitch: ply_camera.pitch(),
+			..current_pose
+		};
+
+		// Framerate independent exponential smoothing
+		current_pose.lerp(&target_pose, 1.0 - (-LOOK_SMOOTHING_RATE * dt).exp())
+			.apply(&mut self.camera);
+
+		let new_position = self.camera.position() + Vec3::from_z(-0.4 * dt);
 		self.camera.set_position(new_position);
 
 		// Draw view screen into stencil
+		core.profiler().begin_scope("stencil");
 		core.use_shader(self.screen_shader);
 		core.set_uniform_mat4("u_proj_view", &ply_camera.projection_view());
 		core.set_uniform_vec4("u_color", Vec4::splat(1.0));
@@ -108,22 +134,24 @@ impl ViewScreen {
 
 		// Clear depth where stencil
 		self.clear_depth_stenciled(core, true);
+		core.profiler().end_scope();
 
 		// Draw space
+		core.profiler().begin_scope("space");
+		core.set_camera(&self.camera);
 		core.use_shader(self.scene_shader);
-
-		core.set_uniform_mat4("u_proj_view", &(self.camera.projection_view()));
-		core.set_uniform_mat4("u_view", &self.camera.view_matrix());
 		core.draw_mesh(self.scene_mesh);
+		core.set_camera(ply_camera);
 
 		// Clear depth where stencil
 		self.clear_depth_stenciled(core, false);
+		core.profiler().end_scope();
 
 		// Draw hologram
+		core.profiler().begin_scope("hologram");
 		core.use_shader(self.scene_shader);
-		core.set_uniform_mat4("u_proj_view", &ply_camera.projection_view());
-		core.set_uniform_mat4("u_view", &ply_camera.view_matrix());
 		self.main_console_holo.draw(core, self.camera.position());
+		core.profiler().end_scope();
 
 		core.set_stencil(None);
 	}
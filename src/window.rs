@@ -103,3 +103,102 @@ impl Window {
 		self.context.swap_buffers().unwrap();
 	}
 }
+
+
+
+/// A GL context with no window, for rendering in tests and tools.
+/// There is no default framebuffer to draw into, so render into a framebuffer created with
+/// `Core::new_framebuffer` and read it back with `Core::read_pixels`
+pub struct HeadlessContext {
+	_context: glutin::Context<glutin::PossiblyCurrent>,
+
+	// Kept alive for the lifetime of the context, when one was needed to create it
+	_event_loop: Option<EventLoop<()>>,
+}
+
+impl HeadlessContext {
+	/// Tries a surfaceless context first (e.g., EGL on Mesa, which can use the software
+	/// rasteriser), then a pbuffer backed headless context, and on unix falls back to OSMesa
+	/// when there is no display server to connect to at all
+	pub fn new() -> Result<Self, Box<dyn Error>> {
+		let builder = || glutin::ContextBuilder::new()
+			.with_gl(glutin::GlRequest::Specific(glutin::Api::OpenGl, (3, 2)))
+			.with_gl_profile(glutin::GlProfile::Core)
+			.with_gl_debug_flag(true);
+
+		// Pbuffers need a size, but nothing is drawn to the default framebuffer anyway
+		let pbuffer_size = glutin::dpi::PhysicalSize::new(1, 1);
+
+		let event_loop = if has_display_server() { Some(new_event_loop()) } else { None };
+
+		let context = match &event_loop {
+			Some(event_loop) => build_surfaceless(builder(), event_loop)
+				.or_else(|_| builder().build_headless(event_loop, pbuffer_size))?,
+
+			None => build_osmesa(builder(), pbuffer_size)?,
+		};
+
+		let context = unsafe { context.make_current().map_err(|(_, err)| err)? };
+
+		gl::load_with(|s| context.get_proc_address(s) as *const _);
+
+		Ok(HeadlessContext {
+			_context: context,
+			_event_loop: event_loop,
+		})
+	}
+}
+
+
+#[cfg(unix)]
+fn has_display_server() -> bool {
+	// winit panics rather than failing if it can't connect to either
+	std::env::var_os("DISPLAY").is_some() || std::env::var_os("WAYLAND_DISPLAY").is_some()
+}
+
+#[cfg(not(unix))]
+fn has_display_server() -> bool { true }
+
+
+#[cfg(unix)]
+fn new_event_loop() -> EventLoop<()> {
+	use glutin::platform::unix::EventLoopExtUnix;
+
+	// Tests don't run on the main thread
+	EventLoop::new_any_thread()
+}
+
+#[cfg(not(unix))]
+fn new_event_loop() -> EventLoop<()> { EventLoop::new() }
+
+
+#[cfg(unix)]
+fn build_surfaceless(builder: glutin::ContextBuilder<'_, glutin::NotCurrent>, event_loop: &EventLoop<()>)
+	-> Result<glutin::Context<glutin::NotCurrent>, glutin::CreationError>
+{
+	use glutin::platform::unix::HeadlessContextExt;
+	builder.build_surfaceless(event_loop)
+}
+
+#[cfg(not(unix))]
+fn build_surfaceless(_: glutin::ContextBuilder<'_, glutin::NotCurrent>, _: &EventLoop<()>)
+	-> Result<glutin::Context<glutin::NotCurrent>, glutin::CreationError>
+{
+	Err(glutin::CreationError::NotSupported("surfaceless contexts are only supported on unix".into()))
+}
+
+
+#[cfg(unix)]
+fn build_osmesa(builder: glutin::ContextBuilder<'_, glutin::NotCurrent>, size: glutin::dpi::PhysicalSize<u32>)
+	-> Result<glutin::Context<glutin::NotCurrent>, glutin::CreationError>
+{
+	use glutin::platform::unix::HeadlessContextExt;
+	builder.build_osmesa(size)
+}
+
+#[cfg(not(unix))]
+fn build_osmesa(_: glutin::ContextBuilder<'_, glutin::NotCurrent>, _: glutin::dpi::PhysicalSize<u32>)
+	-> Result<glutin::Context<glutin::NotCurrent>, glutin::CreationError>
+{
+	Err(glutin::CreationError::NotSupported("OSMesa is only supported on unix".into()))
+}